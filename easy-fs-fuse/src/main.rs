@@ -6,6 +6,8 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 const BLOCK_SZ: usize = 512;
+/// must match PANIC_DUMP_BLOCKS in os/src/config.rs
+const PANIC_DUMP_BLOCKS: u32 = 16;
 
 struct BlockFile(Mutex<File>);
 
@@ -63,7 +65,8 @@ fn easy_fs_pack() -> std::io::Result<()> {
         f
     })));
     // 16MiB*10, at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 1600 * 2048, 1);
+    // the last blocks of the image are left out of the fs for kernel panic dumps
+    let efs = EasyFileSystem::create(block_file, 1600 * 2048 - PANIC_DUMP_BLOCKS, 1);
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
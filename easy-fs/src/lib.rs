@@ -15,4 +15,5 @@ use block_cache::{block_cache_sync_all, get_block_cache};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
 use layout::*;
+pub use layout::EFS_MAGIC;
 pub use vfs::Inode;
@@ -1,6 +1,78 @@
+use std::fmt::Write;
+
+#[allow(dead_code)]
+#[path = "src/ebpf/ctx_abi.rs"]
+mod ctx_abi;
+
+use ctx_abi::*;
+
 static TARGET_PATH: &str = "../user/target/riscv64gc-unknown-none-elf/release/";
+/// the committed header, regenerated with `make ctx-header`
+static CTX_HEADER_PATH: &str = "../user/ebpf/kern/bpf_ctx.h";
 
 fn main() {
     println!("cargo:rerun-if-changed=../user/src/");
     println!("cargo:rerun-if-changed={}", TARGET_PATH);
+    println!("cargo:rerun-if-changed=src/ebpf/ctx_abi.rs");
+    println!("cargo:rerun-if-changed={}", CTX_HEADER_PATH);
+    gen_ctx_header();
+}
+
+/// generate the C side of the eBPF context layouts from ctx_abi.rs into OUT_DIR,
+/// and warn if the committed header differs, the source tree is never written
+fn gen_ctx_header() {
+    let mut h = String::new();
+    writeln!(h, "// generated by os/build.rs from os/src/ebpf/ctx_abi.rs, copied here by `make ctx-header`, do not edit").unwrap();
+    writeln!(h, "#ifndef __LIBS_BPF_CTX_H__").unwrap();
+    writeln!(h, "#define __LIBS_BPF_CTX_H__\n").unwrap();
+    writeln!(h, "typedef unsigned long long size_t;").unwrap();
+    writeln!(h, "typedef long long ssize_t;\n").unwrap();
+
+    writeln!(h, "// x0-x31, then sstatus and sepc").unwrap();
+    writeln!(h, "struct pt_regs {{\n  size_t regs[32];\n  size_t sstatus;\n  size_t sepc;\n}};\n").unwrap();
+    writeln!(h, "// x is a struct pt_regs *, e.g. &ctx->regs of a kprobe_ctx").unwrap();
+    writeln!(h, "#define __PT_REG(x, i) (((size_t *)(x))[i])").unwrap();
+    for (i, reg) in PT_REGS_PARM.iter().enumerate() {
+        writeln!(h, "#define PT_REGS_PARM{}(x) __PT_REG(x, {})", i + 1, reg).unwrap();
+    }
+    writeln!(h, "#define PT_REGS_RC(x) __PT_REG(x, {})", PT_REGS_RC).unwrap();
+    writeln!(h, "#define PT_REGS_RET(x) __PT_REG(x, {})", PT_REGS_RA).unwrap();
+    writeln!(h, "#define PT_REGS_SP(x) __PT_REG(x, {})", PT_REGS_SP).unwrap();
+    writeln!(h, "#define PT_REGS_FP(x) __PT_REG(x, {})", PT_REGS_FP).unwrap();
+    writeln!(h, "#define PT_REGS_IP(x) __PT_REG(x, {})\n", PT_REGS_IP).unwrap();
+
+    writeln!(h, "// ptype of kprobe_ctx, PT_REGS_RC is only valid on KRETPROBE_EXIT").unwrap();
+    writeln!(h, "#define BPF_PTYPE_KPROBE {}", PTYPE_KPROBE).unwrap();
+    writeln!(h, "#define BPF_PTYPE_KRETPROBE_ENTRY {}", PTYPE_KRETPROBE_ENTRY).unwrap();
+    writeln!(h, "#define BPF_PTYPE_KRETPROBE_EXIT {}", PTYPE_KRETPROBE_EXIT).unwrap();
+    writeln!(h, "// paddr is the accessed address").unwrap();
+    writeln!(h, "#define BPF_PTYPE_WATCHPOINT {}", PTYPE_WATCHPOINT).unwrap();
+    writeln!(h, "// ptype of uprobe_ctx").unwrap();
+    writeln!(h, "#define BPF_PTYPE_UPROBE_SYNCFUNC {}\n", PTYPE_UPROBE_SYNCFUNC).unwrap();
+
+    for abi in CTX_ABIS.iter() {
+        writeln!(h, "// {}, {} bytes", abi.attach, abi.size()).unwrap();
+        writeln!(h, "struct {} {{", abi.name).unwrap();
+        for field in abi.fields {
+            let decl = match field.ty {
+                CtxFieldType::U64 => format!("size_t {}", field.name),
+                CtxFieldType::I64 => format!("ssize_t {}", field.name),
+                CtxFieldType::Array(n) => format!("size_t {}[{}]", field.name, n),
+                CtxFieldType::Chars(n) => format!("char {}[{}]", field.name, n),
+                CtxFieldType::PtRegs => format!("struct pt_regs {}", field.name),
+            };
+            writeln!(h, "  {};", decl).unwrap();
+        }
+        writeln!(h, "}};\n").unwrap();
+    }
+    writeln!(h, "#endif").unwrap();
+
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("bpf_ctx.h");
+    std::fs::write(out, &h).unwrap();
+    if std::fs::read_to_string(CTX_HEADER_PATH).ok().as_deref() != Some(h.as_str()) {
+        println!(
+            "cargo:warning={} is out of date with src/ebpf/ctx_abi.rs, run `make ctx-header`",
+            CTX_HEADER_PATH
+        );
+    }
 }
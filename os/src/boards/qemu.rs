@@ -51,7 +51,10 @@ pub fn irq_handler() {
         6 => MOUSE_DEVICE.handle_irq(),
         8 => BLOCK_DEVICE.handle_irq(),
         10 => UART.handle_irq(),
-        12 => UART1.handle_irq(),
+        12 => {
+            UART1.handle_irq();
+            crate::gdbstub::poll();
+        }
         _ => panic!("unsupported IRQ {}", intr_src_id),
     }
     plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
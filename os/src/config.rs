@@ -6,6 +6,10 @@ pub const KERNEL_HEAP_SIZE: usize = 0x400_0000;
 pub const MEMORY_END: usize = 0x88000000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+/// blocks after the end of the fs that panic dumps are written to, see easy-fs-fuse
+pub const PANIC_DUMP_BLOCKS: usize = 16;
+/// max number of harts, used to size per-hart states
+pub const MAX_HARTS: usize = 8;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
@@ -5,6 +5,8 @@ use super::CharDevice;
 use crate::sync::{Condvar, UPIntrFreeCell};
 use crate::task::schedule;
 use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
 use bitflags::*;
 use volatile::{ReadOnly, Volatile, WriteOnly};
 
@@ -117,6 +119,73 @@ impl NS16550aRaw {
             }
         }
     }
+
+    /// write `ch` only if the transmitter is free
+    pub fn try_write(&mut self, ch: u8) -> bool {
+        let write_end = self.write_end();
+        if write_end.lsr.read().contains(LSR::THR_EMPTY) {
+            write_end.thr.write(ch);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// interrupt when the transmitter becomes free, besides on received data
+    pub fn set_tx_interrupt(&mut self, enable: bool) {
+        let mut ier = IER::RX_AVAILABLE;
+        if enable {
+            ier |= IER::TX_EMPTY;
+        }
+        self.write_end().ier.write(ier);
+    }
+}
+
+/// bytes queued by `write_async` and `write`
+const TX_RING_SIZE: usize = 16384;
+/// attempts of `write` to queue its byte and see it sent, the queue may be held by the
+/// code a probe or a gdb stop interrupted on this hart
+const TX_WRITE_TRIES: usize = 1 << 24;
+
+struct TxRing {
+    buf: Vec<u8>,
+    /// next byte to send
+    head: usize,
+    len: usize,
+    /// bytes sent so far
+    sent: usize,
+}
+
+impl TxRing {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; TX_RING_SIZE],
+            head: 0,
+            len: 0,
+            sent: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.buf[(self.head + self.len) % TX_RING_SIZE] = b;
+            self.len += 1;
+        }
+    }
+
+    fn front(&self) -> Option<u8> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.buf[self.head])
+        }
+    }
+
+    fn pop(&mut self) {
+        self.head = (self.head + 1) % TX_RING_SIZE;
+        self.len -= 1;
+        self.sent = self.sent.wrapping_add(1);
+    }
 }
 
 struct NS16550aInner {
@@ -127,6 +196,8 @@ struct NS16550aInner {
 pub struct NS16550a<const BASE_ADDR: usize> {
     inner: UPIntrFreeCell<NS16550aInner>,
     condvar: Condvar,
+    /// not in `inner`: probe handlers queue bytes and must never find it borrowed
+    tx: spin::Mutex<TxRing>,
 }
 
 impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
@@ -139,6 +210,7 @@ impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
         Self {
             inner: unsafe { UPIntrFreeCell::new(inner) },
             condvar: Condvar::new(),
+            tx: spin::Mutex::new(TxRing::new()),
         }
     }
 
@@ -146,6 +218,43 @@ impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
         self.inner
             .exclusive_session(|inner| inner.read_buffer.is_empty())
     }
+
+    /// queue `bytes` to be sent in the background, all of them or none
+    /// never blocks, so probe handlers may call it; false if the queue is full or in use
+    pub fn write_async(&self, bytes: &[u8]) -> bool {
+        let mut tx = match self.tx.try_lock() {
+            Some(tx) => tx,
+            None => return false,
+        };
+        if TX_RING_SIZE - tx.len < bytes.len() {
+            return false;
+        }
+        tx.push(bytes);
+        Self::drain(&mut tx);
+        true
+    }
+
+    /// send queued bytes while the transmitter is free
+    fn drain_tx(&self) {
+        if let Some(mut tx) = self.tx.try_lock() {
+            Self::drain(&mut tx);
+        }
+    }
+
+    /// the TX_EMPTY interrupt brings us back for what is left
+    fn drain(tx: &mut TxRing) {
+        if tx.len == 0 {
+            return;
+        }
+        let mut raw = NS16550aRaw::new(BASE_ADDR);
+        while let Some(ch) = tx.front() {
+            if !raw.try_write(ch) {
+                break;
+            }
+            tx.pop();
+        }
+        raw.set_tx_interrupt(tx.len > 0);
+    }
 }
 
 impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
@@ -169,9 +278,28 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
             }
         }
     }
+    /// `ch` is queued after the bytes queued before and sent before returning, the
+    /// TX_EMPTY interrupt may not come, e.g. while the gdb stub has the kernel stopped.
+    /// if the queue stays in use it is written directly, out of order
     fn write(&self, ch: u8) {
-        let mut inner = self.inner.exclusive_access();
-        inner.ns16550a.write(ch);
+        // position of `ch` in the bytes sent
+        let mut queued = None;
+        for _ in 0..TX_WRITE_TRIES {
+            if let Some(mut tx) = self.tx.try_lock() {
+                if queued.is_none() && tx.len < TX_RING_SIZE {
+                    tx.push(&[ch]);
+                    queued = Some(tx.sent.wrapping_add(tx.len));
+                }
+                Self::drain(&mut tx);
+                if queued.map_or(false, |end| tx.sent.wrapping_sub(end) as isize >= 0) {
+                    return;
+                }
+            }
+        }
+        // a queued byte is sent by the TX_EMPTY interrupt
+        if queued.is_none() {
+            NS16550aRaw::new(BASE_ADDR).write(ch);
+        }
     }
     fn handle_irq(&self) {
         let mut count = 0;
@@ -184,6 +312,7 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
         if count > 0 {
             self.condvar.signal();
         }
+        self.drain_tx();
     }
 }
 
@@ -199,6 +328,18 @@ impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR>{
             -1
         }
     }
+    /// a received byte without waiting, polls the device when interrupts are off
+    /// or the stop interrupted code that holds the inner state
+    pub fn try_read(&self) -> Option<u8> {
+        let mut inner = match self.inner.try_exclusive_access() {
+            Some(inner) => inner,
+            None => return NS16550aRaw::new(BASE_ADDR).read(),
+        };
+        match inner.read_buffer.pop_front() {
+            Some(ch) => Some(ch),
+            None => inner.ns16550a.read(),
+        }
+    }
     pub fn flush(&self){ // useless
         let mut inner = self.inner.exclusive_access();
         inner.read_buffer.clear();
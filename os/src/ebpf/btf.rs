@@ -0,0 +1,98 @@
+//! BTF-lite: type information of selected kernel structs
+//!
+//! rust reorders struct fields, so offsets change from one kernel build to another.
+//! `OS_BTF_STRUCTS` in osutil lists the structs and fields exposed to eBPF programs.
+//! it is a static table evaluated by the compiler, so the offsets and sizes are those of
+//! the kernel being built and are embedded in its image.
+//!
+//! programs refer to them through extern symbols, which are relocated to the value
+//! when the program is loaded (see `user/ebpf/kern/bpf_core.h`):
+//! * `__btf_off__<struct>__<field>` - offset of the field
+//! * `__btf_size__<struct>__<field>` - size of the field
+//! * `__btf_size__<struct>` - size of the struct
+
+use super::osutil::OS_BTF_STRUCTS;
+
+const BTF_OFF_PREFIX: &str = "__btf_off__";
+const BTF_SIZE_PREFIX: &str = "__btf_size__";
+
+pub struct BtfField {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl BtfField {
+    /// make a field that is a cell point at the value inside it
+    pub const fn unwrap_cell(self, value_offset: usize, value_size: usize) -> Self {
+        Self {
+            name: self.name,
+            offset: self.offset + value_offset,
+            size: value_size,
+        }
+    }
+}
+
+pub struct BtfStruct {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: &'static [BtfField],
+}
+
+impl BtfStruct {
+    pub fn field(&self, name: &str) -> Option<&BtfField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// size of what `_ptr` points to, lets btf_field! find field sizes without naming their types
+pub const fn pointee_size<T>(_ptr: *const T) -> usize {
+    core::mem::size_of::<T>()
+}
+
+/// describe `$field` of `$ty` as laid out in this kernel build, usable in statics
+macro_rules! btf_field {
+    ($ty:ty, $field:ident) => {{
+        let uninit = core::mem::MaybeUninit::<$ty>::uninit();
+        let base = uninit.as_ptr();
+        let field = unsafe { core::ptr::addr_of!((*base).$field) };
+        $crate::ebpf::btf::BtfField {
+            name: stringify!($field),
+            offset: unsafe { (field as *const u8).offset_from(base as *const u8) as usize },
+            size: $crate::ebpf::btf::pointee_size(field),
+        }
+    }};
+}
+
+/// describe a struct and the listed fields, e.g. `btf_struct!(TaskControlBlock { kstack })`
+macro_rules! btf_struct {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        $crate::ebpf::btf::BtfStruct {
+            name: stringify!($ty),
+            size: core::mem::size_of::<$ty>(),
+            fields: &[$(btf_field!($ty, $field)),*],
+        }
+    };
+}
+
+pub fn btf_find_struct(name: &str) -> Option<&'static BtfStruct> {
+    OS_BTF_STRUCTS.iter().find(|s| s.name == name)
+}
+
+/// whether a symbol is to be resolved from the kernel type information
+pub fn is_btf_symbol(name: &str) -> bool {
+    name.starts_with(BTF_OFF_PREFIX) || name.starts_with(BTF_SIZE_PREFIX)
+}
+
+/// value of a `__btf_*` symbol, None if the struct or field does not exist in this kernel
+pub fn btf_resolve_symbol(name: &str) -> Option<usize> {
+    if let Some(path) = name.strip_prefix(BTF_OFF_PREFIX) {
+        let (s, field) = path.split_once("__")?;
+        return Some(btf_find_struct(s)?.field(field)?.offset);
+    }
+    let path = name.strip_prefix(BTF_SIZE_PREFIX)?;
+    match path.split_once("__") {
+        Some((s, field)) => Some(btf_find_struct(s)?.field(field)?.size),
+        None => Some(btf_find_struct(path)?.size),
+    }
+}
@@ -27,6 +27,10 @@ numeric_enum! {
         BPF_PROG_LOAD = 5,
         BPF_PROG_ATTACH = 8,
         BPF_PROG_DETACH = 9,
+        BPF_PROG_GET_NEXT_ID = 11,
+        BPF_MAP_GET_NEXT_ID = 12,
+        BPF_OBJ_GET_INFO_BY_FD = 15,
+        BPF_ENABLE_STATS = 32,
         BPF_PROG_LOAD_EX = 1000,
     }
 }
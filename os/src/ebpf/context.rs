@@ -0,0 +1,222 @@
+//! eBPF program contexts
+//!
+//! the layouts are described in `ctx_abi.rs`, the structs here must match them,
+//! which is checked at compile time.
+//! programs are checked at load time for how far they read into the context,
+//! and attaching fails if that is beyond the context of the tracepoint.
+
+use super::ctx_abi::*;
+use super::osutil::os_current_thread;
+use super::retcode::BpfErrorCode::{self, *};
+use super::tracepoints::{Tracepoint, TracepointType::*};
+use crate::probe::arch::trapframe::{get_kernel_trapframe_sp, TrapFrame};
+use crate::probe::ftrace::FentryCtx;
+use crate::probe::tracepoint::*;
+use core::mem::size_of;
+
+#[repr(C)]
+/// kprobe context, see KPROBE_CTX
+pub struct KProbeBPFContext {
+    ptype: usize,
+    paddr: usize,
+    regs: TrapFrame,
+}
+
+impl KProbeBPFContext {
+    pub fn new(tf: &TrapFrame, probed_addr: usize, t: usize) -> Self {
+        let mut regs = tf.clone();
+        regs.x[2] = get_kernel_trapframe_sp(tf);
+        KProbeBPFContext {
+            ptype: t,
+            paddr: probed_addr,
+            regs,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self as *const Self as *const u8
+    }
+
+    pub fn probed_addr(&self) -> usize {
+        self.paddr
+    }
+
+    /// registers of the probed code, sp included
+    pub fn regs(&self) -> &TrapFrame {
+        &self.regs
+    }
+}
+
+#[repr(C)]
+/// uprobe context, see UPROBE_CTX
+pub struct UProbeBPFContext {
+    ptype: usize,
+    paddr: usize,
+    regs: TrapFrame,
+}
+
+impl UProbeBPFContext {
+    pub fn new(tf: &TrapFrame, probed_addr: usize, t: usize) -> Self {
+        UProbeBPFContext {
+            ptype: t,
+            paddr: probed_addr,
+            regs: tf.clone(),
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self as *const Self as *const u8
+    }
+}
+
+#[repr(C)]
+/// raw syscall context, ret is always 0 on sys_enter, see RAW_SYSCALL_CTX
+pub struct RawSyscallBPFContext {
+    id: usize,
+    args: [usize; 3],
+    ret: isize,
+    pid: usize,
+    tid: usize,
+}
+
+impl RawSyscallBPFContext {
+    pub fn new(id: usize, args: [usize; 3], ret: isize) -> Self {
+        let thread = os_current_thread();
+        RawSyscallBPFContext {
+            id,
+            args,
+            ret,
+            pid: thread.get_pid() as usize,
+            tid: thread.get_tid() as usize,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self as *const Self as *const u8
+    }
+}
+
+// the kernel structs must keep the described layouts
+const _: () = assert!(size_of::<TrapFrame>() == PT_REGS_SIZE);
+const _: () = assert!(size_of::<KProbeBPFContext>() == KPROBE_CTX.size());
+const _: () = assert!(size_of::<UProbeBPFContext>() == UPROBE_CTX.size());
+const _: () = assert!(size_of::<RawSyscallBPFContext>() == RAW_SYSCALL_CTX.size());
+const _: () = assert!(size_of::<FentryCtx>() == FENTRY_CTX.size());
+const _: () = assert!(size_of::<SysEnterCtx>() == SYS_ENTER_CTX.size());
+const _: () = assert!(size_of::<SysExitCtx>() == SYS_EXIT_CTX.size());
+const _: () = assert!(size_of::<SchedSwitchCtx>() == SCHED_SWITCH_CTX.size());
+const _: () = assert!(size_of::<ProcessForkCtx>() == PROCESS_FORK_CTX.size());
+const _: () = assert!(size_of::<ProcessExecCtx>() == PROCESS_EXEC_CTX.size());
+const _: () = assert!(size_of::<ProcessExitCtx>() == PROCESS_EXIT_CTX.size());
+const _: () = assert!(size_of::<PageFaultCtx>() == PAGE_FAULT_CTX.size());
+
+/// the context abi passed to programs attached to `tracepoint`
+pub fn ctx_abi(tracepoint: &Tracepoint) -> Option<&'static CtxAbi> {
+    match tracepoint.tp_type {
+        KProbe | KRetProbeEntry | KRetProbeExit | Watchpoint | WatchpointRw => Some(&KPROBE_CTX),
+        UProbe_SyncFunc => Some(&UPROBE_CTX),
+        RawSyscallEnter | RawSyscallExit => Some(&RAW_SYSCALL_CTX),
+        FEntry => Some(&FENTRY_CTX),
+        KTracepoint => {
+            let tp = TRACEPOINTS.get(tracepoint.token)?;
+            CTX_ABIS.iter().copied().find(|abi| {
+                abi.attach
+                    .strip_prefix("tracepoint$")
+                    .and_then(|name| name.split_once(':'))
+                    == Some((tp.category, tp.name))
+            })
+        }
+        _ => None,
+    }
+}
+
+const BPF_LD: u8 = 0x00;
+const BPF_LDX: u8 = 0x01;
+const BPF_ST: u8 = 0x02;
+const BPF_STX: u8 = 0x03;
+const BPF_ALU: u8 = 0x04;
+const BPF_JMP: u8 = 0x05;
+const BPF_JMP32: u8 = 0x06;
+const BPF_ALU64: u8 = 0x07;
+
+const BPF_MOV64_REG: u8 = 0xbf;
+const BPF_LD_IMM64: u8 = 0x18;
+const BPF_JA: u8 = 0x05;
+const BPF_CALL: u8 = 0x85;
+const BPF_EXIT: u8 = 0x95;
+const BPF_MEM: u8 = 0x60;
+
+fn insn_fields(insn: u64) -> (u8, usize, usize, i16) {
+    let opcode = insn as u8;
+    let dst = ((insn >> 8) & 0xf) as usize;
+    let src = ((insn >> 12) & 0xf) as usize;
+    let off = (insn >> 16) as i16;
+    (opcode, dst, src, off)
+}
+
+/// registers holding the ctx pointer as a bit set, r1 at entry
+const CTX_AT_ENTRY: u16 = 1 << 1;
+
+/// how many bytes of the context a program reads, best-effort
+/// ctx is followed through r1 and register copies of it in a single forward scan.
+/// it loses track of ctx on pointer arithmetic, on spills to the stack, across calls
+/// and along backward jumps, and code only reached after an exit is scanned without it,
+/// so reads through those paths are not counted. this catches layout mistakes of
+/// well-behaved programs and is not a verifier, a program can still read past ctx.
+/// returns EACCES if the program writes to ctx or reads before it
+pub fn ctx_access_size(insns: &[u64]) -> Result<usize, BpfErrorCode> {
+    // ctx registers merged in by forward jumps, indexed by target
+    let mut pending = alloc::vec![0u16; insns.len() + 1];
+    let mut ctx_regs = CTX_AT_ENTRY;
+    let mut max = 0usize;
+    let mut pc = 0;
+    while pc < insns.len() {
+        ctx_regs |= pending[pc];
+        let (opcode, dst, src, off) = insn_fields(insns[pc]);
+        let mut next = pc + 1;
+        match opcode & 0x7 {
+            BPF_LDX if opcode & 0xe0 == BPF_MEM && ctx_regs & (1 << src) != 0 => {
+                if off < 0 {
+                    return Err(EACCES);
+                }
+                let size = [4, 2, 1, 8][((opcode >> 3) & 0x3) as usize];
+                max = max.max(off as usize + size);
+                ctx_regs &= !(1 << dst);
+            }
+            BPF_ST | BPF_STX if ctx_regs & (1 << dst) != 0 => return Err(EACCES),
+            BPF_ALU64 if opcode == BPF_MOV64_REG => {
+                if ctx_regs & (1 << src) != 0 {
+                    ctx_regs |= 1 << dst;
+                } else {
+                    ctx_regs &= !(1 << dst);
+                }
+            }
+            BPF_LD | BPF_LDX | BPF_ALU | BPF_ALU64 => {
+                if opcode == BPF_LD_IMM64 {
+                    next += 1;
+                }
+                ctx_regs &= !(1 << dst);
+            }
+            BPF_JMP | BPF_JMP32 => {
+                if opcode == BPF_CALL {
+                    // r0-r5 are clobbered by calls
+                    ctx_regs &= !0x3f;
+                } else if opcode == BPF_EXIT {
+                    ctx_regs = 0;
+                } else {
+                    let target = (pc as isize + 1 + off as isize) as usize;
+                    // backward jumps reach code already scanned with what fell through
+                    if target > pc && target <= insns.len() {
+                        pending[target] |= ctx_regs;
+                    }
+                    if opcode == BPF_JA {
+                        ctx_regs = 0;
+                    }
+                }
+            }
+            _ => {}
+        }
+        pc = next;
+    }
+    Ok(max)
+}
@@ -0,0 +1,180 @@
+//! stable layouts of the contexts passed to eBPF programs
+//!
+//! this file has no dependencies: build.rs also uses it to generate
+//! `user/ebpf/kern/bpf_ctx.h`, so the C side only changes together with this description.
+//! every context is a `#[repr(C)]` struct of 8-byte fields, new fields are only appended.
+
+/// x0-x31 followed by sstatus and sepc, the layout of the kernel TrapFrame
+pub const PT_REGS_NR: usize = 34;
+pub const PT_REGS_SIZE: usize = PT_REGS_NR * 8;
+
+/// pt_regs slots of a0-a5, the first six arguments at function entry
+pub const PT_REGS_PARM: [usize; 6] = [10, 11, 12, 13, 14, 15];
+/// a0, the return value at function exit
+pub const PT_REGS_RC: usize = 10;
+pub const PT_REGS_RA: usize = 1;
+pub const PT_REGS_SP: usize = 2;
+pub const PT_REGS_FP: usize = 8;
+/// sepc, the probed pc
+pub const PT_REGS_IP: usize = 33;
+
+/// `ptype` of kprobe contexts
+pub const PTYPE_KPROBE: usize = 0;
+pub const PTYPE_KRETPROBE_ENTRY: usize = 1;
+/// regs are taken when the function returns, PT_REGS_RC is valid
+pub const PTYPE_KRETPROBE_EXIT: usize = 2;
+/// paddr is the accessed address instead of the probed one
+pub const PTYPE_WATCHPOINT: usize = 3;
+/// `ptype` of uprobe contexts
+pub const PTYPE_UPROBE_SYNCFUNC: usize = 0;
+
+#[derive(Clone, Copy)]
+pub enum CtxFieldType {
+    U64,
+    I64,
+    /// u64[n]
+    Array(usize),
+    /// char[n], padded to 8 bytes
+    Chars(usize),
+    /// struct pt_regs
+    PtRegs,
+}
+
+impl CtxFieldType {
+    pub const fn size(&self) -> usize {
+        match self {
+            CtxFieldType::U64 | CtxFieldType::I64 => 8,
+            CtxFieldType::Array(n) => *n * 8,
+            CtxFieldType::Chars(n) => (*n + 7) / 8 * 8,
+            CtxFieldType::PtRegs => PT_REGS_SIZE,
+        }
+    }
+}
+
+pub struct CtxField {
+    pub name: &'static str,
+    pub ty: CtxFieldType,
+}
+
+pub struct CtxAbi {
+    /// C struct name
+    pub name: &'static str,
+    /// attach targets using this context
+    pub attach: &'static str,
+    pub fields: &'static [CtxField],
+}
+
+impl CtxAbi {
+    pub const fn size(&self) -> usize {
+        let mut size = 0;
+        let mut i = 0;
+        while i < self.fields.len() {
+            size += self.fields[i].ty.size();
+            i += 1;
+        }
+        size
+    }
+}
+
+const fn field(name: &'static str, ty: CtxFieldType) -> CtxField {
+    CtxField { name, ty }
+}
+
+use CtxFieldType::*;
+
+pub const KPROBE_CTX: CtxAbi = CtxAbi {
+    name: "kprobe_ctx",
+    attach: "kprobe$, kretprobe@entry$, kretprobe@exit$, watchpoint$, watchpoint_rw$",
+    fields: &[field("ptype", U64), field("paddr", U64), field("regs", PtRegs)],
+};
+
+pub const UPROBE_CTX: CtxAbi = CtxAbi {
+    name: "uprobe_ctx",
+    attach: "uprobe_syncfunc$",
+    fields: &[field("ptype", U64), field("paddr", U64), field("regs", PtRegs)],
+};
+
+pub const RAW_SYSCALL_CTX: CtxAbi = CtxAbi {
+    name: "raw_syscall_ctx",
+    attach: "raw_syscalls$sys_enter, raw_syscalls$sys_exit",
+    fields: &[
+        field("id", U64),
+        field("args", Array(3)),
+        field("ret", I64),
+        field("pid", U64),
+        field("tid", U64),
+    ],
+};
+
+pub const FENTRY_CTX: CtxAbi = CtxAbi {
+    name: "fentry_ctx",
+    attach: "fentry$",
+    fields: &[field("pc", U64), field("caller", U64), field("args", Array(8))],
+};
+
+pub const SYS_ENTER_CTX: CtxAbi = CtxAbi {
+    name: "sys_enter_ctx",
+    attach: "tracepoint$syscalls:sys_enter",
+    fields: &[field("id", U64), field("args", Array(3))],
+};
+
+pub const SYS_EXIT_CTX: CtxAbi = CtxAbi {
+    name: "sys_exit_ctx",
+    attach: "tracepoint$syscalls:sys_exit",
+    fields: &[field("id", U64), field("args", Array(3)), field("ret", I64)],
+};
+
+pub const SCHED_SWITCH_CTX: CtxAbi = CtxAbi {
+    name: "sched_switch_ctx",
+    attach: "tracepoint$sched:sched_switch",
+    fields: &[
+        field("prev_pid", U64),
+        field("prev_tid", U64),
+        field("next_pid", U64),
+        field("next_tid", U64),
+    ],
+};
+
+pub const PROCESS_FORK_CTX: CtxAbi = CtxAbi {
+    name: "process_fork_ctx",
+    attach: "tracepoint$sched:process_fork",
+    fields: &[field("parent_pid", U64), field("child_pid", U64)],
+};
+
+pub const PROCESS_EXEC_CTX: CtxAbi = CtxAbi {
+    name: "process_exec_ctx",
+    attach: "tracepoint$sched:process_exec",
+    fields: &[field("pid", U64), field("path", Chars(32))],
+};
+
+pub const PROCESS_EXIT_CTX: CtxAbi = CtxAbi {
+    name: "process_exit_ctx",
+    attach: "tracepoint$sched:process_exit",
+    fields: &[field("pid", U64), field("tid", U64), field("exit_code", I64)],
+};
+
+pub const PAGE_FAULT_CTX: CtxAbi = CtxAbi {
+    name: "page_fault_ctx",
+    attach: "tracepoint$exceptions:page_fault",
+    fields: &[
+        field("pid", U64),
+        field("cause", U64),
+        field("addr", U64),
+        field("pc", U64),
+    ],
+};
+
+/// every context, in the order they appear in the header
+pub const CTX_ABIS: [&CtxAbi; 11] = [
+    &KPROBE_CTX,
+    &UPROBE_CTX,
+    &RAW_SYSCALL_CTX,
+    &FENTRY_CTX,
+    &SYS_ENTER_CTX,
+    &SYS_EXIT_CTX,
+    &SCHED_SWITCH_CTX,
+    &PROCESS_FORK_CTX,
+    &PROCESS_EXEC_CTX,
+    &PROCESS_EXIT_CTX,
+    &PAGE_FAULT_CTX,
+];
@@ -13,21 +13,54 @@ use alloc::string::ToString;
 use super::{
     retcode::*,
     osutil::*, map::{bpf_map_lookup_elem, bpf_map_update_elem, bpf_map_delete_elem},
+    subprog::MAX_BPF_STACK,
+    tracepoints::bpf_request_override,
+    trace_channel::trace_output,
 };
 
 /// follow linux convention
 pub type BpfHelperFn = fn(u64, u64, u64, u64, u64) -> i64;
 
-pub const HELPER_FN_COUNT: usize = 17;
+/// helpers that are not in linux get ids from here on, past the ids linux uses
+pub const HELPER_CUSTOM_BASE: u32 = 256;
+
+pub const HELPER_FN_COUNT: usize = HELPER_CUSTOM_BASE as usize + 2;
+
+/// not a linux helper, bpf_trace_output
+pub const HELPER_TRACE_OUTPUT: u32 = HELPER_CUSTOM_BASE;
+
+/// not a linux helper, only in GDB_HELPER_FN_TABLE
+pub const HELPER_GDB_COLLECT: u32 = HELPER_CUSTOM_BASE + 1;
 
 /// use static to make address never change
-/// some function are still in progress, they are redirect to NOP
-pub static HELPER_FN_TABLE: [BpfHelperFn; HELPER_FN_COUNT] = [
+/// ids without a helper are redirect to NOP
+pub static HELPER_FN_TABLE: [BpfHelperFn; HELPER_FN_COUNT] = helper_fn_table(false);
+
+/// HELPER_FN_TABLE with HELPER_GDB_COLLECT, only for the programs the gdb stub generates,
+/// the helper trusts its ctx to be a kprobe context
+pub static GDB_HELPER_FN_TABLE: [BpfHelperFn; HELPER_FN_COUNT] = helper_fn_table(true);
+
+const fn helper_fn_table(gdb: bool) -> [BpfHelperFn; HELPER_FN_COUNT] {
+    let mut table = [bpf_helper_nop as BpfHelperFn; HELPER_FN_COUNT];
+    let mut i = 0;
+    while i < LINUX_HELPER_FNS.len() {
+        table[i] = LINUX_HELPER_FNS[i];
+        i += 1;
+    }
+    table[HELPER_TRACE_OUTPUT as usize] = bpf_helper_trace_output;
+    if gdb {
+        table[HELPER_GDB_COLLECT as usize] = bpf_helper_gdb_collect;
+    }
+    table
+}
+
+/// helpers by their linux id, some function are still in progress, they are redirect to NOP
+const LINUX_HELPER_FNS: [BpfHelperFn; 59] = [
     bpf_helper_nop,
     bpf_helper_map_lookup_elem,
     bpf_helper_map_update_elem,
     bpf_helper_map_delete_elem,
-    bpf_helper_nop, //bpf_helper_probe_read
+    bpf_helper_probe_read,
     bpf_helper_ktime_get_ns,
     bpf_helper_trace_printk,
     bpf_helper_get_prandom_u32,
@@ -40,6 +73,48 @@ pub static HELPER_FN_TABLE: [BpfHelperFn; HELPER_FN_COUNT] = [
     bpf_helper_get_current_pid_tgid,
     bpf_helper_nop, // bpf_get_current_uid_gid
     bpf_helper_get_current_comm,
+    bpf_helper_nop, // bpf_get_cgroup_classid
+    bpf_helper_nop, // bpf_skb_vlan_push
+    bpf_helper_nop, // bpf_skb_vlan_pop
+    bpf_helper_nop, // bpf_skb_get_tunnel_key
+    bpf_helper_nop, // bpf_skb_set_tunnel_key
+    bpf_helper_nop, // bpf_perf_event_read
+    bpf_helper_nop, // bpf_redirect
+    bpf_helper_nop, // bpf_get_route_realm
+    bpf_helper_nop, // bpf_perf_event_output
+    bpf_helper_nop, // bpf_skb_load_bytes
+    bpf_helper_nop, // bpf_get_stackid
+    bpf_helper_nop, // bpf_csum_diff
+    bpf_helper_nop, // bpf_skb_get_tunnel_opt
+    bpf_helper_nop, // bpf_skb_set_tunnel_opt
+    bpf_helper_nop, // bpf_skb_change_proto
+    bpf_helper_nop, // bpf_skb_change_type
+    bpf_helper_nop, // bpf_skb_under_cgroup
+    bpf_helper_nop, // bpf_get_hash_recalc
+    bpf_helper_get_current_task,
+    bpf_helper_nop, // bpf_probe_write_user
+    bpf_helper_nop, // bpf_current_task_under_cgroup
+    bpf_helper_nop, // bpf_skb_change_tail
+    bpf_helper_nop, // bpf_skb_pull_data
+    bpf_helper_nop, // bpf_csum_update
+    bpf_helper_nop, // bpf_set_hash_invalid
+    bpf_helper_nop, // bpf_get_numa_node_id
+    bpf_helper_nop, // bpf_skb_change_head
+    bpf_helper_nop, // bpf_xdp_adjust_head
+    bpf_helper_nop, // bpf_probe_read_str
+    bpf_helper_nop, // bpf_get_socket_cookie
+    bpf_helper_nop, // bpf_get_socket_uid
+    bpf_helper_nop, // bpf_set_hash
+    bpf_helper_nop, // bpf_setsockopt
+    bpf_helper_nop, // bpf_skb_adjust_room
+    bpf_helper_nop, // bpf_redirect_map
+    bpf_helper_nop, // bpf_sk_redirect_map
+    bpf_helper_nop, // bpf_sock_map_update
+    bpf_helper_nop, // bpf_xdp_adjust_meta
+    bpf_helper_nop, // bpf_perf_event_read_value
+    bpf_helper_nop, // bpf_perf_prog_read_value
+    bpf_helper_nop, // bpf_getsockopt
+    bpf_helper_override_return,
 ];
 
 
@@ -71,6 +146,34 @@ fn bpf_helper_nop(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
     0
 }
 
+/// long bpf_probe_read(void *dst, u32 size, const void *src)
+/// copy `size` bytes of kernel memory at `src` to `dst`, e.g. fields found with type info
+/// dst is a buffer on the program stack, so size is at most MAX_BPF_STACK (-E2BIG) and dst
+/// must be kernel memory (-EFAULT)
+/// dst is zeroed and -EFAULT returned if src is not kernel memory
+fn bpf_helper_probe_read(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    let (dst, size, src) = (dst as usize, size as u32 as usize, src as usize);
+    if size > MAX_BPF_STACK {
+        return -(BpfErrorCode::E2BIG as i64);
+    }
+    if !os_kernel_addr_valid(dst, size) {
+        return -(BpfErrorCode::EFAULT as i64);
+    }
+    let dst = dst as *mut u8;
+    if !os_kernel_addr_valid(src, size) {
+        unsafe { core::ptr::write_bytes(dst, 0, size) };
+        return -(BpfErrorCode::EFAULT as i64);
+    }
+    copy(dst, src as *const u8, size);
+    0
+}
+
+/// u64 bpf_get_current_task(void)
+/// address of the TaskControlBlock of the current thread, read it with bpf_probe_read
+fn bpf_helper_get_current_task(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    os_current_task_ptr() as i64
+}
+
 /// u64 bpf_ktime_get_ns(void)
 /// return current ktime
 /// uses os_current_time in `osutils.rs`
@@ -93,6 +196,22 @@ fn bpf_helper_trace_printk(fmt: u64, fmt_size: u64, p1: u64, p2: u64, p3: u64) -
     os_console_write_str(output.as_str()) //return number of bytes written
 }
 
+/// long bpf_trace_output(u64 type, const void *data, u64 size)
+/// send a typed binary record to the debugger through the trace channel on uart1,
+/// HELPER_TRACE_OUTPUT
+/// returns -E2BIG if the record is too large, -EBUSY if the channel queue is full
+fn bpf_helper_trace_output(ty: u64, data: u64, size: u64, _4: u64, _5: u64) -> i64 {
+    let size = size as u32 as usize;
+    if !os_kernel_addr_valid(data as usize, size) {
+        return -(BpfErrorCode::EFAULT as i64);
+    }
+    let payload = unsafe { core::slice::from_raw_parts(data as *const u8, size) };
+    match trace_output(ty as u8, payload) {
+        Ok(()) => 0,
+        Err(err) => -(err as i64),
+    }
+}
+
 /// not implemented
 fn bpf_helper_get_prandom_u32(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
     todo!()
@@ -111,6 +230,20 @@ fn bpf_helper_get_current_pid_tgid(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64)
     ((pid << 32) | pid) as i64
 }
 
+/// long bpf_override_return(void *ctx, u64 rc)
+/// make the probed function return `rc` without running its body
+/// only takes effect on kprobes of allow-listed functions and on uprobe_syncfunc
+fn bpf_helper_override_return(_ctx: u64, rc: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    bpf_request_override(rc);
+    0
+}
+
+/// long gdb_collect(void *ctx, u64 tracepoint)
+/// run the collect actions of a gdb tracepoint on a kprobe context
+fn bpf_helper_gdb_collect(ctx: u64, tracepoint: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    os_gdb_collect(ctx as *const u8, tracepoint as usize)
+}
+
 /// get current thread name
 fn bpf_helper_get_current_comm(dst: u64, buf_size: u64, _1: u64, _2: u64, _3: u64) -> i64 {
     let thread = os_current_thread();
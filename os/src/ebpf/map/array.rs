@@ -67,7 +67,12 @@ impl BpfMap for ArrayMap {
 
     fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
         let out = next_key as *mut u32;
-        let index = unsafe { *(key as *const u32) } as usize;
+        // a null key starts from the first index, like an out of range one
+        let index = if key.is_null() {
+            usize::MAX
+        } else {
+            unsafe { *(key as *const u32) as usize }
+        };
         if index >= self.attr.max_entries {
             unsafe {
                 *out = 0u32;
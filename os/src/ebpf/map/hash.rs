@@ -157,7 +157,6 @@ impl BpfMap for HashMap {
 
     fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
         let key_size = self.attr.key_size;
-        let hashcode = HashMap::hash(key, key_size);
 
         let get_first_key = || {
             //returns the first valid key
@@ -171,6 +170,10 @@ impl BpfMap for HashMap {
             }
         };
 
+        if key.is_null() {
+            return get_first_key();
+        }
+        let hashcode = HashMap::hash(key, key_size);
         let mut iter = self.map.range(hashcode..);
         match iter.next() {
             Some((_, vec)) => {
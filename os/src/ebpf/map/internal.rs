@@ -15,6 +15,7 @@ use super::osutil::{copy, memcmp};
 
 #[derive(Debug, Clone, Copy)]
 pub struct InternalMapAttr {
+    pub map_type: u32,
     pub key_size: usize,
     pub value_size: usize,
     pub max_entries: usize,
@@ -23,6 +24,7 @@ pub struct InternalMapAttr {
 impl From<MapAttr> for InternalMapAttr {
     fn from(attr: MapAttr) -> Self {
         Self {
+            map_type: attr.map_type,
             key_size: attr.key_size as usize,
             value_size: attr.value_size as usize,
             max_entries: attr.max_entries as usize,
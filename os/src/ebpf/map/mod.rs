@@ -46,6 +46,16 @@ pub struct MapOpAttr {
     pub flags: u64,
 }
 
+/// returned by BPF_OBJ_GET_INFO_BY_FD for maps
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BpfMapInfo {
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+}
+
 #[derive(Debug)]
 pub enum BpfMapOp {
     LookUp,
@@ -90,6 +100,17 @@ pub fn bpf_map_close(fd: u32) -> BpfResult {
     bpf_object_remove(fd).map_or(Ok(0), |_| Err(ENOENT))
 }
 
+/// get map info, for BPF_OBJ_GET_INFO_BY_FD
+pub fn bpf_map_get_info(fd: u32) -> Option<BpfMapInfo> {
+    let attr = bpf_map_get_attr(fd)?;
+    Some(BpfMapInfo {
+        map_type: attr.map_type,
+        key_size: attr.key_size as u32,
+        value_size: attr.value_size as u32,
+        max_entries: attr.max_entries as u32,
+    })
+}
+
 /// get map attributes
 pub fn bpf_map_get_attr(fd: u32) -> Option<InternalMapAttr> {
     let bpf_objs = BPF_OBJECTS.lock();
@@ -123,8 +144,16 @@ pub fn bpf_map_ops(fd: u32, op: BpfMapOp, key: *const u8, value: *mut u8, flags:
         let key_size = map.get_attr().key_size;
         let value_size = map.get_attr().value_size;
         let mut key_kern_buf = alloc::vec![0 as u8; key_size];
-        let kptr = key_kern_buf.as_mut_ptr();
-        os_copy_from_user(key as usize, kptr, key_size);
+        // like linux, a null key asks GetNextKey for the first key
+        let kptr = if key.is_null() {
+            if !matches!(op, BpfMapOp::GetNextKey) {
+                return Err(EINVAL);
+            }
+            core::ptr::null_mut()
+        } else {
+            os_copy_from_user(key as usize, key_kern_buf.as_mut_ptr(), key_size);
+            key_kern_buf.as_mut_ptr()
+        };
         let mut value_kern_buf = alloc::vec![0 as u8; value_size];
         let vptr = value_kern_buf.as_mut_ptr();
         match op {
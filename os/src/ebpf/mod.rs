@@ -9,14 +9,22 @@
 #![allow(unused_variables)]
 #![allow(unreachable_code)]
 
+#[macro_use]
+pub mod btf;
 pub mod consts;
+pub mod context;
+pub mod ctx_abi;
 mod helpers;
 pub mod map;
 pub mod program;
 pub mod tracepoints;
 pub mod retcode;
+pub mod subprog;
+pub mod trace_channel;
 pub mod osutil;
 
+pub use helpers::HELPER_GDB_COLLECT;
+
 use lock::Mutex;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
@@ -83,3 +91,22 @@ pub fn bpf_object_create_program(fd: u32, prog: BpfProgram) {
 pub fn bpf_object_remove(fd: u32) -> Option<BpfObject> {
     BPF_OBJECTS.lock().remove(&fd)
 }
+
+/// GetNextIdAttr, follows the linux convection
+///
+/// Used by BPF_PROG_GET_NEXT_ID and BPF_MAP_GET_NEXT_ID, fds serve as ids
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct GetNextIdAttr {
+    pub start_id: u32,
+    pub next_id: u32,
+    pub open_flags: u32,
+}
+
+/// the smallest fd above `start` of a map or, if `map` is false, of a program
+pub fn bpf_object_next_fd(start: u32, map: bool) -> Option<u32> {
+    let objs = BPF_OBJECTS.lock();
+    objs.range(start.saturating_add(1)..)
+        .find(|(_, obj)| obj.is_map().is_some() == map)
+        .map(|(fd, _)| *fd)
+}
@@ -5,13 +5,17 @@
 //! one needs to change os_* to migrate to another kernel
 
 use super::{
+    bpf_object_next_fd, bpf_object_remove, GetNextIdAttr,
+    btf::{BtfField, BtfStruct},
     map::*,
     map::MapAttr,
     map::MapOpAttr,
     retcode::BpfResult,
     tracepoints::KprobeAttachAttr,
     tracepoints::*,
-    program::{bpf_program_load_ex, ProgramLoadExAttr, MapFdEntry},
+    program::{bpf_program_load_ex, bpf_program_get_info, bpf_enable_stats, ProgramLoadExAttr, MapFdEntry,
+              ProgFdEntry, BPF_PROG_NAME_LEN,
+              ObjInfoAttr, EnableStatsAttr, BpfProgInfo},
 };
 
 use core::{mem::size_of, fmt::Write, iter::Map};
@@ -22,6 +26,8 @@ use core::slice::{from_raw_parts, from_raw_parts_mut};
 use downcast_rs::{impl_downcast, DowncastSync};
 
 use crate::{task::TaskControlBlock, drivers::chardev::{UART1, CharDevice}};
+use crate::sync::UPIntrFreeCell;
+use crate::task::{ProcessControlBlock, ProcessControlBlockInner, TaskControlBlockInner};
 
 /// ThreadLike is an analog for Linux thread
 pub trait ThreadLike : DowncastSync {
@@ -39,7 +45,11 @@ impl ThreadLike for TaskControlBlock {
         return proc.pid.0 as u64;
     }
     fn get_tid(&self) -> u64 {
-        return 0; // no viable in rcore tutor
+        // res is None once the thread has exited
+        self.inner_exclusive_access()
+            .res
+            .as_ref()
+            .map_or(0, |res| res.tid as u64)
     }
     fn get_name(&self) -> String {
         return String::from("not viable in rcore tutorial")
@@ -55,14 +65,102 @@ pub fn os_current_thread() -> Arc<dyn ThreadLike> {
     }
 }
 
-/// get current time
+/// pid of the current process, 0 if there is none
+pub fn os_current_pid() -> u64 {
+    crate::task::current_task().map_or(0, |task| task.get_pid())
+}
+
+/// pointer to the TaskControlBlock of the current thread, 0 if there is none
+pub fn os_current_task_ptr() -> usize {
+    crate::task::current_task().map_or(0, |task| Arc::as_ptr(&task) as usize)
+}
+
+/// address of a kernel function, None if the kernel has no symbol for it
+pub fn os_symbol_to_addr(symbol: &str) -> Option<usize> {
+    crate::probe::osutils::symbol_to_addr(symbol)
+}
+
+/// whether [addr, addr + len) is kernel memory that can be read without faulting
+pub fn os_kernel_addr_valid(addr: usize, len: usize) -> bool {
+    extern "C" {
+        fn skernel();
+    }
+    addr >= skernel as usize
+        && addr
+            .checked_add(len)
+            .map_or(false, |end| end <= crate::config::MEMORY_END)
+}
+
+/// kernel structs exposed to eBPF programs by name, see `btf.rs`
+/// evaluated when the kernel is built, fields that are cells point at the value inside the cell
+pub static OS_BTF_STRUCTS: [BtfStruct; 5] = [
+    BtfStruct {
+        name: "TaskControlBlock",
+        size: size_of::<TaskControlBlock>(),
+        fields: &[
+            btf_field!(TaskControlBlock, process),
+            btf_field!(TaskControlBlock, kstack),
+            btf_field!(TaskControlBlock, inner).unwrap_cell(
+                UPIntrFreeCell::<TaskControlBlockInner>::value_offset(),
+                size_of::<TaskControlBlockInner>(),
+            ),
+        ],
+    },
+    btf_struct!(TaskControlBlockInner {
+        res,
+        trap_cx_ppn,
+        task_cx,
+        task_status,
+        exit_code,
+    }),
+    BtfStruct {
+        name: "ProcessControlBlock",
+        size: size_of::<ProcessControlBlock>(),
+        fields: &[
+            btf_field!(ProcessControlBlock, pid),
+            btf_field!(ProcessControlBlock, inner).unwrap_cell(
+                UPIntrFreeCell::<ProcessControlBlockInner>::value_offset(),
+                size_of::<ProcessControlBlockInner>(),
+            ),
+        ],
+    },
+    btf_struct!(ProcessControlBlockInner {
+        is_zombie,
+        memory_set,
+        parent,
+        children,
+        exit_code,
+        fd_table,
+        signals,
+        tasks,
+        path,
+    }),
+    // TaskControlBlock.process is a Weak, it points at the ArcInner (repr(C) in alloc)
+    BtfStruct {
+        name: "ArcInner",
+        size: 2 * size_of::<usize>(),
+        fields: &[
+            BtfField { name: "strong", offset: 0, size: size_of::<usize>() },
+            BtfField { name: "weak", offset: size_of::<usize>(), size: size_of::<usize>() },
+            BtfField { name: "data", offset: 2 * size_of::<usize>(), size: 0 },
+        ],
+    },
+];
+
+/// get current time in ns, precision is one timer tick
 pub fn os_current_time() -> u128 {
-   crate::timer::get_time_ms() as u128 * 1_000_000
+   crate::timer::get_time() as u128 * 1_000_000_000 / crate::config::CLOCK_FREQ as u128
 }
 
 /// get current hart
+#[link_section = ".text.noprobe"]
 pub fn os_get_current_cpu() -> u8 {
-   0 // not viable
+   crate::probe::osutils::current_hart_id() as u8
+}
+
+/// run the collect actions of gdb tracepoint `number` on a kprobe context
+pub fn os_gdb_collect(ctx: *const u8, number: usize) -> i64 {
+    crate::gdbstub::trace_collect(ctx, number)
 }
 
 /// write a str to uart1 log
@@ -77,6 +175,12 @@ pub fn os_console_write_str(s: &str) -> i64 {
     bytes_written as i64
 }
 
+/// queue a trace channel record for uart1 without blocking
+/// returns false if it does not fit in the queue
+pub fn os_trace_write(record: &[u8]) -> bool {
+    UART1.write_async(record)
+}
+
 /// # os_copy_from_user
 /// copy `len` bytes from user space addresss `usr_addr` to `kern_buf`
 #[inline(never)]
@@ -218,10 +322,80 @@ pub fn sys_bpf_program_detach(attr: *const u8, size: usize) -> i32 {
     convert_result(bpf_program_detach(detach_attr.prog_fd))
 }
 
+/// wrapper
+/// copy at most `info_len` bytes of the program or map info to user space
+pub fn sys_bpf_obj_get_info_by_fd(attr: *const u8, size: usize) -> i32 {
+    let info_attr: ObjInfoAttr = get_generic_from_user(attr as usize);
+    if let Some(info) = bpf_map_get_info(info_attr.bpf_fd) {
+        let len = (info_attr.info_len as usize).min(size_of::<BpfMapInfo>());
+        os_copy_to_user(info_attr.info as usize, &info as *const BpfMapInfo as *const u8, len);
+        return 0;
+    }
+    match bpf_program_get_info(info_attr.bpf_fd) {
+        Ok(info) => {
+            let len = (info_attr.info_len as usize).min(size_of::<BpfProgInfo>());
+            os_copy_to_user(info_attr.info as usize, &info as *const BpfProgInfo as *const u8, len);
+            0
+        }
+        Err(err) => convert_result(Err(err)),
+    }
+}
+
+/// wrapper
+/// writes the next fd back to `next_id` of the attr
+pub fn sys_bpf_obj_get_next_id(attr: *const u8, size: usize, map: bool) -> i32 {
+    let mut id_attr: GetNextIdAttr = get_generic_from_user(attr as usize);
+    match bpf_object_next_fd(id_attr.start_id, map) {
+        Some(fd) => {
+            id_attr.next_id = fd;
+            os_copy_to_user(attr as usize, &id_attr as *const GetNextIdAttr as *const u8, size_of::<GetNextIdAttr>());
+            0
+        }
+        None => convert_result(Err(super::retcode::BpfErrorCode::ENOENT)),
+    }
+}
+
+/// wrapper
+pub fn sys_bpf_enable_stats(attr: *const u8, size: usize) -> i32 {
+    let stats_attr: EnableStatsAttr = get_generic_from_user(attr as usize);
+    convert_result(bpf_enable_stats(stats_attr.enable != 0))
+}
+
 /// wrapper
 /// this is a custome function, so we just copy from rCore
-pub fn sys_bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> i32 {
-    let ret = convert_result(bpf_program_load_ex(prog, &map_info));
+/// fills `prog_array` with the loaded programs and returns the fd of the first one.
+/// E2BIG if there are more programs than `prog_array` holds, callers without one only
+/// get the first, the programs are unloaded then
+pub fn sys_bpf_program_load_ex(
+    prog: &mut [u8],
+    map_info: &[(String, u32)],
+    prog_array: *mut ProgFdEntry,
+    prog_array_len: usize,
+) -> i32 {
+    let loaded = match bpf_program_load_ex(prog, &map_info) {
+        Ok(loaded) => loaded,
+        Err(err) => return convert_result(Err(err)),
+    };
+    if loaded.len() > prog_array_len.max(1) {
+        for (_, fd) in loaded.iter() {
+            bpf_object_remove(*fd);
+        }
+        return convert_result(Err(super::retcode::BpfErrorCode::E2BIG));
+    }
+    for i in 0..prog_array_len {
+        let mut entry = ProgFdEntry {
+            name: [0; BPF_PROG_NAME_LEN],
+            fd: 0,
+        };
+        if let Some((name, fd)) = loaded.get(i) {
+            let len = name.len().min(BPF_PROG_NAME_LEN - 1);
+            entry.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+            entry.fd = *fd;
+        }
+        let dst = unsafe { prog_array.add(i) } as usize;
+        os_copy_to_user(dst, &entry as *const ProgFdEntry as *const u8, size_of::<ProgFdEntry>());
+    }
+    let ret = loaded[0].1 as i32;
     trace!("load ex ret: {}", ret);
     ret
 }
@@ -230,7 +404,7 @@ pub fn sys_bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> i
 /// a wrapper that parse the `attr_ptr` and then call `bpf_program_load_ex`
 /// # argumetns
 /// * attr_ptr - a pointer that should points to a `ProgramLoadExAttr` objects
-/// * size - unused
+/// * size - size of the attr, older callers pass one without `prog_array`
 /// # procedure
 /// * cast the attr using `get_generic_from_user`
 /// * copy the BPF elf from user space 
@@ -238,8 +412,10 @@ pub fn sys_bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> i
 /// * call `sys_bpf_program_load_ex`
 #[allow(unused_mut)]
 pub fn sys_preprocess_bpf_program_load_ex(attr_ptr: *const u8, size: usize) -> i32 {
-
-    let attr:ProgramLoadExAttr = get_generic_from_user(attr_ptr as usize);
+    // fields the caller does not pass stay zero
+    let mut attr: ProgramLoadExAttr = unsafe { core::mem::zeroed() };
+    let attr_size = size.min(size_of::<ProgramLoadExAttr>());
+    os_copy_from_user(attr_ptr as usize, &mut attr as *mut ProgramLoadExAttr as *mut u8, attr_size);
 
    trace!("prog load attr\n prog_base:{:x} prog_size={} map_base:{:x} map_num={}", attr.elf_prog, attr.elf_size, attr.map_array as usize, attr.map_array_len);
     let base = attr.elf_prog as usize;
@@ -265,7 +441,8 @@ pub fn sys_preprocess_bpf_program_load_ex(attr_ptr: *const u8, size: usize) -> i
         }   
     }
 
-    sys_bpf_program_load_ex(&mut prog[..], &map_info[..])
+    let prog_array_len = if attr.prog_array.is_null() { 0 } else { attr.prog_array_len as usize };
+    sys_bpf_program_load_ex(&mut prog[..], &map_info[..], attr.prog_array, prog_array_len)
 }
 
 /// read a C style string from user space pointed by `ptr`
@@ -4,10 +4,12 @@
  
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use xmas_elf;
 use xmas_elf::header::Machine;
 use xmas_elf::sections::*;
-use xmas_elf::symbol_table::Entry;
+use xmas_elf::symbol_table::{Entry, Type};
+use alloc::collections::BTreeSet;
 
 #[cfg(target_arch = "riscv64")]
 use ebpf2rv::compile;
@@ -15,8 +17,13 @@ use ebpf2rv::compile;
 use super::{
     *,
     consts::*,
+    btf::{btf_resolve_symbol, is_btf_symbol},
+    context::ctx_access_size,
+    map::{bpf_map_create, MapAttr},
+    osutil::copy,
+    subprog::{inline_calls, set_call_target, MAX_BPF_STACK},
     helpers::*,
-    retcode::BpfErrorCode::*,
+    retcode::BpfErrorCode::{self, *},
     retcode::BpfResult,
 };
 
@@ -27,6 +34,17 @@ pub struct MapFdEntry {
     pub fd: u32,
 }
 
+/// max length of a program name, which is the ELF section name
+pub const BPF_PROG_NAME_LEN: usize = 32;
+
+/// one loaded program, the name is nul-terminated and truncated if too long
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProgFdEntry {
+    pub name: [u8; BPF_PROG_NAME_LEN],
+    pub fd: u32,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct ProgramLoadExAttr {
@@ -34,17 +52,101 @@ pub struct ProgramLoadExAttr {
     pub elf_size: u32,
     pub map_array_len: u32,
     pub map_array: *const MapFdEntry,
+    /// filled with the programs found in the ELF, entries left over get an empty name.
+    /// older callers do not pass these two, see sys_preprocess_bpf_program_load_ex
+    pub prog_array_len: u32,
+    pub prog_array: *mut ProgFdEntry,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ObjInfoAttr {
+    pub bpf_fd: u32,
+    pub info_len: u32,
+    pub info: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EnableStatsAttr {
+    pub enable: u32,
+}
+
+/// returned by BPF_OBJ_GET_INFO_BY_FD for programs
+/// run_time_ns and run_cnt only grow while stats are enabled
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BpfProgInfo {
+    pub jited_prog_len: u32,
+    pub nr_map_fds: u32,
+    pub run_time_ns: u64,
+    pub run_cnt: u64,
+    /// runs skipped because another program was already running on the same hart
+    pub recursion_misses: u64,
+    /// ELF section the program was loaded from, nul-terminated
+    pub name: [u8; BPF_PROG_NAME_LEN],
+}
+
+/// like kernel.bpf_stats_enabled in linux, off by default so that accounting costs nothing
+static BPF_STATS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn bpf_stats_enabled() -> bool {
+    BPF_STATS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// turn runtime stats on or off, returns the previous state
+pub fn bpf_enable_stats(enable: bool) -> BpfResult {
+    Ok(BPF_STATS_ENABLED.swap(enable, Ordering::Relaxed) as usize)
 }
 
 /// actual defination of BpfProgram,
 /// bpf_insns is unused
 pub struct BpfProgram {
+    /// section the program was loaded from
+    pub name: String,
     bpf_insns: Option<Vec<u64>>,
     jited_prog: Option<Vec<u32>>, // TODO: should be something like Vec<u8>
-    pub map_fd_table: Option<Vec<u32>>,
+    /// shared by the programs of one ELF, relocated code points into it
+    pub map_fd_table: Option<Arc<Vec<u32>>>,
+    /// internal maps holding .data, .rodata and .bss, shared by the programs of one ELF
+    data_maps: Option<Arc<DataMaps>>,
+    run_cnt: AtomicU64,
+    run_time_ns: AtomicU64,
+    misses: AtomicU64,
+    /// bytes of the context read by the program, checked on attach
+    ctx_access_size: usize,
 }
 
 impl BpfProgram {
+    /// account one run that took `ns`, called only when stats are enabled
+    pub fn record_run(&self, ns: u64) {
+        self.run_cnt.fetch_add(1, Ordering::Relaxed);
+        self.run_time_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    /// account one run skipped by the recursion guard, always counted
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ctx_access_size(&self) -> usize {
+        self.ctx_access_size
+    }
+
+    pub fn get_info(&self) -> BpfProgInfo {
+        let mut name = [0; BPF_PROG_NAME_LEN];
+        let len = self.name.len().min(BPF_PROG_NAME_LEN - 1);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        BpfProgInfo {
+            jited_prog_len: self.jited_prog.as_ref().map_or(0, |code| code.len() * 4) as u32,
+            nr_map_fds: self.map_fd_table.as_ref().map_or(0, |table| table.len()) as u32,
+            run_time_ns: self.run_time_ns.load(Ordering::Relaxed),
+            run_cnt: self.run_cnt.load(Ordering::Relaxed),
+            recursion_misses: self.misses.load(Ordering::Relaxed),
+            name,
+        }
+    }
+
     /// run cast pointer to a function and runs it
     pub fn run(&self, ctx: *const u8) -> i64 {
         if let Some(compiled_code) = &self.jited_prog {
@@ -59,6 +161,55 @@ impl BpfProgram {
     }
 }
 
+/// fds of the internal maps holding the global data sections of one ELF.
+/// the maps are removed when this is dropped: when the last program using them is removed,
+/// or when loading the ELF fails
+struct DataMaps(Vec<u32>);
+
+impl Drop for DataMaps {
+    fn drop(&mut self) {
+        for &fd in self.0.iter() {
+            bpf_object_remove(fd);
+        }
+    }
+}
+
+/// sections whose globals are kept in internal maps, like libbpf does.
+/// clang puts string literals into .rodata.str1.1 and friends
+fn is_global_data_section(name: &str) -> bool {
+    name == ".data" || name == ".bss" || name.starts_with(".rodata")
+}
+
+/// create a single-element array map holding a global data section of `size` bytes,
+/// initialized with `init` or zeroes (.bss)
+/// returns the map fd and the address of its value, which programs access directly.
+/// nothing stops a program from writing to .rodata, there is no verifier to reject it
+fn create_data_map(size: usize, init: Option<&[u8]>) -> Result<(u32, usize), BpfErrorCode> {
+    let attr = MapAttr {
+        map_type: BPF_MAP_TYPE_ARRAY,
+        key_size: 4,
+        value_size: size as u32,
+        max_entries: 1,
+    };
+    let fd = bpf_map_create(attr)? as u32;
+    let key = 0u32;
+    let addr = {
+        let objs = BPF_OBJECTS.lock();
+        let map = objs.get(&fd).and_then(|obj| obj.is_map()).ok_or(ENOENT)?;
+        let addr = map.lock().lookup_helper(&key as *const u32 as *const u8);
+        addr
+    };
+    let addr = addr.map_err(|err| {
+        bpf_object_remove(fd);
+        err
+    })?;
+    if let Some(data) = init {
+        let len = data.len().min(size);
+        copy(addr as *mut u8, data.as_ptr(), len);
+    }
+    Ok((fd, addr))
+}
+
 /// load the bpf program with map config into kernel
 /// # arguments
 /// * `prog` - &mut [u8] the program elf in hexvalue
@@ -66,13 +217,21 @@ impl BpfProgram {
 /// # procedure
 /// * parse the elf
 /// * build the map fd table
+/// * copy global data sections into internal maps
 /// * relocate access to map by map fd table
+/// * relocate access to global variables to the internal maps
+/// * relocate kernel struct offsets and sizes by the kernel type info
 /// * relocate helper functions
+/// * inline bpf-to-bpf calls of each program
+/// * find how much of the context is read
 /// * JIT the prog
 /// * create BPF objects 
 /// # return value
-/// * fd of the program 
-pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfResult {
+/// * (section name, fd) of every program in the ELF
+pub fn bpf_program_load_ex(
+    prog: &mut [u8],
+    map_info: &[(String, u32)],
+) -> Result<Vec<(String, u32)>, BpfErrorCode> {
     trace!("bpf program load ex");
     let _base = prog.as_ptr();
     let elf = xmas_elf::ElfFile::new(prog).map_err(|_| EINVAL)?;
@@ -91,11 +250,66 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
 
     // build index -> map_fd variable address mapping
     let mut map_symbols = BTreeMap::new();
+    // index -> offset or size of a kernel struct, see btf.rs
+    let mut btf_symbols = BTreeMap::new();
+    // global data sections become internal array maps, section index -> value address
+    // the maps are removed on any error below, as nothing else holds them yet
+    let mut data_maps = DataMaps(Vec::new());
+    let mut data_sections = BTreeMap::new();
+    for (sec_idx, sec_hdr) in elf.section_iter().enumerate() {
+        let name = sec_hdr.get_name(&elf).unwrap_or("");
+        if !is_global_data_section(name) || sec_hdr.size() == 0 {
+            continue;
+        }
+        let init = match sec_hdr.get_type() {
+            Ok(ShType::NoBits) => None,
+            _ => Some(sec_hdr.raw_data(&elf)),
+        };
+        let (fd, addr) = create_data_map(sec_hdr.size() as usize, init)?;
+        info!("global data {} in map fd {}, addr: {:x}", name, fd, addr);
+        data_maps.0.push(fd);
+        data_sections.insert(sec_idx, addr);
+    }
+    // index -> address of a global variable, or of its section for section symbols
+    let mut data_symbols = BTreeMap::new();
+    // code sections, the ones other than .text are programs
+    let mut code_sections = BTreeMap::new();
+    for (sec_idx, sec_hdr) in elf.section_iter().enumerate() {
+        if sec_hdr.flags() & SHF_EXECINSTR != 0 && sec_hdr.size() > 0 {
+            code_sections.insert(sec_idx, sec_hdr.get_name(&elf).map_err(|_| EINVAL)?);
+        }
+    }
+    let text_idx = code_sections.iter().find(|(_, &name)| name == ".text").map(|(&idx, _)| idx);
+    // index -> (section, offset) of a function, or of a code section for section symbols
+    let mut func_symbols = BTreeMap::new();
+    // instruction index of every function in .text
+    let mut text_funcs = BTreeSet::new();
+
     let sym_tab_hdr = elf.find_section_by_name(".symtab").ok_or(ENOENT)?;
     trace!("symbol table");
     if let Ok(SectionData::SymbolTable64(sym_entries)) = sym_tab_hdr.get_data(&elf) {
         for (sym_idx, sym) in sym_entries.iter().enumerate() {
+            let shndx = sym.shndx() as usize;
+            if let Some(&addr) = data_sections.get(&shndx) {
+                data_symbols.insert(sym_idx, addr + sym.value() as usize);
+            }
+            if code_sections.contains_key(&shndx) {
+                func_symbols.insert(sym_idx, (shndx, sym.value() as usize));
+                if Some(shndx) == text_idx && matches!(sym.get_type(), Ok(Type::Func)) {
+                    text_funcs.insert(sym.value() as usize / 8);
+                }
+            }
             if let Ok(name) = sym.get_name(&elf) {
+                if is_btf_symbol(name) {
+                    match btf_resolve_symbol(name) {
+                        Some(value) => btf_symbols.insert(sym_idx, value),
+                        None => {
+                            error!("{} does not match any kernel type", name);
+                            return Err(ENOENT);
+                        }
+                    };
+                    continue;
+                }
                 for (map_idx, map_fd) in map_info.iter().enumerate() {
                     if &(map_fd.0) == name {
                         let base = map_fd_table.as_ptr() as usize;
@@ -113,25 +327,62 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
         return Err(ENOENT);
     }
 
-    // relocate maps
+    // relocate maps, kernel types and global data
+    // calls are collected and fixed when the code of each program is put together
+    let mut calls = Vec::new();
     for sec_hdr in elf.section_iter() {
         if let Ok(ShType::Rel) = sec_hdr.get_type() {
             if let Ok(SectionData::Rel64(rel_entries)) = sec_hdr.get_data(&elf) {
                 let sec_name = sec_hdr.get_name(&elf).map_or(Err(EINVAL), |v| Ok(v))?;
                 let target_sec_name = &sec_name[4..]; // ".relXXX"
                 let target_sec_hdr = elf.find_section_by_name(target_sec_name).ok_or(ENOENT)?;
+                // only code is relocated, debug info and BTF are not used
+                if target_sec_hdr.flags() & SHF_EXECINSTR == 0 {
+                    if is_global_data_section(target_sec_name) {
+                        // pointers stored in global variables
+                        error!("bpf prog relocations in {} are not supported", target_sec_name);
+                        return Err(EINVAL);
+                    }
+                    continue;
+                }
                 let base = target_sec_hdr.raw_data(&elf).as_ptr() as usize;
+                let target_sec_idx = sec_hdr.info() as usize;
 
                 for rel in rel_entries {
                     let offset = rel.get_offset() as usize;
                     let sym_idx = rel.get_symbol_table_index() as usize;
                     let rel_type = rel.get_type();
 
+                    if let Some(&(func_sec, func_offset)) = func_symbols.get(&sym_idx) {
+                        if rel_type != R_BPF_64_32 {
+                            error!("bpf prog relocation at {:x} refers to code", offset);
+                            return Err(EINVAL);
+                        }
+                        // imm holds the offset from the symbol in instructions, minus one
+                        let imm = unsafe { *((base + offset + 4) as *const i32) };
+                        let target = (func_offset as isize + (imm as isize + 1) * 8) as usize;
+                        calls.push(CallReloc {
+                            sec: target_sec_idx,
+                            pc: offset / 8,
+                            target_sec: func_sec,
+                            target: target / 8,
+                        });
+                        continue;
+                    }
+
                     let relocated_addr: usize;
                     if let Some(&addr) = map_symbols.get(&sym_idx) {
                         relocated_addr = addr;
+                    } else if let Some(&value) = btf_symbols.get(&sym_idx) {
+                        // LD_IMM64 of the symbol address becomes the offset or size itself
+                        relocated_addr = value;
+                    } else if let Some(&addr) = data_symbols.get(&sym_idx) {
+                        // imm holds the offset from the symbol, e.g. into .rodata for strings
+                        let imm = unsafe { *((base + offset + 4) as *const u32) };
+                        relocated_addr = addr + imm as usize;
                     } else {
-                        continue;
+                        error!("bpf prog relocation at {:x} refers to unknown symbol {}", offset, sym_idx);
+                        return Err(EINVAL);
                     }
                     trace!("bpf prog relocate entry idx: {} offset:{:x} type:{:?} to addr:{:x}", sym_idx, offset, rel_type, relocated_addr);
 
@@ -158,43 +409,158 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
                                 *p2 = v2;
                             }
                         },
-                        _ => (),
+                        _ => {
+                            error!("bpf prog relocation at {:x} has unsupported type {}", offset, rel_type);
+                            return Err(EINVAL);
+                        }
                     }
                 }
             }
         }
     }
 
-    // compile eBPF code
-    info!("before compile");
-    let sec_hdr = elf.find_section_by_name(".text").ok_or(ENOENT)?;
-    let code = sec_hdr.raw_data(&elf);
-    let bpf_insns = unsafe {
-        core::slice::from_raw_parts(
-            code.as_ptr() as *const u64,
-            code.len() / core::mem::size_of::<u64>(),
-        )
+    // every section other than .text is a program, .text only holds the functions they call.
+    // objects without program sections are one program in .text
+    let mut prog_sections: Vec<usize> = code_sections
+        .keys()
+        .copied()
+        .filter(|&idx| Some(idx) != text_idx)
+        .collect();
+    if prog_sections.is_empty() {
+        prog_sections.extend(text_idx);
+    }
+    if prog_sections.is_empty() {
+        return Err(ENOENT);
+    }
+    let text = match text_idx {
+        Some(idx) => section_insns(&elf, idx),
+        None => Vec::new(),
     };
-    let mut jit_ctx = compile::JitContext::new(bpf_insns);
+
+    let map_fd_table = Arc::new(map_fd_table);
+    let data_maps = match data_maps.0.is_empty() {
+        true => None,
+        false => Some(Arc::new(data_maps)),
+    };
+    let mut programs = Vec::new();
+    for sec_idx in prog_sections {
+        let name = code_sections[&sec_idx];
+        // .text is appended to the program so that calls into it can be inlined
+        let mut insns = if Some(sec_idx) == text_idx {
+            Vec::new()
+        } else {
+            section_insns(&elf, sec_idx)
+        };
+        let text_base = insns.len();
+        insns.extend_from_slice(&text);
+        let position = |sec: usize, pc: usize| {
+            if sec == sec_idx && Some(sec) != text_idx {
+                Some(pc)
+            } else if Some(sec) == text_idx {
+                Some(text_base + pc)
+            } else {
+                None
+            }
+        };
+        for call in calls.iter() {
+            if let Some(pc) = position(call.sec, call.pc) {
+                let target = position(call.target_sec, call.target).ok_or_else(|| {
+                    error!("bpf prog {} calls into another program", name);
+                    EINVAL
+                })?;
+                set_call_target(&mut insns, pc, target);
+            }
+        }
+        let func_starts = text_funcs.iter().map(|pc| text_base + pc).collect();
+        let insns = inline_calls(&insns, &func_starts)?;
+        programs.push(compile_program(
+            name,
+            &insns,
+            &HELPER_FN_TABLE,
+            Some(map_fd_table.clone()),
+            data_maps.clone(),
+        )?);
+    }
+
+    // only create objects once every program has compiled
+    let mut loaded = Vec::new();
+    for program in programs {
+        let fd = bpf_allocate_fd();
+        loaded.push((program.name.clone(), fd));
+        bpf_object_create_program(fd, program);
+    }
+    trace!("bpf prog loadex finished!");
+    Ok(loaded)
+}
+
+/// JIT relocated instructions into a program, calling helpers from `helper_fn_table`
+fn compile_program(
+    name: &str,
+    insns: &[u64],
+    helper_fn_table: &'static [BpfHelperFn],
+    map_fd_table: Option<Arc<Vec<u32>>>,
+    data_maps: Option<Arc<DataMaps>>,
+) -> Result<BpfProgram, BpfErrorCode> {
+    info!("before compile {}", name);
+    let ctx_access_size = ctx_access_size(insns)?;
+    let mut jit_ctx = compile::JitContext::new(insns);
     let helper_fn_table =
-        unsafe { core::mem::transmute::<&[BpfHelperFn], &[u64]>(&HELPER_FN_TABLE) };
-    compile::compile(&mut jit_ctx, helper_fn_table, 512);
+        unsafe { core::mem::transmute::<&[BpfHelperFn], &[u64]>(helper_fn_table) };
+    compile::compile(&mut jit_ctx, helper_fn_table, MAX_BPF_STACK as _);
 
     let compiled_code = jit_ctx.code; // partial move
 
-    let program = BpfProgram {
+    Ok(BpfProgram {
+        name: String::from(name),
         bpf_insns: None, // currently we do not store original BPF instructions
         jited_prog: Some(compiled_code),
-        map_fd_table: Some(map_fd_table),
-    };
+        map_fd_table,
+        data_maps,
+        run_cnt: AtomicU64::new(0),
+        run_time_ns: AtomicU64::new(0),
+        misses: AtomicU64::new(0),
+        ctx_access_size,
+    })
+}
 
+/// load a program the gdb stub generated, its instructions need no relocation.
+/// it is the only kind of program that can call HELPER_GDB_COLLECT
+/// # return value
+/// * fd of the program
+pub fn bpf_program_load_insns(name: &str, insns: &[u64]) -> BpfResult {
+    let program = compile_program(name, insns, &GDB_HELPER_FN_TABLE, None, None)?;
     let fd = bpf_allocate_fd();
     bpf_object_create_program(fd, program);
-    trace!("bpf prog loadex finished!");
     Ok(fd as usize)
 }
 
+/// a call to another bpf function, in instructions from the start of the sections
+struct CallReloc {
+    sec: usize,
+    pc: usize,
+    target_sec: usize,
+    target: usize,
+}
+
+/// copy the instructions of a section, the ELF data may not be aligned for u64
+fn section_insns(elf: &xmas_elf::ElfFile, sec_idx: usize) -> Vec<u64> {
+    let data = elf.section_header(sec_idx as u16).map_or(&[][..], |hdr| hdr.raw_data(elf));
+    data.chunks_exact(8)
+        .map(|insn| u64::from_le_bytes(insn.try_into().unwrap()))
+        .collect()
+}
+
+/// get info of the program with fd `prog_fd`
+pub fn bpf_program_get_info(prog_fd: u32) -> Result<BpfProgInfo, BpfErrorCode> {
+    let objs = BPF_OBJECTS.lock();
+    let program = objs.get(&prog_fd).ok_or(ENOENT)?.is_program().ok_or(EINVAL)?;
+    Ok(program.get_info())
+}
+
 #[cfg(not(target_arch = "riscv64"))]
-pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> SysResult {
+pub fn bpf_program_load_ex(
+    prog: &mut [u8],
+    map_info: &[(String, u32)],
+) -> Result<Vec<(String, u32)>, BpfErrorCode> {
     Err(EINVAL) // not supported
 }
@@ -0,0 +1,409 @@
+//! bpf-to-bpf calls
+//!
+//! the JIT only knows helper calls, so calls to other bpf functions (`BPF_PSEUDO_CALL`)
+//! are inlined before compiling. every call gets its own stack frame:
+//! r6-r9 of the caller are saved below the caller's frame and the callee's frame is below
+//! them. r10 is never written, like linux forbids: the r10-relative accesses of the callee
+//! are rebased onto its frame and `rX = r10` becomes `rX = r10; rX += -base`.
+//! `exit` of a callee jumps to the return sequence.
+//! like linux, the frames of a call chain share MAX_BPF_STACK bytes.
+//!
+//! the frames are sized from what the functions reach through r10 and the pointers
+//! derived from it, tracked along every path. a derived pointer may only be moved by
+//! `+= imm` or `-= imm` and copied, programs that spill it, return it or compute with it
+//! otherwise are rejected, as their frame could not be sized and would overlap the next.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use super::retcode::BpfErrorCode::{self, *};
+
+/// stack of the whole call chain, the JIT allocates this much
+pub const MAX_BPF_STACK: usize = 512;
+/// frames in a call chain, main program included
+const MAX_CALL_FRAMES: usize = 8;
+/// r6-r9 of the caller
+const CALLEE_SAVED_SIZE: usize = 4 * 8;
+
+const BPF_CALL: u8 = 0x85;
+const BPF_EXIT: u8 = 0x95;
+const BPF_JA: u8 = 0x05;
+const BPF_LD_IMM64: u8 = 0x18;
+const BPF_STX_DW: u8 = 0x7b;
+const BPF_LDX_DW: u8 = 0x79;
+const BPF_ADD64_IMM: u8 = 0x07;
+const BPF_SUB64_IMM: u8 = 0x17;
+const BPF_MOV64_REG: u8 = 0xbf;
+const BPF_PSEUDO_CALL: usize = 1;
+const BPF_REG_FP: usize = 10;
+
+fn opcode(insn: u64) -> u8 {
+    insn as u8
+}
+
+fn dst(insn: u64) -> usize {
+    ((insn >> 8) & 0xf) as usize
+}
+
+fn src(insn: u64) -> usize {
+    ((insn >> 12) & 0xf) as usize
+}
+
+fn off(insn: u64) -> i16 {
+    (insn >> 16) as i16
+}
+
+fn imm(insn: u64) -> i32 {
+    (insn >> 32) as i32
+}
+
+fn encode(opcode: u8, dst: usize, src: usize, off: i16, imm: i32) -> u64 {
+    opcode as u64
+        | (dst as u64) << 8
+        | (src as u64) << 12
+        | (off as u16 as u64) << 16
+        | (imm as u32 as u64) << 32
+}
+
+fn with_off(insn: u64, off: i16) -> u64 {
+    insn & !(0xffff << 16) | (off as u16 as u64) << 16
+}
+
+pub fn is_pseudo_call(insn: u64) -> bool {
+    opcode(insn) == BPF_CALL && src(insn) == BPF_PSEUDO_CALL
+}
+
+/// jumps with a pc-relative offset, calls and exit excluded
+fn is_jump(insn: u64) -> bool {
+    let class = opcode(insn) & 0x7;
+    (class == 0x05 || class == 0x06) && opcode(insn) != BPF_CALL && opcode(insn) != BPF_EXIT
+}
+
+/// target of a pseudo call at `pc`
+pub fn call_target(insns: &[u64], pc: usize) -> usize {
+    (pc as isize + 1 + imm(insns[pc]) as isize) as usize
+}
+
+/// point the pseudo call at `pc` to `target`
+pub fn set_call_target(insns: &mut [u64], pc: usize, target: usize) {
+    let rel = target as isize - pc as isize - 1;
+    insns[pc] = encode(BPF_CALL, 0, BPF_PSEUDO_CALL, 0, rel as i32);
+}
+
+/// register used as the base of a load or store, None for other instructions
+fn mem_base(insn: u64) -> Option<usize> {
+    match opcode(insn) & 0x7 {
+        0x01 => Some(src(insn)),        // ldx
+        0x02 | 0x03 => Some(dst(insn)), // st, stx
+        _ => None,
+    }
+}
+
+/// what a register holds, as far as the frames are concerned
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Reg {
+    Scalar,
+    /// a pointer into the frame, at this offset from r10
+    Fp(i64),
+    /// a pointer into the frame of a caller, passed as an argument. only moved up, its
+    /// offset was counted by the caller
+    CallerFp,
+    /// different things on different paths
+    Mixed,
+}
+
+impl Reg {
+    fn join(self, other: Reg) -> Reg {
+        if self == other {
+            self
+        } else {
+            Reg::Mixed
+        }
+    }
+
+    fn is_scalar(self) -> bool {
+        self == Reg::Scalar
+    }
+}
+
+type Regs = [Reg; 11];
+
+fn frame_error(msg: &str) -> BpfErrorCode {
+    error!("bpf prog {}, its stack frame can not be sized", msg);
+    EINVAL
+}
+
+/// the registers after `insn`, counting how deep it reaches into the frame in `depth`
+fn step(insn: u64, regs: &mut Regs, depth: &mut i64, is_main: bool) -> Result<(), BpfErrorCode> {
+    let (dst, src) = (dst(insn), src(insn));
+    match mem_base(insn).map(|reg| regs[reg]) {
+        Some(Reg::Fp(base)) => *depth = (*depth).max(-(base + off(insn) as i64)),
+        Some(Reg::CallerFp) if off(insn) < 0 => return Err(frame_error("reaches below a pointer")),
+        Some(Reg::Mixed) => return Err(frame_error("accesses memory through a mixed pointer")),
+        _ => {}
+    }
+    let class = opcode(insn) & 0x7;
+    match class {
+        // ld_imm64 and ldx
+        0x00 | 0x01 => regs[dst] = Reg::Scalar,
+        0x02 => {}
+        0x03 if !regs[src].is_scalar() => return Err(frame_error("spills a stack pointer")),
+        0x03 => {}
+        0x04 | 0x07 => {
+            let imm = imm(insn) as i64;
+            regs[dst] = match (opcode(insn), regs[dst]) {
+                (BPF_MOV64_REG, _) => regs[src],
+                // mov imm, the byte swaps only touch dst
+                (0xb7 | 0xb4, _) | (0xd4 | 0xdc, Reg::Scalar) => Reg::Scalar,
+                // mov32 reg
+                (0xbc, _) if regs[src].is_scalar() => Reg::Scalar,
+                (BPF_ADD64_IMM, Reg::Fp(base)) => Reg::Fp(base + imm),
+                (BPF_SUB64_IMM, Reg::Fp(base)) => Reg::Fp(base - imm),
+                (BPF_ADD64_IMM, Reg::CallerFp) if imm >= 0 => Reg::CallerFp,
+                (BPF_SUB64_IMM, Reg::CallerFp) if imm <= 0 => Reg::CallerFp,
+                (BPF_ADD64_IMM | BPF_SUB64_IMM, Reg::Scalar | Reg::Mixed) => regs[dst],
+                (op, reg) => {
+                    // reg-sourced ops read src
+                    if !reg.is_scalar() || (op & 0x08 != 0 && !regs[src].is_scalar()) {
+                        return Err(frame_error("computes with a stack pointer"));
+                    }
+                    Reg::Scalar
+                }
+            };
+            if let Reg::Fp(base) = regs[dst] {
+                *depth = (*depth).max(-base);
+            }
+        }
+        _ => match opcode(insn) {
+            BPF_CALL => regs[..=5].fill(Reg::Scalar),
+            BPF_EXIT if !is_main && !regs[0].is_scalar() => {
+                return Err(frame_error("returns a stack pointer"))
+            }
+            _ => {}
+        },
+    }
+    Ok(())
+}
+
+/// bytes of stack used by a function, from the r10-relative accesses and the pointers
+/// derived from r10 (`rX = r10; rX += imm`), e.g. buffers passed to helpers, followed
+/// along every path. `args` are r1-r5 at entry.
+/// also returns the registers before every instruction, None if it is never reached
+fn check_frame(
+    body: &[u64],
+    args: [Reg; 5],
+    is_main: bool,
+) -> Result<(usize, Vec<Option<Regs>>), BpfErrorCode> {
+    let mut entry = [Reg::Scalar; 11];
+    entry[1..=5].copy_from_slice(&args);
+    entry[BPF_REG_FP] = Reg::Fp(0);
+    let mut states: Vec<Option<Regs>> = alloc::vec![None; body.len()];
+    states[0] = Some(entry);
+    let mut work = alloc::vec![0];
+    let mut depth = 0i64;
+    while let Some(pc) = work.pop() {
+        let insn = body[pc];
+        let mut regs = states[pc].unwrap();
+        step(insn, &mut regs, &mut depth, is_main)?;
+        let next = if opcode(insn) == BPF_LD_IMM64 { pc + 2 } else { pc + 1 };
+        let targets = match opcode(insn) {
+            BPF_EXIT => [None, None],
+            BPF_JA => [Some(pc as isize + 1 + off(insn) as isize), None],
+            _ if is_jump(insn) => [Some(next as isize), Some(pc as isize + 1 + off(insn) as isize)],
+            _ => [Some(next as isize), None],
+        };
+        for target in targets.into_iter().flatten() {
+            if target < 0 || target as usize >= body.len() {
+                return Err(frame_error("leaves its function"));
+            }
+            let target = target as usize;
+            let joined = match states[target] {
+                Some(old) => {
+                    let mut joined = old;
+                    for (reg, new) in joined.iter_mut().zip(regs) {
+                        *reg = reg.join(new);
+                    }
+                    joined
+                }
+                None => regs,
+            };
+            if states[target] != Some(joined) {
+                states[target] = Some(joined);
+                work.push(target);
+            }
+        }
+    }
+    Ok(((depth.max(0) as usize + 7) / 8 * 8, states))
+}
+
+/// r1-r5 of a callee from the registers of its caller at the call
+fn callee_args(regs: Option<Regs>) -> [Reg; 5] {
+    let mut args = [Reg::Scalar; 5];
+    if let Some(regs) = regs {
+        for (arg, reg) in args.iter_mut().zip(&regs[1..=5]) {
+            *arg = match reg {
+                Reg::Fp(_) | Reg::CallerFp => Reg::CallerFp,
+                reg => *reg,
+            };
+        }
+    }
+    args
+}
+
+/// whether `insn` writes r10, which linux forbids and the frames rely on
+fn writes_fp(insn: u64) -> bool {
+    matches!(opcode(insn) & 0x7, 0x00 | 0x01 | 0x04 | 0x07) && dst(insn) == BPF_REG_FP
+}
+
+struct Inliner<'a> {
+    insns: &'a [u64],
+    /// entries of all functions, sorted
+    func_starts: BTreeSet<usize>,
+}
+
+impl Inliner<'_> {
+    /// [start, end) of the function at `start`
+    fn body(&self, start: usize) -> &[u64] {
+        let end = self
+            .func_starts
+            .range(start + 1..)
+            .next()
+            .copied()
+            .unwrap_or(self.insns.len());
+        &self.insns[start..end]
+    }
+
+    /// the function at `start` with its calls inlined
+    /// `stack` is the stack used by the callers, where the frame starts below r10,
+    /// `frames` their number, `args` what r1-r5 hold at entry
+    fn expand(
+        &self,
+        start: usize,
+        stack: usize,
+        frames: usize,
+        args: [Reg; 5],
+    ) -> Result<Vec<u64>, BpfErrorCode> {
+        if frames >= MAX_CALL_FRAMES {
+            // also catches recursion
+            error!("bpf prog calls are nested too deep");
+            return Err(E2BIG);
+        }
+        let body = self.body(start);
+        if body.iter().any(|&insn| writes_fp(insn)) {
+            error!("bpf prog writes r10");
+            return Err(EINVAL);
+        }
+        let is_main = frames == 0;
+        let (frame, states) = check_frame(body, args, is_main)?;
+        if stack + frame > MAX_BPF_STACK {
+            error!("bpf prog call chain needs more than {} bytes of stack", MAX_BPF_STACK);
+            return Err(E2BIG);
+        }
+
+        // expand calls first so that jumps over them can be fixed
+        let mut callees = Vec::new();
+        for pc in 0..body.len() {
+            if is_pseudo_call(body[pc]) {
+                let target = call_target(self.insns, start + pc);
+                if !self.func_starts.contains(&target) {
+                    return Err(EINVAL);
+                }
+                let callee_stack = stack + frame + CALLEE_SAVED_SIZE;
+                let args = callee_args(states[pc]);
+                callees.push(self.expand(target, callee_stack, frames + 1, args)?);
+            }
+        }
+
+        // new position of every instruction of the body
+        let mut pos = Vec::with_capacity(body.len() + 1);
+        let mut len = 0;
+        let mut callee_iter = callees.iter();
+        for &insn in body {
+            pos.push(len);
+            len += if is_pseudo_call(insn) {
+                callee_iter.next().unwrap().len() + 8
+            } else if stack > 0 && opcode(insn) == BPF_MOV64_REG && src(insn) == BPF_REG_FP {
+                2
+            } else {
+                1
+            };
+        }
+        pos.push(len);
+
+        let save_off = |reg: usize| to_off(-((stack + frame + (reg - 5) * 8) as isize));
+        let mut out = Vec::with_capacity(len);
+        let mut callee_iter = callees.into_iter();
+        let mut pc = 0;
+        while pc < body.len() {
+            let insn = body[pc];
+            if is_pseudo_call(insn) {
+                // save r6-r9 below our frame, the callee's frame is below them
+                for reg in 6..=9 {
+                    out.push(encode(BPF_STX_DW, BPF_REG_FP, reg, save_off(reg)?, 0));
+                }
+                out.extend(callee_iter.next().unwrap());
+                for reg in 6..=9 {
+                    out.push(encode(BPF_LDX_DW, reg, BPF_REG_FP, save_off(reg)?, 0));
+                }
+            } else if opcode(insn) == BPF_EXIT && !is_main {
+                // return to the caller, which restores the registers after our body
+                let rel = len as isize - out.len() as isize - 1;
+                out.push(encode(BPF_JA, 0, 0, to_off(rel)?, 0));
+            } else if is_jump(insn) {
+                let target = (pc as isize + 1 + off(insn) as isize) as usize;
+                if target > body.len() {
+                    return Err(EINVAL);
+                }
+                let rel = pos[target] as isize - out.len() as isize - 1;
+                out.push(with_off(insn, to_off(rel)?));
+            } else if opcode(insn) == BPF_LD_IMM64 && pc + 1 < body.len() {
+                out.push(insn);
+                out.push(body[pc + 1]);
+                pc += 1;
+            } else if stack > 0 && mem_base(insn) == Some(BPF_REG_FP) {
+                // rebase onto our frame
+                let rebased = to_off(off(insn) as isize - stack as isize)?;
+                out.push(with_off(insn, rebased));
+            } else if stack > 0 && opcode(insn) == BPF_MOV64_REG && src(insn) == BPF_REG_FP {
+                out.push(insn);
+                out.push(encode(BPF_ADD64_IMM, dst(insn), 0, 0, -(stack as i32)));
+            } else if stack > 0 && src(insn) == BPF_REG_FP && opcode(insn) & 0x7 != 0x05 {
+                // any other use of r10 would see the caller's frame
+                error!("bpf prog function uses r10 as a value");
+                return Err(EINVAL);
+            } else {
+                out.push(insn);
+            }
+            pc += 1;
+        }
+        Ok(out)
+    }
+}
+
+fn to_off(rel: isize) -> Result<i16, BpfErrorCode> {
+    i16::try_from(rel).map_err(|_| E2BIG)
+}
+
+/// inline every bpf-to-bpf call of the program starting at 0
+/// `func_starts` are the entries of the functions the program may call
+pub fn inline_calls(
+    insns: &[u64],
+    func_starts: &BTreeSet<usize>,
+) -> Result<Vec<u64>, BpfErrorCode> {
+    if !insns.iter().any(|&insn| is_pseudo_call(insn)) {
+        return Ok(insns.to_vec());
+    }
+    let mut starts = func_starts.clone();
+    starts.insert(0);
+    // calls resolved by the assembler have no symbol, their targets are entries too
+    for pc in 0..insns.len() {
+        if is_pseudo_call(insns[pc]) {
+            starts.insert(call_target(insns, pc));
+        }
+    }
+    let inliner = Inliner {
+        insns,
+        func_starts: starts,
+    };
+    inliner.expand(0, 0, 0, [Reg::Scalar; 5])
+}
@@ -0,0 +1,83 @@
+//! binary trace channel to the debugger on UART1
+//!
+//! programs send typed records with `bpf_trace_output`. records are queued and drained
+//! to UART1 in the background, `side-stub.py` decodes them. a record is, little endian:
+//!
+//! | sync 0xeb 0x9f | type u8 | cpu u8 | len u16 | seq u16 | pid u32 | time_ns u64 | payload | sum u16 |
+//!
+//! `sum` is the fletcher-16 of everything between the sync bytes and the sum. `seq` counts
+//! records sent, records lost to a full queue are reported by a DROPPED record.
+
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use super::osutil::{os_current_pid, os_current_time, os_get_current_cpu, os_trace_write};
+use super::retcode::BpfErrorCode::{self, *};
+
+pub const TRACE_SYNC: [u8; 2] = [0xeb, 0x9f];
+pub const TRACE_HEADER_SIZE: usize = 20;
+pub const TRACE_MAX_PAYLOAD: usize = 512;
+
+/// probed address u64, then struct pt_regs
+pub const TRACE_REGS: u8 = 1;
+/// address u64, then the bytes read there
+pub const TRACE_MEMORY: u8 = 2;
+/// utf-8 text
+pub const TRACE_MESSAGE: u8 = 3;
+/// u32 count of records lost since the last record
+pub const TRACE_DROPPED: u8 = 4;
+/// probed address u64, then argument registers a0, a1, ...
+pub const TRACE_ARGS: u8 = 5;
+
+static SEQ: AtomicU16 = AtomicU16::new(0);
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+fn fletcher16(bytes: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &byte in bytes {
+        a = (a + byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    b << 8 | a
+}
+
+/// frame and queue one record, no allocation so probe handlers may call it
+fn emit(ty: u8, payload: &[u8]) -> bool {
+    let mut record = [0u8; TRACE_HEADER_SIZE + TRACE_MAX_PAYLOAD + 2];
+    let end = TRACE_HEADER_SIZE + payload.len();
+    record[..2].copy_from_slice(&TRACE_SYNC);
+    record[2] = ty;
+    record[3] = os_get_current_cpu();
+    record[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    record[8..12].copy_from_slice(&(os_current_pid() as u32).to_le_bytes());
+    record[12..20].copy_from_slice(&(os_current_time() as u64).to_le_bytes());
+    record[TRACE_HEADER_SIZE..end].copy_from_slice(payload);
+    // seq only advances for records that are sent, so gaps mean corruption on the line
+    let seq = SEQ.load(Ordering::Relaxed);
+    record[6..8].copy_from_slice(&seq.to_le_bytes());
+    let sum = fletcher16(&record[2..end]);
+    record[end..end + 2].copy_from_slice(&sum.to_le_bytes());
+    if os_trace_write(&record[..end + 2]) {
+        SEQ.store(seq.wrapping_add(1), Ordering::Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
+/// send a record of type `ty`
+/// returns E2BIG if the payload is too large, EBUSY if the queue is full
+pub fn trace_output(ty: u8, payload: &[u8]) -> Result<(), BpfErrorCode> {
+    if payload.len() > TRACE_MAX_PAYLOAD {
+        return Err(E2BIG);
+    }
+    let dropped = DROPPED.load(Ordering::Relaxed);
+    if dropped > 0 && emit(TRACE_DROPPED, &dropped.to_le_bytes()) {
+        DROPPED.fetch_sub(dropped, Ordering::Relaxed);
+    }
+    if emit(ty, payload) {
+        Ok(())
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        Err(EBUSY)
+    }
+}
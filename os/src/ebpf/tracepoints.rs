@@ -2,9 +2,10 @@
 //!
 //! attach a program to hookpoints
 //!
-//! currently we only support Kprobe and Uprobe_syncfunc
+//! currently we only support Kprobe, Uprobe_syncfunc, static kernel tracepoints
+//! and raw syscall tracepoints
 
-use crate::probe::{arch::trapframe::TrapFrame, kprobes::unregister_kprobe};
+use crate::probe::arch::trapframe::TrapFrame;
 use alloc::string::{ToString, String};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -13,7 +14,6 @@ use lazy_static::lazy_static;
 use ruprobes::{uprobe_register, ProbePlace, ProbeType}; //todo where is unregister?
 use spin::Mutex as spin_Mutex;
 
-use lock::Mutex;
 use trapframe::{TrapFrame as UprobeCrateTrapframe, UserContext,GeneralRegs};
 
 use super::{
@@ -23,6 +23,19 @@ use super::{
     *,
 };
 use crate::probe::{register_kprobe, register_kretprobe, KProbeArgs, KRetProbeArgs};
+use crate::probe::{unregister_kprobe, unregister_kretprobe, unregister_tracepoint};
+use crate::probe::{register_tracepoint, tracepoint_index, TracepointArgs};
+use crate::probe::tracepoint::{SysEnterCtx, SysExitCtx, TracepointHandlerFn};
+use crate::probe::ftrace::{register_fentry, unregister_fentry, FentryCtx};
+use crate::probe::watchpoints::{WATCH_LOAD, WATCH_STORE};
+use crate::probe::{register_watchpoint, unregister_watchpoint, WatchpointArgs};
+use super::osutil::{os_current_thread, os_current_time, os_get_current_cpu, os_symbol_to_addr};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::config::MAX_HARTS;
+use super::program::bpf_stats_enabled;
+use super::context::{ctx_abi, KProbeBPFContext, RawSyscallBPFContext, UProbeBPFContext};
+use super::ctx_abi::*;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -45,6 +58,12 @@ pub enum TracepointType {
     UProbe_SyncFunc,
     URetProbeEntry_SyncFunc,
     URetProbeExit_SyncFunc,
+    KTracepoint, // static tracepoint, token is the tracepoint id
+    RawSyscallEnter, // token is the syscall id filter
+    RawSyscallExit,
+    FEntry, // function entry traced by ftrace, token is the function address
+    Watchpoint, // stores to a range, token is the start address
+    WatchpointRw, // loads and stores to a range
 }
 
 use TracepointType::*;
@@ -64,8 +83,156 @@ impl Tracepoint {
 }
 
 lazy_static! {
-    static ref ATTACHED_PROGS: Mutex<BTreeMap<Tracepoint, Vec<Arc<BpfProgram>>>> =
-        Mutex::new(BTreeMap::new());
+    /// a spin mutex so that missed runs can try_lock it, attach and detach lock it
+    /// through lock_attached_progs
+    static ref ATTACHED_PROGS: spin_Mutex<AttachedProgs> = spin_Mutex::new(BTreeMap::new());
+}
+
+/// number of bpf programs running on each hart, a non-zero value means
+/// a program (or a helper it called) has hit another tracepoint.
+/// attach and detach also count while they hold ATTACHED_PROGS
+static BPF_PROG_ACTIVE: [AtomicUsize; MAX_HARTS] = {
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_HARTS]
+};
+
+/// probe registered for a tracepoint, released when its last program is detached
+#[derive(Clone, Copy, Debug)]
+enum ProbeHandle {
+    KProbe(usize),
+    /// registered by address, shared by the entry and exit tracepoints
+    KRetProbe(usize),
+    Tracepoint(usize),
+    Watchpoint(usize),
+    FEntry(usize),
+    /// ruprobes can not unregister, the uprobe stays and finds no program to run
+    UProbe,
+}
+
+impl ProbeHandle {
+    /// false if the probe is still registered
+    fn release(self) -> bool {
+        let released = match self {
+            ProbeHandle::KProbe(handle) => unregister_kprobe(handle).is_some(),
+            ProbeHandle::KRetProbe(addr) => unregister_kretprobe(addr).is_some(),
+            ProbeHandle::Tracepoint(handle) => unregister_tracepoint(handle).is_some(),
+            ProbeHandle::Watchpoint(handle) => unregister_watchpoint(handle),
+            ProbeHandle::FEntry(handle) => unregister_fentry(handle),
+            ProbeHandle::UProbe => true,
+        };
+        if !released {
+            warn!("failed to release {:?}", self);
+        }
+        released
+    }
+}
+
+/// programs attached to a tracepoint, run in attach order, and the probe behind them
+struct AttachedTracepoint {
+    programs: Vec<Arc<BpfProgram>>,
+    handle: ProbeHandle,
+}
+
+impl AttachedTracepoint {
+    fn new(handle: ProbeHandle) -> Self {
+        Self {
+            programs: Vec::new(),
+            handle,
+        }
+    }
+}
+
+type AttachedProgs = BTreeMap<Tracepoint, AttachedTracepoint>;
+
+/// ATTACHED_PROGS held by attach or detach. the hart counts as running programs
+/// meanwhile, so a probe hit under the lock is a miss instead of a deadlock
+struct AttachedProgsGuard {
+    map: Option<spin::MutexGuard<'static, AttachedProgs>>,
+    active: &'static AtomicUsize,
+}
+
+impl Deref for AttachedProgsGuard {
+    type Target = AttachedProgs;
+    fn deref(&self) -> &AttachedProgs {
+        self.map.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for AttachedProgsGuard {
+    fn deref_mut(&mut self) -> &mut AttachedProgs {
+        self.map.as_mut().unwrap()
+    }
+}
+
+impl Drop for AttachedProgsGuard {
+    fn drop(&mut self) {
+        // unlock before the hart stops counting as active
+        self.map = None;
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        let busy = BUSY_MISSES.swap(0, Ordering::Relaxed);
+        if busy > 0 {
+            warn!("{} locked runs skipped, the attached programs were busy", busy);
+        }
+    }
+}
+
+/// runs of run_attached_programs_locked skipped because ATTACHED_PROGS was held, their
+/// programs are unknown then. reported by the next attach or detach
+static BUSY_MISSES: AtomicUsize = AtomicUsize::new(0);
+
+fn lock_attached_progs() -> AttachedProgsGuard {
+    let active = &BPF_PROG_ACTIVE[os_get_current_cpu() as usize];
+    active.fetch_add(1, Ordering::Relaxed);
+    AttachedProgsGuard {
+        map: Some(ATTACHED_PROGS.lock()),
+        active,
+    }
+}
+
+const NO_OVERRIDE: spin_Mutex<Option<u64>> = spin_Mutex::new(None);
+/// return value requested by bpf_override_return in the programs being run on each hart
+static OVERRIDE_RC: [spin_Mutex<Option<u64>>; MAX_HARTS] = [NO_OVERRIDE; MAX_HARTS];
+/// override requested by a uprobe program on each hart, applied by the trap handler
+/// after ruprobes returns
+static UPROBE_OVERRIDE_RC: [spin_Mutex<Option<u64>>; MAX_HARTS] = [NO_OVERRIDE; MAX_HARTS];
+
+/// called by the bpf_override_return helper, the last request wins
+pub fn bpf_request_override(rc: u64) {
+    *OVERRIDE_RC[os_get_current_cpu() as usize].lock() = Some(rc);
+}
+
+/// mark a run of programs on this hart, None if the hart is already running programs
+/// or holds ATTACHED_PROGS. the attached programs of `tracepoint` then count a miss
+/// if the lock is free, it is busy when the hart holds it
+#[link_section = ".text.noprobe"]
+fn enter_programs(tracepoint: &Tracepoint) -> Option<&'static AtomicUsize> {
+    let active = &BPF_PROG_ACTIVE[os_get_current_cpu() as usize];
+    if active.fetch_add(1, Ordering::Relaxed) > 0 {
+        if let Some(map) = ATTACHED_PROGS.try_lock() {
+            for program in map.get(tracepoint).into_iter().flat_map(|tp| tp.programs.iter()) {
+                program.record_miss();
+            }
+        }
+        active.fetch_sub(1, Ordering::Relaxed);
+        return None;
+    }
+    Some(active)
+}
+
+/// run programs one by one, order is preserved
+#[link_section = ".text.noprobe"]
+fn run_programs(programs: &[Arc<BpfProgram>], ctx: *const u8) {
+    let stats = bpf_stats_enabled();
+    for program in programs.iter() {
+        if stats {
+            let start = os_current_time();
+            let _result = program.run(ctx);
+            program.record_run((os_current_time() - start) as u64);
+        } else {
+            let _result = program.run(ctx);
+        }
+        // error!("run resultadr: {}", result);
+    }
 }
 
 /// # run attached programs
@@ -76,76 +243,105 @@ lazy_static! {
 /// # prodecure
 /// * get the bpf program object by tracepoint.token
 /// * run them one by one, order is preserved
-fn run_attached_programs(tracepoint: &Tracepoint, ctx: *const u8) {
-    let map = ATTACHED_PROGS.lock();
-    let programs = map.get(tracepoint).unwrap();
-    for program in programs {
-        let _result = program.run(ctx);
-        // error!("run resultadr: {}", result);
-    }
+/// * nested runs on the same hart are skipped and counted as misses of the skipped programs,
+///   so are runs while the hart attaches or detaches
+/// * ATTACHED_PROGS is released before any program runs
+/// # return value
+/// * Some(rc) if any program called bpf_override_return
+#[link_section = ".text.noprobe"]
+fn run_attached_programs(tracepoint: &Tracepoint, ctx: *const u8) -> Option<u64> {
+    let active = enter_programs(tracepoint)?;
+    // not held by this hart, attach and detach count as active
+    let programs = ATTACHED_PROGS
+        .lock()
+        .get(tracepoint)
+        .map(|tp| tp.programs.clone())
+        .unwrap_or_default();
+    run_programs(&programs, ctx);
+    active.fetch_sub(1, Ordering::Relaxed);
+    OVERRIDE_RC[os_get_current_cpu() as usize].lock().take()
 }
 
-#[repr(C)]
-/// kProbe context are just registers, or Trapframe
-struct KProbeBPFContext {
-    ptype: usize,
-    paddr: usize,
-    tf: TrapFrame,
-}
-
-impl KProbeBPFContext {
-    pub fn new(tf: &TrapFrame, probed_addr: usize, t: usize) -> Self {
-        KProbeBPFContext {
-            ptype: t,
-            paddr: probed_addr,
-            tf: tf.clone(),
+/// like run_attached_programs, but the programs run with ATTACHED_PROGS held instead of
+/// being cloned out of it, for handlers that must not touch the heap (ftrace_handler).
+/// tracepoints hit by these programs are misses that are not counted, the lock is busy.
+/// the lock is only tried, its holder may be waiting for the caller (e.g. attach
+/// registering an fentry while ftrace_handler holds the sites), a busy lock is counted
+/// in BUSY_MISSES
+#[link_section = ".text.noprobe"]
+fn run_attached_programs_locked(tracepoint: &Tracepoint, ctx: *const u8) -> Option<u64> {
+    let active = enter_programs(tracepoint)?;
+    match ATTACHED_PROGS.try_lock() {
+        Some(map) => {
+            if let Some(attached) = map.get(tracepoint) {
+                run_programs(&attached.programs, ctx);
+            }
+        }
+        None => {
+            BUSY_MISSES.fetch_add(1, Ordering::Relaxed);
         }
     }
-
-    pub fn as_ptr(&self) -> *const u8 {
-        unsafe { core::mem::transmute(self) }
-    }
+    active.fetch_sub(1, Ordering::Relaxed);
+    OVERRIDE_RC[os_get_current_cpu() as usize].lock().take()
 }
 
-#[repr(C)]
-/// uProbe context are just registers, or Trapframe
-struct UProbeBPFContext {
-    ptype: usize,//0 is syncfunc
-    paddr: usize,
-    tf: TrapFrame,
-}
-
-impl UProbeBPFContext {
-    pub fn new(tf: &TrapFrame, probed_addr: usize, t: usize) -> Self {
-        UProbeBPFContext {
-            ptype: t,
-            paddr: probed_addr,
-            tf: tf.clone(),
-        }
-    }
+/// functions whose return value may be overridden by kprobe programs.
+/// they must return a plain value in a0 and have no side effects the caller depends on
+/// besides that value. only a0 is set, so functions returning in a0/a1, e.g. an
+/// Option<FrameTracker>, must not be listed
+fn override_allowed(addr: usize) -> bool {
+    use crate::syscall::fs::*;
+    [
+        sys_open as usize,
+        sys_close as usize,
+        sys_read as usize,
+        sys_write as usize,
+        sys_pipe as usize,
+        sys_dup as usize,
+    ]
+    .contains(&addr)
+}
 
-    pub fn as_ptr(&self) -> *const u8 {
-        unsafe { core::mem::transmute(self) }
-    }
+/// take the pending uprobe override, called after the uprobe trap is handled
+pub fn take_uprobe_override() -> Option<u64> {
+    UPROBE_OVERRIDE_RC[os_get_current_cpu() as usize]
+        .lock()
+        .take()
 }
 
+/// raw syscall tracepoint token that matches every syscall
+pub const RAW_SYSCALL_ANY: usize = usize::MAX;
+
 /// the handler function that passed to register kprobe
+#[link_section = ".text.noprobe"]
 fn kprobe_handler(tf: &mut TrapFrame, probed_addr: usize) -> isize {
     let tracepoint: Tracepoint = Tracepoint::new(KProbe, probed_addr);
-    let ctx = KProbeBPFContext::new(tf, probed_addr, 0);
+    let ctx = KProbeBPFContext::new(tf, probed_addr, PTYPE_KPROBE);
     info!("run attached progs!");
-    run_attached_programs(&tracepoint, ctx.as_ptr());
+    let rc = run_attached_programs(&tracepoint, ctx.as_ptr());
     info!("run attached progs exit!");
 
+    if let Some(rc) = rc {
+        if override_allowed(probed_addr) {
+            // return to the caller directly, as if the function returned rc
+            tf.x[10] = rc as usize;
+            tf.sepc = tf.x[1];
+            return 1; // pc is changed, tell kprobes to skip the probed instruction
+        }
+        warn!("bpf_override_return ignored, {:#x} is not overridable", probed_addr);
+    }
     0
 }
 
 
 fn uprobe_syncfunc_handler(tf: &mut trap_context_riscv::TrapContext, probed_addr: usize) {//tag: uprobe_handler
     let tracepoint:Tracepoint=Tracepoint::new(UProbe_SyncFunc, probed_addr);
-    let ctx: UProbeBPFContext = UProbeBPFContext::new(&tf,probed_addr,0);
+    let ctx: UProbeBPFContext = UProbeBPFContext::new(&tf,probed_addr,PTYPE_UPROBE_SYNCFUNC);
     info!("run attached progs in uprobe_syncfunc_handler!");
-    run_attached_programs(&tracepoint, ctx.as_ptr());
+    if let Some(rc) = run_attached_programs(&tracepoint, ctx.as_ptr()) {
+        // ruprobes sets sepc itself after this handler, so the skip is applied in trap_handler
+        *UPROBE_OVERRIDE_RC[os_get_current_cpu() as usize].lock() = Some(rc);
+    }
     info!("run attached progs in uprobe_syncfunc_handler exit!");
 }
 
@@ -195,18 +391,97 @@ fn uprobe_syncfunc_handler(tf: &mut trap_context_riscv::TrapContext, probed_addr
 //     }, sstatus: cx.sstatus.bits(), sepc: cx.sepc }
 // }
 
+/// the handler function that passed to register a static tracepoint
+/// ctx already points to the typed context of that tracepoint
+fn ktracepoint_handler(ctx: *const u8, index: usize) -> isize {
+    let tracepoint = Tracepoint::new(KTracepoint, index);
+    run_attached_programs(&tracepoint, ctx);
+    0
+}
+
+/// registered on `syscalls:sys_enter`, filter is the syscall id or RAW_SYSCALL_ANY
+fn raw_syscall_enter_handler(ctx: *const u8, filter: usize) -> isize {
+    let tp_ctx = unsafe { &*(ctx as *const SysEnterCtx) };
+    if filter != RAW_SYSCALL_ANY && filter != tp_ctx.id {
+        return 0;
+    }
+    let tracepoint = Tracepoint::new(RawSyscallEnter, filter);
+    let ctx = RawSyscallBPFContext::new(tp_ctx.id, tp_ctx.args, 0);
+    run_attached_programs(&tracepoint, ctx.as_ptr());
+    0
+}
+
+/// registered on `syscalls:sys_exit`, filter is the syscall id or RAW_SYSCALL_ANY
+fn raw_syscall_exit_handler(ctx: *const u8, filter: usize) -> isize {
+    let tp_ctx = unsafe { &*(ctx as *const SysExitCtx) };
+    if filter != RAW_SYSCALL_ANY && filter != tp_ctx.id {
+        return 0;
+    }
+    let tracepoint = Tracepoint::new(RawSyscallExit, filter);
+    let ctx = RawSyscallBPFContext::new(tp_ctx.id, tp_ctx.args, tp_ctx.ret);
+    run_attached_programs(&tracepoint, ctx.as_ptr());
+    0
+}
+
+/// registered on a watchpoint, paddr of the context is the accessed address
+#[link_section = ".text.noprobe"]
+fn watchpoint_handler(tf: &mut TrapFrame, hit_addr: usize, token: usize) -> isize {
+    let tp_type = if token & WATCHPOINT_RW_FLAG != 0 { WatchpointRw } else { Watchpoint };
+    let tracepoint = Tracepoint::new(tp_type, token & !WATCHPOINT_RW_FLAG);
+    let ctx = KProbeBPFContext::new(tf, hit_addr, PTYPE_WATCHPOINT);
+    run_attached_programs(&tracepoint, ctx.as_ptr());
+    0
+}
+
+/// bit in watchpoint user_data telling the tracepoint type, kernel addresses never use it
+const WATCHPOINT_RW_FLAG: usize = 1;
+/// watched length when none is given, one machine word
+const WATCHPOINT_DEFAULT_LEN: usize = 8;
+
+/// parse "0x<addr>" or "0x<addr>+<len>"
+fn parse_watch_range(range: &str) -> Result<(usize, usize), BpfErrorCode> {
+    let (addr, len) = match range.split_once('+') {
+        Some((addr, len)) => (addr, len.parse::<usize>().map_err(|_| EINVAL)?),
+        None => (range, WATCHPOINT_DEFAULT_LEN),
+    };
+    let addr = addr
+        .strip_prefix("0x")
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+        .ok_or(EINVAL)?;
+    Ok((addr, len))
+}
+
+/// registered with ftrace, ctx is passed to programs as-is
+/// runs in ftrace_handler, which must not allocate
+#[link_section = ".text.noprobe"]
+fn fentry_handler(ctx: &FentryCtx, addr: usize) -> isize {
+    let tracepoint = Tracepoint::new(FEntry, addr);
+    run_attached_programs_locked(&tracepoint, ctx as *const FentryCtx as *const u8);
+    0
+}
+
+/// parse "sys_enter@<syscall id>", no filter means RAW_SYSCALL_ANY
+fn parse_syscall_filter(name: &str) -> Result<usize, BpfErrorCode> {
+    match name.split_once('@') {
+        Some((_, id)) => id.parse::<usize>().map_err(|_| EINVAL),
+        None => Ok(RAW_SYSCALL_ANY),
+    }
+}
+
 /// unused
+#[link_section = ".text.noprobe"]
 fn kretprobe_entry_handler(tf: &mut TrapFrame, probed_addr: usize) -> isize {
     let tracepoint = Tracepoint::new(KRetProbeEntry, probed_addr);
-    let ctx = KProbeBPFContext::new(tf, probed_addr, 1);
+    let ctx = KProbeBPFContext::new(tf, probed_addr, PTYPE_KRETPROBE_ENTRY);
     run_attached_programs(&tracepoint, ctx.as_ptr());
     0
 }
 
 /// unused
+#[link_section = ".text.noprobe"]
 fn kretprobe_exit_handler(tf: &mut TrapFrame, probed_addr: usize) -> isize {
     let tracepoint = Tracepoint::new(KRetProbeExit, probed_addr);
-    let ctx = KProbeBPFContext::new(tf, probed_addr, 2);
+    let ctx = KProbeBPFContext::new(tf, probed_addr, PTYPE_KRETPROBE_EXIT);
     run_attached_programs(&tracepoint, ctx.as_ptr());
     0
 }
@@ -265,6 +540,26 @@ fn parse_tracepoint<'a>(
         tp_type = URetProbeEntry_SyncFunc;
     } else if type_str.eq_ignore_ascii_case("uretprobe_syncfunc@exit") {
         tp_type = URetProbeExit_SyncFunc;
+    } else if type_str.eq_ignore_ascii_case("tracepoint") {
+        // fn_name is the tracepoint name here, e.g. "tracepoint$sched:sched_switch"
+        tp_type = KTracepoint;
+    } else if type_str.eq_ignore_ascii_case("raw_syscalls") {
+        // fn_name is "sys_enter" or "sys_exit", optionally with a syscall filter like "sys_enter@64"
+        if fn_name.starts_with("sys_enter") {
+            tp_type = RawSyscallEnter;
+        } else if fn_name.starts_with("sys_exit") {
+            tp_type = RawSyscallExit;
+        } else {
+            return Err(EINVAL);
+        }
+    } else if type_str.eq_ignore_ascii_case("watchpoint") {
+        // fn_name is "0x<addr>" or "0x<addr>+<len>", fires on stores
+        tp_type = Watchpoint;
+    } else if type_str.eq_ignore_ascii_case("watchpoint_rw") {
+        tp_type = WatchpointRw;
+    } else if type_str.eq_ignore_ascii_case("fentry") {
+        // fn_name is the function address, no trap is taken on hit
+        tp_type = FEntry;
     } else {
         return Err(EINVAL);
     }
@@ -282,6 +577,7 @@ fn parse_tracepoint<'a>(
 /// # prodecure
 /// * get the bpf program object by prog_fd
 /// * get the tracepoint by target name
+/// * check that the program does not read past the context, best-effort (see ctx_access_size)
 /// * add program to tracepoint handlers
 ///  if it is the first time a program is attached to, register the kprobe
 /// # return value
@@ -302,29 +598,48 @@ pub fn bpf_program_attach(target: &str, prog_fd: u32) -> BpfResult {
     let (tp_type, addr_string, user_program_path) = parse_tracepoint(target)?;
     //let addr = resolve_symbol(&fn_name).ok_or(ENOENT)?;
     debug!("addr string is {:?}", addr_string);
-    let addr:usize = usize::from_str_radix(&addr_string[2..], 16).unwrap();
+    let addr: usize = match tp_type {
+        KTracepoint => tracepoint_index(&addr_string).ok_or(ENOENT)?,
+        RawSyscallEnter | RawSyscallExit => parse_syscall_filter(&addr_string)?,
+        Watchpoint | WatchpointRw => parse_watch_range(&addr_string)?.0,
+        _ => addr_string
+            .strip_prefix("0x")
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .or_else(|| os_symbol_to_addr(&addr_string))
+            .ok_or(EINVAL)?,
+    };
     //let addr = addr_string.parse::<usize>().unwrap();
 
     let tracepoint = Tracepoint::new(tp_type, addr);
+    // the program must not read past the context of this tracepoint, as far as
+    // ctx_access_size can tell
+    let ctx_size = ctx_abi(&tracepoint).map_or(0, |abi| abi.size());
+    if program.ctx_access_size() > ctx_size {
+        warn!(
+            "bpf prog reads {} bytes of ctx, only {} are available",
+            program.ctx_access_size(),
+            ctx_size
+        );
+        return Err(EACCES);
+    }
 
-    let mut map = ATTACHED_PROGS.lock();
-    if let Some(programs) = map.get_mut(&tracepoint) {
-        for other_prog in programs.iter() {
+    let mut map = lock_attached_progs();
+    if let Some(attached) = map.get_mut(&tracepoint) {
+        for other_prog in attached.programs.iter() {
             if Arc::ptr_eq(&program, other_prog) {
                 return Err(EAGAIN);
             }
         }
-        programs.push(program);
+        attached.programs.push(program);
     } else {
-        match tp_type {
+        let handle = match tp_type {
             KProbe => {
                 let args = KProbeArgs {
                     pre_handler: Arc::new(kprobe_handler),
                     post_handler: None,
                     user_data: addr,
                 };
-                let _ = register_kprobe(addr, args).ok_or(EINVAL)?;
-                map.insert(tracepoint, vec![program]);
+                ProbeHandle::KProbe(register_kprobe(addr, args).ok_or(EINVAL)?)
             }
             KRetProbeEntry | KRetProbeExit => {
                 let args = KRetProbeArgs {
@@ -333,27 +648,62 @@ pub fn bpf_program_attach(target: &str, prog_fd: u32) -> BpfResult {
                     limit: None,
                     user_data: addr,
                 };
-                let _ = register_kretprobe(addr, args).ok_or(EINVAL)?;
-
-                let dual_tp: Tracepoint;
-                if tp_type == KRetProbeEntry {
-                    dual_tp = Tracepoint::new(KRetProbeExit, addr);
-                } else {
-                    dual_tp = Tracepoint::new(KRetProbeEntry, addr);
-                }
-                map.insert(tracepoint, vec![program]);
-                map.insert(dual_tp, vec![]);
+                register_kretprobe(addr, args).ok_or(EINVAL)?;
+                let dual_tp = kretprobe_dual(&tracepoint).unwrap();
+                map.insert(dual_tp, AttachedTracepoint::new(ProbeHandle::KRetProbe(addr)));
+                ProbeHandle::KRetProbe(addr)
             }
             UProbe_Insn => todo!(),
             URetProbeEntry_Insn => todo!(),
             URetProbeExit_Insn => todo!(),
             UProbe_SyncFunc => { //tag: uprobe_handler
                 uprobe_register(user_program_path.unwrap().to_string(), addr,  Arc::new(spin_Mutex::new(uprobe_syncfunc_handler)),None, ruprobes::ProbeType::SyncFunc);  
-                map.insert(tracepoint, vec![program]);
+                ProbeHandle::UProbe
             }
             URetProbeEntry_SyncFunc => todo!(),
             URetProbeExit_SyncFunc => todo!(),
-        }
+            KTracepoint => {
+                let args = TracepointArgs {
+                    handler: Arc::new(ktracepoint_handler),
+                    user_data: addr,
+                };
+                ProbeHandle::Tracepoint(register_tracepoint(addr, args).ok_or(EINVAL)?)
+            }
+            RawSyscallEnter | RawSyscallExit => {
+                // every syscall filter gets its own handler on the sys_enter/sys_exit tracepoint
+                let (tp_name, handler): (&str, TracepointHandlerFn) = if tp_type == RawSyscallEnter {
+                    ("syscalls:sys_enter", raw_syscall_enter_handler)
+                } else {
+                    ("syscalls:sys_exit", raw_syscall_exit_handler)
+                };
+                let index = tracepoint_index(tp_name).unwrap();
+                let args = TracepointArgs {
+                    handler: Arc::new(handler),
+                    user_data: addr,
+                };
+                ProbeHandle::Tracepoint(register_tracepoint(index, args).ok_or(EINVAL)?)
+            }
+            Watchpoint | WatchpointRw => {
+                let (_, len) = parse_watch_range(&addr_string)?;
+                let (access, token) = if tp_type == WatchpointRw {
+                    (WATCH_LOAD | WATCH_STORE, addr | WATCHPOINT_RW_FLAG)
+                } else {
+                    (WATCH_STORE, addr)
+                };
+                let args = WatchpointArgs {
+                    handler: Arc::new(watchpoint_handler),
+                    access,
+                    user_data: token,
+                };
+                ProbeHandle::Watchpoint(register_watchpoint(addr, len, args).ok_or(EINVAL)?)
+            }
+            FEntry => {
+                ProbeHandle::FEntry(register_fentry(addr, Arc::new(fentry_handler), addr).ok_or(EINVAL)?)
+            }
+        };
+        let mut attached = AttachedTracepoint::new(handle);
+        attached.programs.push(program);
+        map.insert(tracepoint, attached);
     }
     // trace!(
     //     "bpf prog attached! tracepoint symbol:{} addr: {:x}",
@@ -367,38 +717,72 @@ pub fn bpf_program_attach(target: &str, prog_fd: u32) -> BpfResult {
     Ok(0)
 }
 
+/// the other end of a kretprobe tracepoint, both ends share one registration
+fn kretprobe_dual(tracepoint: &Tracepoint) -> Option<Tracepoint> {
+    match tracepoint.tp_type {
+        KRetProbeEntry => Some(Tracepoint::new(KRetProbeExit, tracepoint.token)),
+        KRetProbeExit => Some(Tracepoint::new(KRetProbeEntry, tracepoint.token)),
+        _ => None,
+    }
+}
+
 /// # bpf_program_detach
-/// detach a program from hookpoint
+/// detach a program from all of its hookpoints and unload it
 /// # arguments
 /// * prog_fd - the fd of the bpf program
 /// # prodecure
-/// * get the bpf program object by prog_fd
-/// * remove program from tracepoint handlers
+/// * remove the bpf program object of prog_fd, unless it is a map
+/// * remove program from tracepoint handlers, it may be attached to none
+/// * release the probes that have no program left, a kretprobe once both ends have none.
+///   a probe that fails to release is kept, a later detach releases it again
 /// # return value
 /// * OK(0) on success
+/// * ENOENT if prog_fd does not exist, EINVAL if it is not a program
+/// * EBUSY if a probe is still registered, the program is detached and unloaded anyway
 pub fn bpf_program_detach(prog_fd: u32) -> BpfResult {
-    if let Some(prog) = bpf_object_remove(prog_fd) {
+    let prog = {
+        let mut objs = BPF_OBJECTS.lock();
+        match objs.get(&prog_fd) {
+            Some(obj) if obj.is_program().is_some() => objs.remove(&prog_fd),
+            // maps are not removed by detach
+            Some(_) => return Err(EINVAL),
+            None => None,
+        }
+    };
+    if let Some(prog) = prog {
         let prog = prog.is_program().unwrap();
-        let mut map = ATTACHED_PROGS.lock();
-        let mut t = Tracepoint::new(TracepointType::KProbe, 0);
-        let mut id = 0;
-        for (k, v) in map.iter() {
-            let mut find = false;
-            for (i, p) in v.iter().enumerate() {
-                if Arc::ptr_eq(p, &prog) {
-                    t = k.clone();
-                    id = i;
-                    find = true;
-                }
+        let mut map = lock_attached_progs();
+        for attached in map.values_mut() {
+            attached.programs.retain(|p| !Arc::ptr_eq(p, prog));
+        }
+        let is_idle = |tp: &Tracepoint| map.get(tp).map_or(true, |attached| attached.programs.is_empty());
+        let idle: Vec<Tracepoint> = map
+            .keys()
+            .filter(|tp| is_idle(tp) && kretprobe_dual(tp).map_or(true, |dual| is_idle(&dual)))
+            .cloned()
+            .collect();
+        let mut handles = Vec::new();
+        for tp in idle {
+            let attached = map.remove(&tp).unwrap();
+            // both ends of a kretprobe hold its handle, release it once
+            if tp.tp_type != KRetProbeExit {
+                handles.push((tp, attached.handle));
             }
-            if find {
-                break;
+        }
+        // probes take their own locks, which their handlers may hold when they run programs
+        drop(map);
+        let kept: Vec<(Tracepoint, ProbeHandle)> =
+            handles.into_iter().filter(|(_, handle)| !handle.release()).collect();
+        if kept.is_empty() {
+            return Ok(0);
+        }
+        let mut map = lock_attached_progs();
+        for (tp, handle) in kept {
+            for tp in core::iter::once(tp).chain(kretprobe_dual(&tp)) {
+                map.entry(tp).or_insert_with(|| AttachedTracepoint::new(handle));
             }
         }
-        let v = map.get_mut(&t).unwrap();
-        v.remove(id);
-        //unregister_kprobe(t.token);
-        Ok(0)
+        Err(EBUSY)
     } else {
         Err(ENOENT)
     }
@@ -1,8 +1,10 @@
 mod inode;
 mod pipe;
+mod procfs;
 mod stdio;
 
 use crate::mm::UserBuffer;
+use alloc::sync::Arc;
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
@@ -13,4 +15,14 @@ pub trait File: Send + Sync {
 
 pub use inode::{list_apps, open_file, OSInode, OpenFlags, ROOT_INODE};
 pub use pipe::{make_pipe, Pipe};
+pub use procfs::open_proc;
 pub use stdio::{Stdin, Stdout};
+
+/// open a file of procfs if `path` is below /proc, of easy-fs otherwise
+pub fn open(path: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    if procfs::is_proc_path(path) {
+        open_proc(path, flags).map(|file| file as Arc<dyn File + Send + Sync>)
+    } else {
+        open_file(path, flags).map(|inode| inode as Arc<dyn File + Send + Sync>)
+    }
+}
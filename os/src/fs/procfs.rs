@@ -0,0 +1,268 @@
+//! read-only procfs, mounted at /proc next to easy-fs
+//!
+//! a file is generated when it is opened, reading it gives the kernel state of that time.
+//! directories read as their entries, one per line:
+//! * `/proc`: pids, then `meminfo`, `kprobes` and `bpf`
+//! * `/proc/<pid>`: `status`, `maps` and `fd`
+//! * `/proc/<pid>/status`: `Key:\tvalue` lines and a line per thread
+//! * `/proc/<pid>/maps`: `start-end perm type frames` per area of the address space
+//! * `/proc/<pid>/fd`: `fd perm` per open file
+//! * `/proc/meminfo`: frames and kernel heap
+//! * `/proc/kprobes`: `addr symbol+offset handlers` per kprobe
+//! * `/proc/bpf`: a line per eBPF program and map
+
+use super::{File, OpenFlags};
+use crate::ebpf::bpf_object_next_fd;
+use crate::ebpf::consts::{BPF_MAP_TYPE_ARRAY, BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_PROG_ARRAY};
+use crate::ebpf::map::bpf_map_get_info;
+use crate::ebpf::program::bpf_program_get_info;
+use crate::ksyms::addr_to_symbol;
+use crate::mm::{frame_stats, heap_stats, MapPermission, MapType, UserBuffer};
+use crate::probe::kprobes::kprobe_list;
+use crate::sync::UPIntrFreeCell;
+use crate::task::{pid2process, ProcessControlBlock, ProcessState, TaskStatus, PID2PCB};
+use crate::timer::ticks_to_ms;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+const PROC_ROOT: &str = "/proc";
+const PAGE_KB: usize = crate::config::PAGE_SIZE / 1024;
+
+pub struct ProcFile {
+    content: Vec<u8>,
+    offset: UPIntrFreeCell<usize>,
+}
+
+impl ProcFile {
+    fn new(content: String) -> Self {
+        Self {
+            content: content.into_bytes(),
+            offset: unsafe { UPIntrFreeCell::new(0) },
+        }
+    }
+}
+
+impl File for ProcFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let rest = &self.content[*offset..];
+            let read_size = rest.len().min(slice.len());
+            if read_size == 0 {
+                break;
+            }
+            slice[..read_size].copy_from_slice(&rest[..read_size]);
+            *offset += read_size;
+            total_read_size += read_size;
+        }
+        total_read_size
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
+
+/// whether `path` is /proc or below it
+pub fn is_proc_path(path: &str) -> bool {
+    path.strip_prefix(PROC_ROOT)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// open a file below /proc, only for reading
+pub fn open_proc(path: &str, flags: OpenFlags) -> Option<Arc<ProcFile>> {
+    if flags.read_write() != (true, false) || !is_proc_path(path) {
+        return None;
+    }
+    let parts: Vec<&str> = path[PROC_ROOT.len()..]
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect();
+    let content = match parts.as_slice() {
+        [] => root_entries(),
+        ["meminfo"] => meminfo(),
+        ["kprobes"] => kprobes(),
+        ["bpf"] => bpf(),
+        [pid] => {
+            process(pid)?;
+            String::from("status\nmaps\nfd\n")
+        }
+        [pid, "status"] => status(&process(pid)?),
+        [pid, "maps"] => maps(&process(pid)?),
+        [pid, "fd"] => fds(&process(pid)?),
+        _ => return None,
+    };
+    Some(Arc::new(ProcFile::new(content)))
+}
+
+fn process(pid: &str) -> Option<Arc<ProcessControlBlock>> {
+    pid2process(pid.parse().ok()?)
+}
+
+fn root_entries() -> String {
+    let mut s = String::new();
+    for pid in PID2PCB.exclusive_access().keys() {
+        let _ = writeln!(s, "{}", pid);
+    }
+    s.push_str("meminfo\nkprobes\nbpf\n");
+    s
+}
+
+fn status(process: &ProcessControlBlock) -> String {
+    let tasks: Vec<_> = process
+        .inner_exclusive_access()
+        .tasks
+        .iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let mut threads = String::new();
+    for task in tasks.iter() {
+        let inner = task.inner_exclusive_access();
+        let state = match inner.task_status {
+            TaskStatus::Ready => "ready",
+            TaskStatus::Running => "running",
+            TaskStatus::Blocked => "blocked",
+        };
+        if let Some(res) = inner.res.as_ref() {
+            let _ = writeln!(threads, "Thread {}:\t{}", res.tid, state);
+        }
+    }
+    let state = match process.state() {
+        ProcessState::Running => "R (running)",
+        ProcessState::Sleeping => "S (sleeping)",
+        ProcessState::Zombie => "Z (zombie)",
+    };
+    let ppid = process.getppid();
+    let inner = process.inner_exclusive_access();
+    let mut s = String::new();
+    let _ = writeln!(s, "Name:\t{}", inner.path);
+    let _ = writeln!(s, "State:\t{}", state);
+    let _ = writeln!(s, "Pid:\t{}", process.getpid());
+    let _ = writeln!(s, "PPid:\t{}", ppid);
+    let _ = writeln!(s, "Threads:\t{}", tasks.len());
+    let _ = writeln!(s, "VmRSS:\t{} kB", inner.resident_pages() * PAGE_KB);
+    let _ = writeln!(s, "CpuTime:\t{} ms", ticks_to_ms(inner.cpu_time));
+    let _ = writeln!(s, "Fds:\t{}", inner.fd_table.iter().flatten().count());
+    let _ = writeln!(s, "SigPnd:\t{:#x}", inner.signals.bits());
+    if inner.is_zombie {
+        let _ = writeln!(s, "ExitCode:\t{}", inner.exit_code);
+    }
+    s + &threads
+}
+
+fn maps(process: &ProcessControlBlock) -> String {
+    let inner = process.inner_exclusive_access();
+    let mut s = String::new();
+    for area in inner.memory_set.areas() {
+        let perm = area.map_perm();
+        let flag = |bit: MapPermission, ch: char| if perm.contains(bit) { ch } else { '-' };
+        let map_type = match area.map_type() {
+            MapType::Identical => "identical",
+            MapType::Framed => "framed",
+            MapType::Linear(_) => "linear",
+        };
+        let _ = writeln!(
+            s,
+            "{:016x}-{:016x} {}{}{}{} {} {}",
+            area.start_va().0,
+            area.end_va().0,
+            flag(MapPermission::R, 'r'),
+            flag(MapPermission::W, 'w'),
+            flag(MapPermission::X, 'x'),
+            flag(MapPermission::U, 'u'),
+            map_type,
+            area.frame_count()
+        );
+    }
+    s
+}
+
+fn fds(process: &ProcessControlBlock) -> String {
+    let inner = process.inner_exclusive_access();
+    let mut s = String::new();
+    for (fd, file) in inner.fd_table.iter().enumerate() {
+        if let Some(file) = file {
+            let readable = if file.readable() { 'r' } else { '-' };
+            let writable = if file.writable() { 'w' } else { '-' };
+            let _ = writeln!(s, "{} {}{}", fd, readable, writable);
+        }
+    }
+    s
+}
+
+fn meminfo() -> String {
+    let (total_frames, free_frames) = frame_stats();
+    let (heap_total, heap_used) = heap_stats();
+    let mut s = String::new();
+    let _ = writeln!(s, "MemTotal:\t{} kB", total_frames * PAGE_KB);
+    let _ = writeln!(s, "MemFree:\t{} kB", free_frames * PAGE_KB);
+    let _ = writeln!(s, "FramesTotal:\t{}", total_frames);
+    let _ = writeln!(s, "FramesFree:\t{}", free_frames);
+    let _ = writeln!(s, "HeapTotal:\t{} kB", heap_total / 1024);
+    let _ = writeln!(s, "HeapUsed:\t{} kB", heap_used / 1024);
+    s
+}
+
+fn kprobes() -> String {
+    let mut s = String::new();
+    for (addr, handlers) in kprobe_list() {
+        let _ = match addr_to_symbol(addr) {
+            Some((name, offset)) => {
+                writeln!(s, "{:016x} {}+{:#x} {}", addr, name, offset, handlers)
+            }
+            None => writeln!(s, "{:016x} ? {}", addr, handlers),
+        };
+    }
+    s
+}
+
+fn bpf() -> String {
+    let mut s = String::new();
+    let mut fd = 0;
+    while let Some(next) = bpf_object_next_fd(fd, false) {
+        fd = next;
+        if let Ok(info) = bpf_program_get_info(fd) {
+            let name_len = info
+                .name
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(info.name.len());
+            let _ = writeln!(
+                s,
+                "prog {:#x} name {} jited {} run_cnt {} run_time_ns {}",
+                fd,
+                core::str::from_utf8(&info.name[..name_len]).unwrap_or("?"),
+                info.jited_prog_len,
+                info.run_cnt,
+                info.run_time_ns
+            );
+        }
+    }
+    let mut fd = 0;
+    while let Some(next) = bpf_object_next_fd(fd, true) {
+        fd = next;
+        if let Some(info) = bpf_map_get_info(fd) {
+            let map_type = match info.map_type {
+                BPF_MAP_TYPE_HASH => "hash",
+                BPF_MAP_TYPE_ARRAY => "array",
+                BPF_MAP_TYPE_PROG_ARRAY => "prog_array",
+                _ => "?",
+            };
+            let _ = writeln!(
+                s,
+                "map {:#x} type {} key {} value {} max_entries {}",
+                fd, map_type, info.key_size, info.value_size, info.max_entries
+            );
+        }
+    }
+    s
+}
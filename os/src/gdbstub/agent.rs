@@ -0,0 +1,168 @@
+//! gdb agent expressions, the bytecode of `collect` actions that are not plain registers
+//! or memory ranges. only integer operations are supported, trace state variables are not.
+//! see "Agent Expressions" in the gdb manual for the opcodes.
+
+use alloc::vec::Vec;
+
+const MAX_STACK: usize = 64;
+/// bound on executed opcodes, expressions may loop with goto
+const MAX_STEPS: usize = 4096;
+
+/// what an expression runs against
+pub trait AgentTarget {
+    fn reg(&self, index: usize) -> Option<usize>;
+    fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>>;
+    /// record `len` bytes at `addr` in the trace frame
+    fn trace(&mut self, addr: usize, len: usize);
+}
+
+fn sign_extend(value: u64, bits: u32) -> u64 {
+    if bits >= 64 {
+        return value;
+    }
+    let shift = 64 - bits;
+    ((value << shift) as i64 >> shift) as u64
+}
+
+fn zero_extend(value: u64, bits: u32) -> u64 {
+    if bits >= 64 {
+        value
+    } else {
+        value & ((1 << bits) - 1)
+    }
+}
+
+/// run `code`, None if it is malformed or faults
+pub fn eval(code: &[u8], target: &mut impl AgentTarget) -> Option<u64> {
+    let mut stack: Vec<u64> = Vec::with_capacity(MAX_STACK);
+    let mut pc = 0;
+    let imm = |pc: usize, len: usize| -> Option<u64> {
+        let bytes = code.get(pc..pc + len)?;
+        Some(bytes.iter().fold(0u64, |value, &b| value << 8 | b as u64))
+    };
+    for _ in 0..MAX_STEPS {
+        let op = *code.get(pc)?;
+        pc += 1;
+        match op {
+            // binary operators, top is the right operand
+            0x02..=0x0b | 0x0f..=0x11 | 0x13..=0x15 => {
+                let b = stack.pop()?;
+                let a = stack.pop()?;
+                stack.push(match op {
+                    0x02 => a.wrapping_add(b),
+                    0x03 => a.wrapping_sub(b),
+                    0x04 => a.wrapping_mul(b),
+                    0x05 => (a as i64).checked_div(b as i64)? as u64,
+                    0x06 => a.checked_div(b)?,
+                    0x07 => (a as i64).checked_rem(b as i64)? as u64,
+                    0x08 => a.checked_rem(b)?,
+                    0x09 => a.wrapping_shl(b as u32),
+                    0x0a => (a as i64).wrapping_shr(b as u32) as u64,
+                    0x0b => a.wrapping_shr(b as u32),
+                    0x0f => a & b,
+                    0x10 => a | b,
+                    0x11 => a ^ b,
+                    0x13 => (a == b) as u64,
+                    0x14 => ((a as i64) < (b as i64)) as u64,
+                    _ => (a < b) as u64,
+                });
+            }
+            // trace: addr size -> (nothing)
+            0x0c => {
+                let len = stack.pop()? as usize;
+                let addr = stack.pop()? as usize;
+                target.trace(addr, len);
+            }
+            // trace_quick n, trace16 n: addr -> addr
+            0x0d | 0x30 => {
+                let width = if op == 0x0d { 1 } else { 2 };
+                let len = imm(pc, width)? as usize;
+                pc += width;
+                target.trace(*stack.last()? as usize, len);
+            }
+            0x0e => {
+                let a = stack.pop()?;
+                stack.push((a == 0) as u64);
+            }
+            0x12 => {
+                let a = stack.pop()?;
+                stack.push(!a);
+            }
+            // ext n, zero_ext n
+            0x16 | 0x2a => {
+                let bits = imm(pc, 1)? as u32;
+                pc += 1;
+                let a = stack.pop()?;
+                stack.push(if op == 0x16 {
+                    sign_extend(a, bits)
+                } else {
+                    zero_extend(a, bits)
+                });
+            }
+            // ref8, ref16, ref32, ref64
+            0x17..=0x1a => {
+                let len = 1 << (op - 0x17);
+                let addr = stack.pop()? as usize;
+                let bytes = target.read(addr, len)?;
+                let value = bytes
+                    .iter()
+                    .rev()
+                    .fold(0u64, |value, &b| value << 8 | b as u64);
+                stack.push(value);
+            }
+            // if_goto, goto
+            0x20 | 0x21 => {
+                let target_pc = imm(pc, 2)? as usize;
+                pc += 2;
+                if op == 0x21 || stack.pop()? != 0 {
+                    pc = target_pc;
+                }
+            }
+            // const8 .. const64
+            0x22..=0x25 => {
+                let width = 1 << (op - 0x22);
+                stack.push(imm(pc, width)?);
+                pc += width;
+            }
+            0x26 => {
+                let index = imm(pc, 2)? as usize;
+                pc += 2;
+                stack.push(target.reg(index)? as u64);
+            }
+            0x27 => return Some(stack.pop().unwrap_or(0)),
+            0x28 => {
+                let a = *stack.last()?;
+                stack.push(a);
+            }
+            0x29 => {
+                stack.pop()?;
+            }
+            0x2b => {
+                let len = stack.len();
+                if len < 2 {
+                    return None;
+                }
+                stack.swap(len - 1, len - 2);
+            }
+            // pick n
+            0x32 => {
+                let n = imm(pc, 1)? as usize;
+                pc += 1;
+                let value = *stack.get(stack.len().checked_sub(n + 1)?)?;
+                stack.push(value);
+            }
+            // rot: a b c -> c a b
+            0x33 => {
+                let c = stack.pop()?;
+                let b = stack.pop()?;
+                let a = stack.pop()?;
+                stack.extend_from_slice(&[c, a, b]);
+            }
+            _ => return None,
+        }
+        if stack.len() > MAX_STACK {
+            return None;
+        }
+    }
+    None
+}
@@ -0,0 +1,522 @@
+//! gdb remote serial protocol stub on UART1
+//!
+//! lets gdb (or side-stub.py) debug the running kernel through the second serial port,
+//! without the QEMU gdbstub. supported packets:
+//! * `?`, `g`, `G`, `m`, `M`, `c`, `s`, `D` and ctrl-c
+//! * `Z0`/`z0` breakpoints, placed with kprobes
+//! * `qSupported`, `qfThreadInfo`/`qsThreadInfo`, `qC`, `Hg<pid>`
+//! * the side-stub tracepoint commands, see `tracepoint.rs`
+//! * gdb tracepoints (`QTDP`, `QTStart`, `QTFrame`, ...), see `tracing.rs`
+//!
+//! threads are processes, the thread id is pid + 1 since gdb reserves 0. `Hg` selects the
+//! process whose page table `m`/`M` use below the kernel.
+//!
+//! received bytes are parsed in the UART1 interrupt, ctrl-c stops the kernel at the end of
+//! that trap. a stopped kernel polls UART1 with interrupts off until gdb resumes it.
+
+mod agent;
+mod packet;
+mod step;
+mod tracepoint;
+mod tracing;
+
+use crate::drivers::chardev::UART1;
+use crate::ebpf::osutil::os_kernel_addr_valid;
+use crate::mm::{try_translated_byte_buffer, try_translated_copy};
+use crate::probe::arch::get_kernel_trapframe_sp;
+use crate::probe::kprobes::check_kprobe_addr;
+use crate::probe::{register_kprobe, unregister_kprobe, KProbeArgs, TrapFrame};
+use crate::task::{pid2process, try_current_task, try_pid2process, PID2PCB};
+use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+use packet::*;
+
+/// x0-x31 and pc
+const GDB_REGS: usize = 33;
+
+struct Breakpoint {
+    /// None until the kprobe is registered
+    handle: Option<usize>,
+    /// the original instruction, the kprobe replaces it with an ebreak
+    insn: u32,
+}
+
+struct GdbStub {
+    parser: Parser,
+    /// process selected by Hg, None for the current one
+    pid: Option<usize>,
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    /// removed by gdb, the kprobe is unregistered later
+    removed: BTreeMap<usize, Breakpoint>,
+    /// kprobe handles of the `vTR`, `vTM` and `vTA` captures
+    captures: Vec<usize>,
+    /// captures dropped by `D`, unregistered later like `removed`
+    removed_captures: Vec<usize>,
+}
+
+/// the interrupted context of a stopped kernel
+struct Stop<'a> {
+    tf: &'a mut TrapFrame,
+    /// stopped in user mode, the kernel has no sp of its own in tf then
+    user: bool,
+}
+
+enum Action {
+    Reply(Vec<u8>),
+    Resume,
+    /// stop the kernel and send the stop reply
+    Halt,
+}
+
+lazy_static! {
+    static ref STUB: spin::Mutex<GdbStub> = spin::Mutex::new(GdbStub {
+        parser: Parser::new(),
+        pid: None,
+        breakpoints: BTreeMap::new(),
+        removed: BTreeMap::new(),
+        captures: Vec::new(),
+        removed_captures: Vec::new(),
+    });
+}
+
+static HALT_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// stopped in a kprobe handler, kprobes can not be (un)registered until it returns
+static IN_KPROBE: AtomicBool = AtomicBool::new(false);
+
+impl Stop<'_> {
+    fn reg(&self, index: u32) -> usize {
+        match index {
+            0 => 0,
+            2 if !self.user => get_kernel_trapframe_sp(self.tf),
+            _ => self.tf.x[index as usize],
+        }
+    }
+}
+
+/// None also if the processor is borrowed, the stub may run inside a probe
+fn current_pid() -> Option<usize> {
+    try_current_task()
+        .and_then(|task| task.process.upgrade())
+        .map(|process| process.getpid())
+}
+
+/// gdb thread id of the current process, 1 (initproc) during boot
+fn current_thread_id() -> usize {
+    current_pid().unwrap_or(0) + 1
+}
+
+/// kernel memory is accessed directly, the rest through the page table of process `pid`.
+/// None also if the process is borrowed by the code the stub or a capture interrupted
+fn memory(pid: Option<usize>, addr: usize, len: usize) -> Option<Vec<&'static mut [u8]>> {
+    if os_kernel_addr_valid(addr, len) {
+        return Some(vec![unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) }]);
+    }
+    let token = try_pid2process(pid.or_else(current_pid)?)?
+        .try_inner_exclusive_access()?
+        .get_user_token();
+    try_translated_byte_buffer(token, addr as *const u8, len)
+}
+
+/// like `memory` for the current process, but copies into `dst` without allocating
+fn copy_memory(addr: usize, dst: &mut [u8]) -> Option<()> {
+    if os_kernel_addr_valid(addr, dst.len()) {
+        let src = unsafe { core::slice::from_raw_parts(addr as *const u8, dst.len()) };
+        dst.copy_from_slice(src);
+        return Some(());
+    }
+    let token = try_pid2process(current_pid()?)?
+        .try_inner_exclusive_access()?
+        .get_user_token();
+    try_translated_copy(token, addr as *const u8, dst)
+}
+
+fn read_insn(addr: usize) -> u32 {
+    let low = unsafe { *(addr as *const u16) } as u32;
+    if low & 0b11 != 0b11 {
+        low
+    } else {
+        low | (unsafe { *((addr + 2) as *const u16) } as u32) << 16
+    }
+}
+
+impl GdbStub {
+    fn stop_reply(&self) -> Vec<u8> {
+        format!("T05thread:{:x};", current_thread_id()).into_bytes()
+    }
+
+    fn read_registers(&self, stop: &Stop) -> Vec<u8> {
+        let mut reply = Vec::with_capacity(GDB_REGS * 16);
+        for i in 0..32 {
+            push_hex_le(&mut reply, stop.reg(i));
+        }
+        push_hex_le(&mut reply, stop.tf.sepc);
+        reply
+    }
+
+    fn write_registers(&self, stop: &mut Stop, args: &[u8]) -> Vec<u8> {
+        if args.len() < GDB_REGS * 16 {
+            return b"E01".to_vec();
+        }
+        let mut regs = [0usize; GDB_REGS];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            match decode_hex_le(&args[i * 16..(i + 1) * 16]) {
+                Some(value) => *reg = value,
+                None => return b"E01".to_vec(),
+            }
+        }
+        // the kernel sp is where the trap frame lives, it can not move
+        for i in 1..32 {
+            if i != 2 || stop.user {
+                stop.tf.x[i] = regs[i];
+            }
+        }
+        stop.tf.sepc = regs[32];
+        b"OK".to_vec()
+    }
+
+    fn read_memory(&self, args: &[u8]) -> Vec<u8> {
+        let (addr, len) = match parse_addr_len(args) {
+            Some((addr, len)) if len <= PACKET_SIZE / 2 => (addr, len),
+            _ => return b"E01".to_vec(),
+        };
+        match memory(self.pid, addr, len) {
+            Some(chunks) => {
+                let mut reply = Vec::with_capacity(len * 2);
+                for chunk in chunks.iter() {
+                    push_hex_bytes(&mut reply, chunk);
+                }
+                reply
+            }
+            None => b"E14".to_vec(),
+        }
+    }
+
+    fn write_memory(&self, args: &[u8]) -> Vec<u8> {
+        let colon = match args.iter().position(|&ch| ch == b':') {
+            Some(colon) => colon,
+            None => return b"E01".to_vec(),
+        };
+        let (addr, data) = match (parse_addr_len(&args[..colon]), decode_hex_bytes(&args[colon + 1..])) {
+            (Some((addr, len)), Some(data)) if data.len() == len => (addr, data),
+            _ => return b"E01".to_vec(),
+        };
+        match memory(self.pid, addr, data.len()) {
+            Some(chunks) => {
+                let mut data = &data[..];
+                for chunk in chunks {
+                    let (head, rest) = data.split_at(chunk.len());
+                    chunk.copy_from_slice(head);
+                    data = rest;
+                }
+                crate::probe::arch::invalidate_icache();
+                b"OK".to_vec()
+            }
+            None => b"E14".to_vec(),
+        }
+    }
+
+    /// `Z0,addr,kind` and `z0,addr,kind`
+    fn set_breakpoint(&mut self, args: &[u8], insert: bool) -> Vec<u8> {
+        let addr = match args.strip_prefix(b"0,").and_then(parse_addr_len) {
+            Some((addr, _)) => addr,
+            None => return Vec::new(),
+        };
+        if insert {
+            if !self.breakpoints.contains_key(&addr) {
+                // inserted again before the kprobe was unregistered, e.g. resuming from a stop
+                let bp = match self.removed.remove(&addr) {
+                    Some(bp) => bp,
+                    None if check_kprobe_addr(addr) => Breakpoint {
+                        handle: None,
+                        insn: read_insn(addr),
+                    },
+                    None => return b"E01".to_vec(),
+                };
+                self.breakpoints.insert(addr, bp);
+            }
+        } else if let Some(bp) = self.breakpoints.remove(&addr) {
+            if bp.handle.is_some() {
+                self.removed.insert(addr, bp);
+            }
+        }
+        self.sync_breakpoints();
+        // the kprobe could not be registered, unless that waits for the handler to return
+        if insert && !IN_KPROBE.load(Ordering::Relaxed) && !self.breakpoints.contains_key(&addr) {
+            return b"E01".to_vec();
+        }
+        b"OK".to_vec()
+    }
+
+    /// (un)register the kprobes of changed breakpoints, later if called from a kprobe handler
+    fn sync_breakpoints(&mut self) {
+        if IN_KPROBE.load(Ordering::Relaxed) {
+            return;
+        }
+        for (_, bp) in core::mem::take(&mut self.removed) {
+            unregister_kprobe(bp.handle.unwrap());
+        }
+        for handle in core::mem::take(&mut self.removed_captures) {
+            unregister_kprobe(handle);
+        }
+        let mut failed = Vec::new();
+        for (&addr, bp) in self.breakpoints.iter_mut().filter(|(_, bp)| bp.handle.is_none()) {
+            let args = KProbeArgs {
+                pre_handler: Arc::new(breakpoint_handler),
+                post_handler: None,
+                user_data: addr,
+            };
+            match register_kprobe(addr, args) {
+                Some(handle) => bp.handle = Some(handle),
+                None => failed.push(addr),
+            }
+        }
+        for addr in failed {
+            warn!("gdbstub: can not place a breakpoint at {:#x}", addr);
+            self.breakpoints.remove(&addr);
+        }
+    }
+
+    /// put step breakpoints after the instruction at pc
+    fn step(&self, stop: &Stop) -> bool {
+        if stop.user {
+            return false;
+        }
+        let pc = stop.tf.sepc;
+        // a kprobe may have replaced the instruction with an ebreak
+        let insn = match self.breakpoints.get(&pc).or_else(|| self.removed.get(&pc)) {
+            Some(bp) => bp.insn,
+            None if crate::probe::arch::is_ebreak(pc) => return false,
+            None => read_insn(pc),
+        };
+        step::insert_step_breakpoints(&step::successors(insn, pc, |i| stop.reg(i)))
+    }
+
+    fn thread_list(&self) -> Vec<u8> {
+        let pids: Vec<usize> = PID2PCB.exclusive_access().keys().cloned().collect();
+        let mut reply = b"m".to_vec();
+        for (i, pid) in pids.iter().enumerate() {
+            if i > 0 {
+                reply.push(b',');
+            }
+            reply.extend_from_slice(format!("{:x}", pid + 1).as_bytes());
+        }
+        reply
+    }
+
+    fn select_thread(&mut self, args: &[u8]) -> Vec<u8> {
+        match args {
+            b"0" | b"-1" => self.pid = None,
+            _ => match parse_hex(args) {
+                Some(id) if id > 0 && pid2process(id - 1).is_some() => self.pid = Some(id - 1),
+                _ => return b"E01".to_vec(),
+            },
+        }
+        b"OK".to_vec()
+    }
+
+    fn detach(&mut self) {
+        let addrs: Vec<usize> = self.breakpoints.keys().cloned().collect();
+        for addr in addrs {
+            let bp = self.breakpoints.remove(&addr).unwrap();
+            if bp.handle.is_some() {
+                self.removed.insert(addr, bp);
+            }
+        }
+        let captures = core::mem::take(&mut self.captures);
+        self.removed_captures.extend(captures);
+        tracepoint::disable_captures();
+        self.sync_breakpoints();
+        self.pid = None;
+    }
+
+    /// handle a packet, `stop` is None while the kernel runs
+    fn handle(&mut self, packet: &[u8], stop: Option<&mut Stop>) -> Action {
+        let (&command, args) = match packet.split_first() {
+            Some(split) => split,
+            None => return Action::Reply(Vec::new()),
+        };
+        let reply = match (command, stop) {
+            (b'g', _) if tracing::frame_selected() => tracing::frame_read_registers(),
+            (b'm', _) if tracing::frame_selected() => tracing::frame_read_memory(args),
+            (b'q' | b'Q', _) if packet.starts_with(b"qT") || packet.starts_with(b"QT") => {
+                if tracing::attaches_probes(packet) {
+                    deferred_in_kprobe(|| tracing::handle(packet).unwrap_or_default())
+                } else {
+                    tracing::handle(packet).unwrap_or_default()
+                }
+            }
+            (b'?', None) => return Action::Halt,
+            (b'?', Some(_)) => self.stop_reply(),
+            (b'g', Some(stop)) => self.read_registers(stop),
+            (b'G', Some(stop)) => self.write_registers(stop, args),
+            (b'c', Some(stop)) => {
+                if let Some(addr) = parse_hex(args) {
+                    stop.tf.sepc = addr;
+                }
+                return Action::Resume;
+            }
+            (b's', Some(stop)) => {
+                if let Some(addr) = parse_hex(args) {
+                    stop.tf.sepc = addr;
+                }
+                if self.step(stop) {
+                    return Action::Resume;
+                }
+                b"E01".to_vec()
+            }
+            (b'g' | b'G' | b'c' | b's', None) => b"E01".to_vec(),
+            (b'D', stop) => {
+                self.detach();
+                if stop.is_some() {
+                    send_packet(b"OK");
+                    return Action::Resume;
+                }
+                b"OK".to_vec()
+            }
+            (b'm', _) => self.read_memory(args),
+            (b'M', _) => self.write_memory(args),
+            (b'Z', _) => self.set_breakpoint(args, true),
+            (b'z', _) => self.set_breakpoint(args, false),
+            (b'H', _) if args.first() == Some(&b'g') => self.select_thread(&args[1..]),
+            (b'H', _) => b"OK".to_vec(),
+            (b'T', _) => match parse_hex(args) {
+                Some(id) if id > 0 && pid2process(id - 1).is_some() => b"OK".to_vec(),
+                _ => b"E01".to_vec(),
+            },
+            (b'q', _) => {
+                if packet.starts_with(b"qSupported") {
+                    format!("PacketSize={:x}", PACKET_SIZE).into_bytes()
+                } else if packet == b"qfThreadInfo" {
+                    self.thread_list()
+                } else if packet == b"qsThreadInfo" {
+                    b"l".to_vec()
+                } else if packet == b"qC" {
+                    format!("QC{:x}", current_thread_id()).into_bytes()
+                } else if packet == b"qAttached" {
+                    b"1".to_vec()
+                } else {
+                    Vec::new()
+                }
+            }
+            (b'v', _) => {
+                if let Some(args) = packet.strip_prefix(b"vTR") {
+                    deferred_in_kprobe(|| tracepoint::trace_registers(args, &mut self.captures))
+                } else if let Some(args) = packet.strip_prefix(b"vTM") {
+                    deferred_in_kprobe(|| tracepoint::trace_memory(args, &mut self.captures))
+                } else if let Some(args) = packet.strip_prefix(b"vTA") {
+                    deferred_in_kprobe(|| tracepoint::trace_arguments(args, &mut self.captures))
+                } else if let Some(args) = packet.strip_prefix(b"vTU") {
+                    tracepoint::trace_user_registers(args)
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        };
+        Action::Reply(reply)
+    }
+}
+
+/// kprobes can not be registered while stopped in a kprobe handler
+fn deferred_in_kprobe(f: impl FnOnce() -> Vec<u8>) -> Vec<u8> {
+    if IN_KPROBE.load(Ordering::Relaxed) {
+        b"E16".to_vec()
+    } else {
+        f()
+    }
+}
+
+/// report a stop and serve gdb until it resumes, returns whether gdb changed pc
+fn stop(tf: &mut TrapFrame, user: bool) -> bool {
+    // a probe hit while the stub itself was running
+    let mut stub = match STUB.try_lock() {
+        Some(stub) => stub,
+        None => return false,
+    };
+    HALT_REQUESTED.store(false, Ordering::Relaxed);
+    let pc = tf.sepc;
+    let mut stop = Stop { tf, user };
+    send_packet(&stub.stop_reply());
+    loop {
+        let ch = match UART1.try_read() {
+            Some(ch) => ch,
+            None => continue,
+        };
+        let packet = match stub.parser.feed(ch) {
+            Some(Event::Packet(packet)) => packet,
+            _ => continue,
+        };
+        match stub.handle(&packet, Some(&mut stop)) {
+            Action::Reply(reply) => send_packet(&reply),
+            Action::Halt => send_packet(&stub.stop_reply()),
+            Action::Resume => break,
+        }
+    }
+    stop.tf.sepc != pc
+}
+
+#[link_section = ".text.noprobe"]
+fn breakpoint_handler(tf: &mut TrapFrame, addr: usize) -> isize {
+    // removed by gdb, but the kprobe is still there
+    if STUB.try_lock().map_or(true, |stub| !stub.breakpoints.contains_key(&addr)) {
+        return 0;
+    }
+    IN_KPROBE.store(true, Ordering::Relaxed);
+    let pc_changed = stop(tf, false);
+    IN_KPROBE.store(false, Ordering::Relaxed);
+    pc_changed as isize
+}
+
+/// feed bytes received by UART1 to the stub, called from its interrupt
+pub fn poll() {
+    let mut stub = match STUB.try_lock() {
+        Some(stub) => stub,
+        None => return,
+    };
+    while let Some(ch) = UART1.try_read() {
+        let action = match stub.parser.feed(ch) {
+            Some(Event::Packet(packet)) => stub.handle(&packet, None),
+            Some(Event::Interrupt) => Action::Halt,
+            None => continue,
+        };
+        match action {
+            Action::Reply(reply) => send_packet(&reply),
+            Action::Halt => HALT_REQUESTED.store(true, Ordering::Relaxed),
+            Action::Resume => {}
+        }
+    }
+}
+
+/// stop here if gdb asked for it, called at the end of interrupt handling
+#[link_section = ".text.noprobe"]
+pub fn check_halt(tf: &mut TrapFrame, user: bool) {
+    if HALT_REQUESTED.load(Ordering::Relaxed) {
+        stop(tf, user);
+    }
+}
+
+/// the kernel ebreak trap of a single step, returns false if it is someone else's
+#[link_section = ".text.noprobe"]
+pub fn step_trap_handler(tf: &mut TrapFrame) -> bool {
+    if !step::remove_step_breakpoints(tf.sepc) {
+        return false;
+    }
+    stop(tf, false);
+    true
+}
+
+/// apply breakpoint changes made while stopped in a kprobe, called on timer interrupts
+pub fn tick() {
+    if let Some(mut stub) = STUB.try_lock() {
+        if !stub.removed.is_empty()
+            || !stub.removed_captures.is_empty()
+            || stub.breakpoints.values().any(|bp| bp.handle.is_none())
+        {
+            stub.sync_breakpoints();
+        }
+    }
+}
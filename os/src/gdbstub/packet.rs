@@ -0,0 +1,159 @@
+//! remote serial protocol framing: `$payload#checksum`, acks and hex encoding
+
+use crate::drivers::chardev::{CharDevice, UART1};
+use alloc::vec::Vec;
+
+/// largest packet payload we accept, advertised in qSupported
+pub const PACKET_SIZE: usize = 4096;
+
+pub enum Event {
+    /// a packet with a good checksum, already acked
+    Packet(Vec<u8>),
+    /// ctrl-c outside of a packet
+    Interrupt,
+}
+
+enum State {
+    Idle,
+    Payload,
+    Checksum1,
+    Checksum2,
+}
+
+pub struct Parser {
+    state: State,
+    payload: Vec<u8>,
+    sum: u8,
+    received: u8,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle,
+            payload: Vec::new(),
+            sum: 0,
+            received: 0,
+        }
+    }
+
+    /// feed one received byte, acks are sent here
+    pub fn feed(&mut self, ch: u8) -> Option<Event> {
+        match self.state {
+            State::Idle => match ch {
+                b'$' => self.restart(),
+                0x03 => return Some(Event::Interrupt),
+                // acks from the other side and line noise
+                _ => {}
+            },
+            State::Payload => match ch {
+                b'#' => self.state = State::Checksum1,
+                // the start of a packet we missed the end of
+                b'$' => self.restart(),
+                _ => {
+                    if self.payload.len() < PACKET_SIZE {
+                        self.payload.push(ch);
+                    }
+                    self.sum = self.sum.wrapping_add(ch);
+                }
+            },
+            State::Checksum1 => {
+                self.received = hex_val(ch).unwrap_or(0xf0) << 4;
+                self.state = State::Checksum2;
+            }
+            State::Checksum2 => {
+                self.state = State::Idle;
+                match hex_val(ch) {
+                    Some(low) if self.received | low == self.sum => {
+                        send_raw(b"+");
+                        return Some(Event::Packet(core::mem::take(&mut self.payload)));
+                    }
+                    _ => send_raw(b"-"),
+                }
+            }
+        }
+        None
+    }
+
+    fn restart(&mut self) {
+        self.payload.clear();
+        self.sum = 0;
+        self.state = State::Payload;
+    }
+}
+
+pub fn send_raw(bytes: &[u8]) {
+    for &ch in bytes {
+        UART1.write(ch);
+    }
+}
+
+pub fn send_packet(payload: &[u8]) {
+    let sum = payload.iter().fold(0u8, |sum, &ch| sum.wrapping_add(ch));
+    send_raw(b"$");
+    send_raw(payload);
+    send_raw(b"#");
+    let mut tail = Vec::new();
+    push_hex_u8(&mut tail, sum);
+    send_raw(&tail);
+}
+
+pub fn hex_val(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'a'..=b'f' => Some(ch - b'a' + 10),
+        b'A'..=b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// a hex number, with or without `0x`
+pub fn parse_hex(s: &[u8]) -> Option<usize> {
+    let s = s.strip_prefix(b"0x").unwrap_or(s);
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0usize, |value, &ch| Some(value << 4 | hex_val(ch)? as usize))
+}
+
+pub fn push_hex_u8(out: &mut Vec<u8>, byte: u8) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    out.push(DIGITS[(byte >> 4) as usize]);
+    out.push(DIGITS[(byte & 0xf) as usize]);
+}
+
+pub fn push_hex_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    for &byte in bytes {
+        push_hex_u8(out, byte);
+    }
+}
+
+/// a register as gdb sends it, target byte order
+pub fn push_hex_le(out: &mut Vec<u8>, value: usize) {
+    push_hex_bytes(out, &value.to_le_bytes());
+}
+
+pub fn decode_hex_bytes(s: &[u8]) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks(2)
+        .map(|pair| Some(hex_val(pair[0])? << 4 | hex_val(pair[1])?))
+        .collect()
+}
+
+/// a register in target byte order
+pub fn decode_hex_le(s: &[u8]) -> Option<usize> {
+    let bytes = decode_hex_bytes(s)?;
+    if bytes.len() != core::mem::size_of::<usize>() {
+        return None;
+    }
+    Some(usize::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// `<hex>,<hex>` as in m and M
+pub fn parse_addr_len(s: &[u8]) -> Option<(usize, usize)> {
+    let comma = s.iter().position(|&ch| ch == b',')?;
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
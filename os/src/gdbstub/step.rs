@@ -0,0 +1,136 @@
+//! single step by temporary breakpoints on every instruction that may run next
+//!
+//! the kernel has no hardware single step, so the instruction at pc is decoded, a c.ebreak
+//! is put on each successor (both ways of a branch) and the kernel resumes. the first one hit
+//! restores all of them. the ebreak trap is taken before kprobes see it.
+
+use crate::probe::arch::{byte_copy, inject_breakpoints, invalidate_icache};
+use crate::probe::osutils::kernel_text_range;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const OPCODE_BRANCH: u32 = 0b110_0011;
+const OPCODE_JALR: u32 = 0b110_0111;
+const OPCODE_JAL: u32 = 0b110_1111;
+
+/// a c.ebreak and the halfword it replaced
+struct StepBreakpoint {
+    addr: usize,
+    orig: u16,
+}
+
+lazy_static! {
+    static ref STEP_BREAKPOINTS: spin::Mutex<Vec<StepBreakpoint>> = spin::Mutex::new(Vec::new());
+}
+
+fn sign_extend(value: u32, bits: u32) -> isize {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as isize
+}
+
+fn bit(i: u32, from: u32, to: u32) -> u32 {
+    ((i >> from) & 1) << to
+}
+
+/// addresses execution may continue at after the instruction `insn` at `pc`,
+/// `reg` reads the registers the instruction sees
+pub fn successors(insn: u32, pc: usize, reg: impl Fn(u32) -> usize) -> Vec<usize> {
+    let offset = |imm: isize| (pc as isize + imm) as usize;
+    if insn & 0b11 != 0b11 {
+        let funct3 = (insn >> 13) & 0b111;
+        let rd = (insn >> 7) & 0x1f;
+        let rs2 = (insn >> 2) & 0x1f;
+        return match (insn & 0b11, funct3) {
+            // c.j
+            (0b01, 0b101) => {
+                let imm = bit(insn, 12, 11)
+                    | bit(insn, 11, 4)
+                    | bit(insn, 10, 9)
+                    | bit(insn, 9, 8)
+                    | bit(insn, 8, 10)
+                    | bit(insn, 7, 6)
+                    | bit(insn, 6, 7)
+                    | bit(insn, 5, 3)
+                    | bit(insn, 4, 2)
+                    | bit(insn, 3, 1)
+                    | bit(insn, 2, 5);
+                vec![offset(sign_extend(imm, 12))]
+            }
+            // c.beqz, c.bnez
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let imm = bit(insn, 12, 8)
+                    | bit(insn, 11, 4)
+                    | bit(insn, 10, 3)
+                    | bit(insn, 6, 7)
+                    | bit(insn, 5, 6)
+                    | bit(insn, 4, 2)
+                    | bit(insn, 3, 1)
+                    | bit(insn, 2, 5);
+                vec![pc + 2, offset(sign_extend(imm, 9))]
+            }
+            // c.jr, c.jalr
+            (0b10, 0b100) if rd != 0 && rs2 == 0 => vec![reg(rd) & !1],
+            _ => vec![pc + 2],
+        };
+    }
+    match insn & 0x7f {
+        OPCODE_JAL => {
+            let imm = (insn >> 31) << 20
+                | ((insn >> 21) & 0x3ff) << 1
+                | ((insn >> 20) & 1) << 11
+                | ((insn >> 12) & 0xff) << 12;
+            vec![offset(sign_extend(imm, 21))]
+        }
+        OPCODE_JALR => {
+            let rs1 = (insn >> 15) & 0x1f;
+            let imm = sign_extend(insn >> 20, 12);
+            vec![(reg(rs1) as isize + imm) as usize & !1]
+        }
+        OPCODE_BRANCH => {
+            let imm = (insn >> 31) << 12
+                | ((insn >> 25) & 0x3f) << 5
+                | ((insn >> 8) & 0xf) << 1
+                | ((insn >> 7) & 1) << 11;
+            vec![pc + 4, offset(sign_extend(imm, 13))]
+        }
+        _ => vec![pc + 4],
+    }
+}
+
+/// put step breakpoints on `addrs`, all of them must be in kernel text
+pub fn insert_step_breakpoints(addrs: &[usize]) -> bool {
+    let (start, end) = kernel_text_range();
+    if addrs.iter().any(|&addr| addr < start || addr >= end || addr % 2 != 0) {
+        return false;
+    }
+    let mut bps = STEP_BREAKPOINTS.lock();
+    for &addr in addrs {
+        if bps.iter().any(|bp| bp.addr == addr) {
+            continue;
+        }
+        let mut orig = 0u16;
+        byte_copy(&mut orig as *mut u16 as usize, addr, 2);
+        inject_breakpoints(addr, None);
+        bps.push(StepBreakpoint { addr, orig });
+    }
+    invalidate_icache();
+    true
+}
+
+/// if `pc` is a step breakpoint, restore all of them and return true
+pub fn remove_step_breakpoints(pc: usize) -> bool {
+    let mut bps = match STEP_BREAKPOINTS.try_lock() {
+        Some(bps) => bps,
+        None => return false,
+    };
+    if !bps.iter().any(|bp| bp.addr == pc) {
+        return false;
+    }
+    // in reverse, in case a successor was already a breakpoint of someone else
+    for bp in bps.iter().rev() {
+        byte_copy(bp.addr, &bp.orig as *const u16 as usize, 2);
+    }
+    bps.clear();
+    invalidate_icache();
+    true
+}
@@ -0,0 +1,321 @@
+//! tracepoints requested by side-stub.py, they capture and stream instead of stopping:
+//! * `vTR<addr>` registers at a kernel address
+//! * `vTM<addr>:<base>,<len>` `len` bytes of memory at `base`, an address or `reg[+-offset]`
+//! * `vTA<addr>[:<count>]` the first `count` (default 8) argument registers of a function
+//! * `vTU<path>:<addr>` registers at an address of a user program
+//!
+//! kernel addresses may also be symbols. captures are sent as trace channel records,
+//! the reply only tells whether the probe is set. `D` removes the kernel captures and
+//! disables the user ones, ruprobes can not unregister a uprobe.
+//!
+//! kernel captures run in kprobes, anywhere in the kernel, so they only try the locks and
+//! borrows they need and send a TRACE_MESSAGE when one is held.
+
+use super::packet::parse_hex;
+use crate::ebpf::trace_channel::{
+    trace_output, TRACE_ARGS, TRACE_MAX_PAYLOAD, TRACE_MEMORY, TRACE_MESSAGE, TRACE_REGS,
+};
+use crate::probe::arch::get_kernel_trapframe_sp;
+use crate::probe::{register_kprobe, KProbeArgs, TrapFrame};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use ruprobes::{uprobe_register, ProbeType};
+
+/// x0-x31, sstatus and sepc as saved by the trap entry
+const SAVED_REGS: usize = 34;
+/// a0-a7
+const MAX_ARGS: usize = 8;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// what a kernel capture sends
+#[derive(Clone, Copy)]
+enum Capture {
+    Registers,
+    /// start and length of the window
+    Memory(Base, usize),
+    /// number of argument registers
+    Arguments(usize),
+}
+
+/// user captures set by `vTU`
+struct UserCaptures {
+    /// path and address of every uprobe registered
+    registered: BTreeSet<(String, usize)>,
+    /// addresses whose registers are sent
+    enabled: BTreeSet<usize>,
+}
+
+lazy_static! {
+    /// kernel captures and their probed address, by the user_data of their kprobe
+    static ref CAPTURES: spin::Mutex<BTreeMap<usize, (usize, Capture)>> =
+        spin::Mutex::new(BTreeMap::new());
+    static ref USER_CAPTURES: spin::Mutex<UserCaptures> = spin::Mutex::new(UserCaptures {
+        registered: BTreeSet::new(),
+        enabled: BTreeSet::new(),
+    });
+}
+
+static NEXT_CAPTURE: AtomicUsize = AtomicUsize::new(0);
+
+/// start of a captured memory window
+#[derive(Clone, Copy)]
+enum Base {
+    Addr(usize),
+    /// register index and offset
+    Reg(u32, isize),
+}
+
+/// address of a kernel function, as a hex number or a symbol
+fn parse_kernel_addr(s: &[u8]) -> Option<usize> {
+    parse_hex(s).or_else(|| {
+        let symbol = core::str::from_utf8(s).ok()?;
+        crate::probe::osutils::symbol_to_addr(symbol)
+    })
+}
+
+fn parse_reg(name: &[u8]) -> Option<u32> {
+    let name = core::str::from_utf8(name).ok()?;
+    if name == "fp" {
+        return Some(8);
+    }
+    if let Some(index) = name.strip_prefix('x').and_then(|i| i.parse::<u32>().ok()) {
+        return if index < 32 { Some(index) } else { None };
+    }
+    REG_NAMES.iter().position(|&reg| reg == name).map(|i| i as u32)
+}
+
+fn parse_base(s: &[u8]) -> Option<Base> {
+    if let Some(addr) = parse_hex(s) {
+        return Some(Base::Addr(addr));
+    }
+    match s.iter().position(|&ch| ch == b'+' || ch == b'-') {
+        Some(sign) => {
+            let offset = parse_hex(&s[sign + 1..])? as isize;
+            let offset = if s[sign] == b'-' { -offset } else { offset };
+            Some(Base::Reg(parse_reg(&s[..sign])?, offset))
+        }
+        None => Some(Base::Reg(parse_reg(s)?, 0)),
+    }
+}
+
+/// a register of the probed kernel code, the saved sp is not the real one
+fn kernel_reg(tf: &TrapFrame, index: u32) -> usize {
+    match index {
+        0 => 0,
+        2 => get_kernel_trapframe_sp(tf),
+        _ => tf.x[index as usize],
+    }
+}
+
+/// a record payload built on the stack, the heap lock may be held by the probed code
+struct Payload {
+    buf: [u8; TRACE_MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Payload {
+    fn new() -> Self {
+        Self {
+            buf: [0; TRACE_MAX_PAYLOAD],
+            len: 0,
+        }
+    }
+
+    /// the payload is cut at TRACE_MAX_PAYLOAD
+    fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(TRACE_MAX_PAYLOAD - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+
+    fn send(&self, ty: u8) {
+        let _ = trace_output(ty, &self.buf[..self.len]);
+    }
+}
+
+impl Write for Payload {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// the probed address, then the saved registers with the real sp
+#[link_section = ".text.noprobe"]
+fn send_regs(tf: &TrapFrame, addr: usize, sp: usize) {
+    let mut payload = Payload::new();
+    payload.push(&(addr as u64).to_le_bytes());
+    let regs = unsafe { core::slice::from_raw_parts(tf as *const TrapFrame as *const usize, SAVED_REGS) };
+    for (i, &reg) in regs.iter().enumerate() {
+        let reg = if i == 2 { sp } else { reg };
+        payload.push(&(reg as u64).to_le_bytes());
+    }
+    payload.send(TRACE_REGS);
+}
+
+/// the window address, then its bytes, read through the page table for user addresses
+#[link_section = ".text.noprobe"]
+fn send_memory(tf: &TrapFrame, base: Base, len: usize) {
+    let addr = match base {
+        Base::Addr(addr) => addr,
+        Base::Reg(index, offset) => (kernel_reg(tf, index) as isize).wrapping_add(offset) as usize,
+    };
+    // `len` was checked against TRACE_MAX_PAYLOAD by `vTM`
+    let mut payload = Payload::new();
+    payload.push(&(addr as u64).to_le_bytes());
+    let start = payload.len;
+    if super::copy_memory(addr, &mut payload.buf[start..start + len]).is_none() {
+        let mut message = Payload::new();
+        let _ = write!(message, "can not read {} bytes at {:#x}, unmapped or busy", len, addr);
+        message.send(TRACE_MESSAGE);
+        return;
+    }
+    payload.len += len;
+    payload.send(TRACE_MEMORY);
+}
+
+#[link_section = ".text.noprobe"]
+fn send_args(tf: &TrapFrame, addr: usize, count: usize) {
+    let mut payload = Payload::new();
+    payload.push(&(addr as u64).to_le_bytes());
+    for &arg in tf.x[10..10 + count].iter() {
+        payload.push(&(arg as u64).to_le_bytes());
+    }
+    payload.send(TRACE_ARGS);
+}
+
+/// the kprobe handler of every kernel capture, `id` is its key in CAPTURES
+#[link_section = ".text.noprobe"]
+fn capture_handler(tf: &mut TrapFrame, id: usize) -> isize {
+    let capture = match CAPTURES.try_lock() {
+        Some(captures) => captures.get(&id).copied(),
+        None => {
+            let _ = trace_output(TRACE_MESSAGE, b"capture skipped, the captures are busy");
+            return 0;
+        }
+    };
+    // None once removed by `D`, until the kprobe is unregistered
+    match capture {
+        Some((addr, Capture::Registers)) => send_regs(tf, addr, get_kernel_trapframe_sp(tf)),
+        Some((_, Capture::Memory(base, len))) => send_memory(tf, base, len),
+        Some((addr, Capture::Arguments(count))) => send_args(tf, addr, count),
+        None => {}
+    }
+    0
+}
+
+/// put a kprobe running `capture` on `addr`, its handle is added to `captures`
+fn register_capture(addr: usize, capture: Capture, captures: &mut Vec<usize>) -> Vec<u8> {
+    let id = NEXT_CAPTURE.fetch_add(1, Ordering::Relaxed);
+    CAPTURES.lock().insert(id, (addr, capture));
+    let kprobe_args = KProbeArgs {
+        pre_handler: Arc::new(capture_handler),
+        post_handler: None,
+        user_data: id,
+    };
+    match register_kprobe(addr, kprobe_args) {
+        Some(handle) => {
+            captures.push(handle);
+            b"OK".to_vec()
+        }
+        None => {
+            CAPTURES.lock().remove(&id);
+            b"E02".to_vec()
+        }
+    }
+}
+
+/// `vTR<addr>`
+pub fn trace_registers(args: &[u8], captures: &mut Vec<usize>) -> Vec<u8> {
+    match parse_kernel_addr(args) {
+        Some(addr) => register_capture(addr, Capture::Registers, captures),
+        None => b"E01".to_vec(),
+    }
+}
+
+/// `vTM<addr>:<base>,<len>`
+pub fn trace_memory(args: &[u8], captures: &mut Vec<usize>) -> Vec<u8> {
+    let colon = args.iter().position(|&ch| ch == b':');
+    let comma = args.iter().rposition(|&ch| ch == b',');
+    let (colon, comma) = match (colon, comma) {
+        (Some(colon), Some(comma)) if colon < comma => (colon, comma),
+        _ => return b"E01".to_vec(),
+    };
+    let addr = parse_kernel_addr(&args[..colon]);
+    let base = parse_base(&args[colon + 1..comma]);
+    let len = parse_hex(&args[comma + 1..]);
+    match (addr, base, len) {
+        (Some(addr), Some(base), Some(len))
+            if len > 0 && len <= TRACE_MAX_PAYLOAD - size_of::<u64>() =>
+        {
+            register_capture(addr, Capture::Memory(base, len), captures)
+        }
+        _ => b"E01".to_vec(),
+    }
+}
+
+/// `vTA<addr>[:<count>]`
+pub fn trace_arguments(args: &[u8], captures: &mut Vec<usize>) -> Vec<u8> {
+    let (addr, count) = match args.iter().rposition(|&ch| ch == b':') {
+        Some(colon) => (parse_kernel_addr(&args[..colon]), parse_hex(&args[colon + 1..])),
+        None => (parse_kernel_addr(args), Some(MAX_ARGS)),
+    };
+    match (addr, count) {
+        (Some(addr), Some(count)) if count > 0 && count <= MAX_ARGS => {
+            register_capture(addr, Capture::Arguments(count), captures)
+        }
+        _ => b"E01".to_vec(),
+    }
+}
+
+fn user_regs_handler(tf: &mut TrapFrame, addr: usize) {
+    if USER_CAPTURES.try_lock().map_or(false, |captures| captures.enabled.contains(&addr)) {
+        send_regs(tf, addr, tf.x[2]);
+    }
+}
+
+/// `vTU<path>:<addr>`
+pub fn trace_user_registers(args: &[u8]) -> Vec<u8> {
+    let colon = match args.iter().rposition(|&ch| ch == b':') {
+        Some(colon) => colon,
+        None => return b"E01".to_vec(),
+    };
+    let (path, addr) = match (core::str::from_utf8(&args[..colon]), parse_hex(&args[colon + 1..])) {
+        (Ok(path), Some(addr)) if !path.is_empty() => (String::from(path), addr),
+        _ => return b"E01".to_vec(),
+    };
+    let mut captures = USER_CAPTURES.lock();
+    let probe = (path, addr);
+    if !captures.registered.contains(&probe) {
+        let ret = uprobe_register(
+            probe.0.clone(),
+            addr,
+            Arc::new(spin::Mutex::new(user_regs_handler)),
+            None,
+            ProbeType::SyncFunc,
+        );
+        if ret < 0 {
+            return b"E02".to_vec();
+        }
+        captures.registered.insert(probe);
+    }
+    captures.enabled.insert(addr);
+    b"OK".to_vec()
+}
+
+/// stop sending every capture, the caller unregisters the kprobes, the uprobes stay
+pub fn disable_captures() {
+    CAPTURES.lock().clear();
+    USER_CAPTURES.lock().enabled.clear();
+}
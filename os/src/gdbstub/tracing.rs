@@ -0,0 +1,589 @@
+//! gdb tracepoints: `tstart`/`tstop`, collect actions and trace frames read back with `tfind`
+//!
+//! `tstart` loads a generated eBPF program for every enabled tracepoint and attaches it to
+//! `kprobe$<addr>` with bpf_program_attach. the program only calls HELPER_GDB_COLLECT, which
+//! runs the actions and stores a trace frame into an array map. frames stay until the next
+//! `QTinit`, so they can be looked at after `tstop`. a frame is `number u32 | used u32`,
+//! then blocks: `R` and the 33 registers (x0-x31, pc), or `M`, address u64, length u16, bytes.
+
+use super::agent::{self, AgentTarget};
+use super::packet::*;
+use crate::ebpf::consts::BPF_MAP_TYPE_ARRAY;
+use crate::ebpf::context::KProbeBPFContext;
+use crate::ebpf::map::{
+    bpf_map_close, bpf_map_create, bpf_map_lookup_elem, bpf_map_update_elem, MapAttr,
+};
+use crate::ebpf::program::bpf_program_load_insns;
+use crate::ebpf::tracepoints::{bpf_program_attach, bpf_program_detach};
+use crate::ebpf::HELPER_GDB_COLLECT;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use lazy_static::*;
+
+const MAX_FRAMES: usize = 256;
+const FRAME_SIZE: usize = 1024;
+const FRAME_HEADER_SIZE: usize = 8;
+/// x0-x31 and pc
+const FRAME_REGS: usize = 33;
+const BLOCK_REGS: u8 = b'R';
+const BLOCK_MEMORY: u8 = b'M';
+/// `M` actions use this base register for absolute addresses
+const NO_BASE_REG: usize = 0xffff_ffff;
+
+enum Action {
+    Registers,
+    /// `offset` is added to the base register, if any
+    Memory {
+        base_reg: Option<usize>,
+        offset: usize,
+        len: usize,
+    },
+    Expression(Vec<u8>),
+}
+
+struct Tracepoint {
+    enabled: bool,
+    actions: Vec<Action>,
+    prog_fd: Option<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    NotRun,
+    Running,
+    Stopped,
+    /// stopped because the frame buffer is full
+    Full,
+}
+
+struct TraceState {
+    /// (number, address) -> tracepoint, gdb gives a tracepoint one entry per location
+    tracepoints: BTreeMap<(usize, usize), Tracepoint>,
+    status: Status,
+    frames_map: Option<u32>,
+    frame_count: usize,
+    /// frame selected by QTFrame
+    selected: Option<usize>,
+}
+
+lazy_static! {
+    static ref TRACE: spin::Mutex<TraceState> = spin::Mutex::new(TraceState {
+        tracepoints: BTreeMap::new(),
+        status: Status::NotRun,
+        frames_map: None,
+        frame_count: 0,
+        selected: None,
+    });
+}
+
+/// a trace frame being filled in
+struct FrameWriter<'a> {
+    regs: &'a [usize; FRAME_REGS],
+    frame: Vec<u8>,
+}
+
+impl FrameWriter<'_> {
+    fn push_block(&mut self, block: &[u8]) {
+        if self.frame.len() + block.len() <= FRAME_SIZE {
+            self.frame.extend_from_slice(block);
+        }
+    }
+
+    fn push_registers(&mut self) {
+        let mut block = Vec::with_capacity(1 + FRAME_REGS * size_of::<u64>());
+        block.push(BLOCK_REGS);
+        for &reg in self.regs.iter() {
+            block.extend_from_slice(&(reg as u64).to_le_bytes());
+        }
+        self.push_block(&block);
+    }
+
+    fn push_memory(&mut self, addr: usize, len: usize) {
+        let bytes = match self.read(addr, len) {
+            Some(bytes) => bytes,
+            None => return,
+        };
+        let mut block = Vec::with_capacity(11 + len);
+        block.push(BLOCK_MEMORY);
+        block.extend_from_slice(&(addr as u64).to_le_bytes());
+        block.extend_from_slice(&(len as u16).to_le_bytes());
+        block.extend_from_slice(&bytes);
+        self.push_block(&block);
+    }
+}
+
+impl AgentTarget for FrameWriter<'_> {
+    fn reg(&self, index: usize) -> Option<usize> {
+        self.regs.get(index).copied()
+    }
+
+    fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        if len > FRAME_SIZE {
+            return None;
+        }
+        let chunks = super::memory(None, addr, len)?;
+        Some(chunks.concat())
+    }
+
+    fn trace(&mut self, addr: usize, len: usize) {
+        self.push_memory(addr, len);
+    }
+}
+
+/// mov r2, number; call HELPER_GDB_COLLECT; mov r0, 0; exit
+fn collect_program(number: usize) -> [u64; 4] {
+    const MOV64_IMM: u64 = 0xb7;
+    const CALL: u64 = 0x85;
+    const EXIT: u64 = 0x95;
+    [
+        MOV64_IMM | 2 << 8 | (number as u32 as u64) << 32,
+        CALL | (HELPER_GDB_COLLECT as u64) << 32,
+        MOV64_IMM,
+        EXIT,
+    ]
+}
+
+/// collect a frame for tracepoint `number`, called by its program through HELPER_GDB_COLLECT
+pub fn trace_collect(ctx: *const u8, number: usize) -> i64 {
+    let ctx = unsafe { &*(ctx as *const KProbeBPFContext) };
+    let mut state = match TRACE.try_lock() {
+        Some(state) => state,
+        None => return -1,
+    };
+    let fd = match (state.status, state.frames_map) {
+        (Status::Running, Some(fd)) => fd,
+        _ => return 0,
+    };
+    if state.frame_count >= MAX_FRAMES {
+        state.status = Status::Full;
+        return 0;
+    }
+    let tracepoint = match state.tracepoints.get(&(number, ctx.probed_addr())) {
+        Some(tracepoint) => tracepoint,
+        None => return -1,
+    };
+    let mut regs = [0usize; FRAME_REGS];
+    regs[1..32].copy_from_slice(&ctx.regs().x[1..32]);
+    regs[32] = ctx.probed_addr();
+    let mut writer = FrameWriter {
+        regs: &regs,
+        frame: Vec::with_capacity(FRAME_SIZE),
+    };
+    writer
+        .frame
+        .extend_from_slice(&(number as u32).to_le_bytes());
+    writer.frame.extend_from_slice(&[0; 4]);
+    for action in tracepoint.actions.iter() {
+        match action {
+            Action::Registers => writer.push_registers(),
+            Action::Memory {
+                base_reg,
+                offset,
+                len,
+            } => {
+                let base = base_reg.map_or(0, |reg| regs.get(reg).copied().unwrap_or(0));
+                writer.push_memory(base.wrapping_add(*offset), *len);
+            }
+            Action::Expression(code) => {
+                let _ = agent::eval(code, &mut writer);
+            }
+        }
+    }
+    let mut frame = writer.frame;
+    let used = frame.len() as u32;
+    frame[4..8].copy_from_slice(&used.to_le_bytes());
+    frame.resize(FRAME_SIZE, 0);
+    let key = state.frame_count as u32;
+    if bpf_map_update_elem(
+        fd,
+        &key as *const u32 as *const u8,
+        frame.as_mut_ptr(),
+        0,
+        false,
+    )
+    .is_ok()
+    {
+        state.frame_count += 1;
+    }
+    0
+}
+
+/// parse the actions of a `QTDP:-` packet
+fn parse_actions(mut s: &[u8], actions: &mut Vec<Action>) -> Option<()> {
+    let hex_run = |s: &[u8]| s.iter().take_while(|&&ch| hex_val(ch).is_some()).count();
+    while let Some((&kind, rest)) = s.split_first() {
+        s = rest;
+        match kind {
+            // register mask, every register is collected anyway
+            b'R' => {
+                s = &s[hex_run(s)..];
+                actions.push(Action::Registers);
+            }
+            // M<base reg>,<offset>,<len>
+            b'M' => {
+                let mut fields = [0usize; 3];
+                for (i, field) in fields.iter_mut().enumerate() {
+                    let (value, rest) = if s.first() == Some(&b'-') {
+                        let len = hex_run(&s[1..]);
+                        (NO_BASE_REG, &s[1 + len..])
+                    } else {
+                        let len = hex_run(s);
+                        (parse_hex(&s[..len])?, &s[len..])
+                    };
+                    *field = value;
+                    s = if i < 2 {
+                        rest.strip_prefix(b",")?
+                    } else {
+                        rest
+                    };
+                }
+                actions.push(Action::Memory {
+                    base_reg: if fields[0] >= 32 {
+                        None
+                    } else {
+                        Some(fields[0])
+                    },
+                    offset: fields[1],
+                    len: fields[2],
+                });
+            }
+            // X<len>,<bytecode>
+            b'X' => {
+                let len_digits = hex_run(s);
+                let len = parse_hex(&s[..len_digits])?;
+                s = s[len_digits..].strip_prefix(b",")?;
+                let code = decode_hex_bytes(s.get(..len * 2)?)?;
+                s = &s[len * 2..];
+                actions.push(Action::Expression(code));
+            }
+            // while-stepping is not supported, collect nothing for it
+            b'S' => return Some(()),
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+impl TraceState {
+    /// `QTDP:n:addr:E:step:pass[-]` and `QTDP:-n:addr:actions[-]`
+    fn define(&mut self, args: &[u8]) -> Option<()> {
+        let args = args.strip_suffix(b"-").unwrap_or(args);
+        let (more, args) = match args.strip_prefix(b"-") {
+            Some(args) => (true, args),
+            None => (false, args),
+        };
+        let mut fields = args.splitn(3, |&ch| ch == b':');
+        let number = parse_hex(fields.next()?)?;
+        let addr = parse_hex(fields.next()?)?;
+        let rest = fields.next()?;
+        if more {
+            let tracepoint = self.tracepoints.get_mut(&(number, addr))?;
+            return parse_actions(rest, &mut tracepoint.actions);
+        }
+        self.tracepoints.insert(
+            (number, addr),
+            Tracepoint {
+                enabled: rest.first() == Some(&b'E'),
+                actions: Vec::new(),
+                prog_fd: None,
+            },
+        );
+        Some(())
+    }
+
+    fn clear_frames(&mut self) {
+        if let Some(fd) = self.frames_map.take() {
+            let _ = bpf_map_close(fd);
+        }
+        self.frame_count = 0;
+        self.selected = None;
+    }
+
+    fn start(&mut self) -> Vec<u8> {
+        self.stop();
+        self.clear_frames();
+        let attr = MapAttr {
+            map_type: BPF_MAP_TYPE_ARRAY,
+            key_size: size_of::<u32>() as u32,
+            value_size: FRAME_SIZE as u32,
+            max_entries: MAX_FRAMES as u32,
+        };
+        match bpf_map_create(attr) {
+            Ok(fd) => self.frames_map = Some(fd as u32),
+            Err(_) => return b"E01".to_vec(),
+        }
+        let mut error = None;
+        for (&(number, addr), tracepoint) in self.tracepoints.iter_mut() {
+            if !tracepoint.enabled {
+                continue;
+            }
+            let name = format!("gdb_tracepoint_{}", number);
+            let fd = match bpf_program_load_insns(&name, &collect_program(number)) {
+                Ok(fd) => fd as u32,
+                Err(_) => {
+                    error = Some(b"E02");
+                    break;
+                }
+            };
+            tracepoint.prog_fd = Some(fd);
+            if bpf_program_attach(&format!("kprobe${:#x}", addr), fd).is_err() {
+                warn!(
+                    "gdbstub: can not attach tracepoint {} at {:#x}",
+                    number, addr
+                );
+                error = Some(b"E03");
+                break;
+            }
+        }
+        if let Some(error) = error {
+            // detach the tracepoints started before
+            self.stop();
+            return error.to_vec();
+        }
+        self.status = Status::Running;
+        b"OK".to_vec()
+    }
+
+    fn stop(&mut self) {
+        for tracepoint in self.tracepoints.values_mut() {
+            if let Some(fd) = tracepoint.prog_fd.take() {
+                let _ = bpf_program_detach(fd);
+            }
+        }
+        if self.status == Status::Running {
+            self.status = Status::Stopped;
+        }
+    }
+
+    fn status(&self) -> Vec<u8> {
+        let state = match self.status {
+            Status::NotRun => "T0;tnotrun:0",
+            Status::Running => "T1",
+            Status::Stopped => "T0;tstop::0",
+            Status::Full => "T0;tfull:0",
+        };
+        let free = (MAX_FRAMES - self.frame_count) * FRAME_SIZE;
+        format!(
+            "{};tframes:{:x};tcreated:{:x};tfree:{:x};tsize:{:x};circular:0;disconn:0",
+            state,
+            self.frame_count,
+            self.frame_count,
+            free,
+            MAX_FRAMES * FRAME_SIZE
+        )
+        .into_bytes()
+    }
+
+    fn read_frame(&self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.frame_count {
+            return None;
+        }
+        let mut frame = alloc::vec![0u8; FRAME_SIZE];
+        let key = index as u32;
+        bpf_map_lookup_elem(
+            self.frames_map?,
+            &key as *const u32 as *const u8,
+            frame.as_mut_ptr(),
+            0,
+            false,
+        )
+        .ok()?;
+        let used = u32::from_le_bytes(frame[4..8].try_into().ok()?) as usize;
+        frame.truncate(used.min(FRAME_SIZE));
+        Some(frame)
+    }
+
+    /// the first frame from `start` on that `pred(number, pc)` accepts
+    fn find_frame(
+        &self,
+        start: usize,
+        pred: impl Fn(usize, usize) -> bool,
+    ) -> Option<(usize, usize)> {
+        (start..self.frame_count).find_map(|index| {
+            let frame = self.read_frame(index)?;
+            let number = u32::from_le_bytes(frame[..4].try_into().ok()?) as usize;
+            let pc = frame_registers(&frame).map_or(0, |regs| regs[32]);
+            if pred(number, pc) {
+                Some((index, number))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// `QTFrame:n`, `QTFrame:pc:addr`, `QTFrame:tdp:t`, `QTFrame:range:start:end`
+    /// and `QTFrame:outside:start:end`
+    fn select_frame(&mut self, args: &[u8]) -> Vec<u8> {
+        let next = self.selected.map_or(0, |index| index + 1);
+        let mut fields = args.split(|&ch| ch == b':');
+        let kind = fields.next().unwrap_or(b"");
+        let a = fields.next().and_then(parse_hex);
+        let b = fields.next().and_then(parse_hex);
+        let found = match (kind, a, b) {
+            (b"pc", Some(addr), _) => self.find_frame(next, |_, pc| pc == addr),
+            (b"tdp", Some(tp), _) => self.find_frame(next, |number, _| number == tp),
+            (b"range", Some(start), Some(end)) => {
+                self.find_frame(next, |_, pc| pc >= start && pc <= end)
+            }
+            (b"outside", Some(start), Some(end)) => {
+                self.find_frame(next, |_, pc| pc < start || pc > end)
+            }
+            // -1 (ffffffff) leaves frame selection
+            (index, _, _) => match parse_hex(index) {
+                Some(index) if index < self.frame_count => self.find_frame(index, |_, _| true),
+                _ => None,
+            },
+        };
+        match found {
+            Some((index, number)) => {
+                self.selected = Some(index);
+                format!("F{:x}T{:x}", index, number).into_bytes()
+            }
+            None => {
+                self.selected = None;
+                b"F-1".to_vec()
+            }
+        }
+    }
+}
+
+/// the registers block of a frame
+fn frame_registers(frame: &[u8]) -> Option<[usize; FRAME_REGS]> {
+    let mut pos = FRAME_HEADER_SIZE;
+    while pos < frame.len() {
+        match frame[pos] {
+            BLOCK_REGS => {
+                let mut regs = [0usize; FRAME_REGS];
+                for (i, reg) in regs.iter_mut().enumerate() {
+                    let at = pos + 1 + i * 8;
+                    *reg = u64::from_le_bytes(frame.get(at..at + 8)?.try_into().ok()?) as usize;
+                }
+                return Some(regs);
+            }
+            BLOCK_MEMORY => {
+                let len = u16::from_le_bytes(frame.get(pos + 9..pos + 11)?.try_into().ok()?);
+                pos += 11 + len as usize;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// bytes of the selected frame at [addr, addr + len), if one memory block has all of them
+fn frame_memory(frame: &[u8], addr: usize, len: usize) -> Option<&[u8]> {
+    let mut pos = FRAME_HEADER_SIZE;
+    while pos < frame.len() {
+        match frame[pos] {
+            BLOCK_REGS => pos += 1 + FRAME_REGS * 8,
+            BLOCK_MEMORY => {
+                let start =
+                    u64::from_le_bytes(frame.get(pos + 1..pos + 9)?.try_into().ok()?) as usize;
+                let block_len =
+                    u16::from_le_bytes(frame.get(pos + 9..pos + 11)?.try_into().ok()?) as usize;
+                let data = frame.get(pos + 11..pos + 11 + block_len)?;
+                if addr >= start && addr + len <= start + block_len {
+                    return Some(&data[addr - start..addr - start + len]);
+                }
+                pos += 11 + block_len;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// whether `g` and `m` are answered from a trace frame
+pub fn frame_selected() -> bool {
+    TRACE.lock().selected.is_some()
+}
+
+/// `g` on the selected frame, registers that were not collected are unavailable
+pub fn frame_read_registers() -> Vec<u8> {
+    let state = TRACE.lock();
+    let regs = state
+        .selected
+        .and_then(|index| state.read_frame(index))
+        .and_then(|frame| frame_registers(&frame));
+    let mut reply = Vec::new();
+    match regs {
+        Some(regs) => regs.iter().for_each(|&reg| push_hex_le(&mut reply, reg)),
+        None => reply.resize(FRAME_REGS * 16, b'x'),
+    }
+    reply
+}
+
+/// `m` on the selected frame
+pub fn frame_read_memory(args: &[u8]) -> Vec<u8> {
+    let state = TRACE.lock();
+    let (addr, len) = match parse_addr_len(args) {
+        Some(range) => range,
+        None => return b"E01".to_vec(),
+    };
+    let frame = match state.selected.and_then(|index| state.read_frame(index)) {
+        Some(frame) => frame,
+        None => return b"E01".to_vec(),
+    };
+    match frame_memory(&frame, addr, len) {
+        Some(bytes) => {
+            let mut reply = Vec::with_capacity(len * 2);
+            push_hex_bytes(&mut reply, bytes);
+            reply
+        }
+        None => b"E01".to_vec(),
+    }
+}
+
+/// QT* and qT* packets, None if the packet is not about tracepoints
+pub fn handle(packet: &[u8]) -> Option<Vec<u8>> {
+    let mut state = TRACE.lock();
+    let reply = if packet == b"QTinit" {
+        state.stop();
+        state.clear_frames();
+        state.tracepoints.clear();
+        state.status = Status::NotRun;
+        b"OK".to_vec()
+    } else if let Some(args) = packet.strip_prefix(b"QTDP:") {
+        match state.define(args) {
+            Some(()) => b"OK".to_vec(),
+            None => b"E01".to_vec(),
+        }
+    } else if packet == b"QTStart" {
+        state.start()
+    } else if packet == b"QTStop" {
+        state.stop();
+        b"OK".to_vec()
+    } else if let Some(args) = packet.strip_prefix(b"QTFrame:") {
+        state.select_frame(args)
+    } else if packet == b"qTStatus" {
+        state.status()
+    } else if packet.starts_with(b"qTfP")
+        || packet.starts_with(b"qTsP")
+        || packet.starts_with(b"qTfV")
+        || packet.starts_with(b"qTsV")
+    {
+        // nothing to upload, the kernel keeps no tracepoints gdb did not define
+        b"l".to_vec()
+    } else if packet.starts_with(b"QTDPsrc")
+        || packet.starts_with(b"QTDV")
+        || packet.starts_with(b"QTro")
+        || packet.starts_with(b"QTBuffer")
+        || packet.starts_with(b"QTDisconnected")
+        || packet.starts_with(b"QTNotes")
+    {
+        // sources, trace state variables and buffer settings are accepted and ignored
+        b"OK".to_vec()
+    } else if packet.starts_with(b"QT") || packet.starts_with(b"qT") {
+        Vec::new()
+    } else {
+        return None;
+    };
+    Some(reply)
+}
+
+/// whether `handle` may (un)register kprobes for this packet
+pub fn attaches_probes(packet: &[u8]) -> bool {
+    packet == b"QTinit" || packet == b"QTStart" || packet == b"QTStop"
+}
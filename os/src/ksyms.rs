@@ -0,0 +1,50 @@
+//! kernel symbol table for backtraces and symbol lookups
+//!
+//! the table is filled in after linking by `make kernel`: `rust-nm` lists the text symbols,
+//! sorted by address, and `rust-objcopy --update-section` writes them into `.ksymtab`.
+//! it is text, one `<hex addr> <name>` line per symbol, padded with zeros. a kernel built
+//! without that step has an empty table.
+
+/// space reserved for the table, must match KSYMTAB_SIZE in the Makefile
+const KSYMTAB_SIZE: usize = 0x80000;
+
+#[used]
+#[link_section = ".ksymtab"]
+static KSYMTAB: [u8; KSYMTAB_SIZE] = [0; KSYMTAB_SIZE];
+
+extern "C" {
+    fn sksymtab();
+    fn eksymtab();
+}
+
+/// the filled part of the table, read through the linker symbols so that the zeros
+/// the compiler sees are not folded in
+fn table() -> &'static [u8] {
+    let len = eksymtab as usize - sksymtab as usize;
+    let table = unsafe { core::slice::from_raw_parts(sksymtab as *const u8, len) };
+    let end = table.iter().position(|&b| b == 0).unwrap_or(len);
+    &table[..end]
+}
+
+/// (address, name) of every symbol, in address order
+fn symbols() -> impl Iterator<Item = (usize, &'static str)> {
+    table().split(|&b| b == b'\n').filter_map(|line| {
+        let line = core::str::from_utf8(line).ok()?;
+        let (addr, name) = line.split_once(' ')?;
+        Some((usize::from_str_radix(addr, 16).ok()?, name))
+    })
+}
+
+/// the symbol containing `addr` and the offset into it
+pub fn addr_to_symbol(addr: usize) -> Option<(&'static str, usize)> {
+    symbols()
+        .take_while(|&(start, _)| start <= addr)
+        .last()
+        .map(|(start, name)| (name, addr - start))
+}
+
+pub fn symbol_to_addr(symbol: &str) -> Option<usize> {
+    symbols()
+        .find(|&(_, name)| name == symbol)
+        .map(|(addr, _)| addr)
+}
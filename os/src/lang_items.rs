@@ -1,38 +1,175 @@
+use crate::config::{KERNEL_STACK_SIZE, PANIC_DUMP_BLOCKS};
+use crate::drivers::BLOCK_DEVICE;
+use crate::ebpf::osutil::os_kernel_addr_valid;
+use crate::ksyms::addr_to_symbol;
+use crate::probe::arch::get_kernel_trapframe_sp;
 use crate::sbi::shutdown;
-use crate::task::current_kstack_top;
+use crate::task::try_current_task;
+use crate::trap::{current_kernel_trap_cx, TrapContext};
+use crate::DEV_NON_BLOCKING_ACCESS;
 use core::arch::asm;
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use easy_fs::{BLOCK_SZ, EFS_MAGIC};
 use log::*;
+use riscv::register::{scause, sstatus, stval};
+
+/// frames printed at most, in case the frame pointer chain is corrupted into a loop
+const MAX_BACKTRACE_DEPTH: usize = 64;
+const DUMP_SIZE: usize = PANIC_DUMP_BLOCKS * BLOCK_SZ;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// the dump is printed and kept for the disk, the heap may be what panicked
+struct PanicDump {
+    buf: [u8; DUMP_SIZE],
+    len: usize,
+}
+
+static mut DUMP: PanicDump = PanicDump {
+    buf: [0; DUMP_SIZE],
+    len: 0,
+};
+
+impl Write for PanicDump {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        let len = s.len().min(DUMP_SIZE - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    if let Some(location) = info.location() {
-        error!(
-            "[kernel] Panicked at {}:{} {}",
-            location.file(),
-            location.line(),
-            info.message().unwrap()
-        );
-    } else {
-        error!("[kernel] Panicked: {}", info.message().unwrap());
-    }
     unsafe {
-        backtrace();
+        sstatus::clear_sie();
+    }
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        // e.g. a borrowed cell or the disk driver while dumping
+        error!("[kernel] Panicked again while dumping: {}", info);
+        shutdown(true)
     }
+    let dump = unsafe { &mut DUMP };
+    let _ = write_dump(dump, info);
+    save_dump(dump);
     shutdown(true)
 }
 
-unsafe fn backtrace() {
+fn write_symbol(out: &mut PanicDump, addr: usize) -> fmt::Result {
+    match addr_to_symbol(addr) {
+        Some((name, offset)) => write!(out, " <{}+{:#x}>", name, offset),
+        None => Ok(()),
+    }
+}
+
+fn write_dump(out: &mut PanicDump, info: &PanicInfo) -> fmt::Result {
+    writeln!(out, "[kernel] {}", info)?;
+    match try_current_task() {
+        Some(task) => {
+            let pid = task.process.upgrade().map(|process| process.getpid());
+            let tid = task
+                .inner
+                .try_exclusive_access()
+                .and_then(|inner| inner.res.as_ref().map(|res| res.tid));
+            writeln!(out, "pid {:?} tid {:?}", pid, tid)?;
+            if let Some(inner) = task.inner.try_exclusive_access() {
+                writeln!(out, "---USER TRAP CONTEXT---")?;
+                let tf = inner.get_trap_cx();
+                write_trap_cx(out, tf, tf.x[2])?;
+            }
+        }
+        None => writeln!(out, "no current task")?,
+    }
+    if let Some(tf) = current_kernel_trap_cx() {
+        writeln!(out, "---KERNEL TRAP CONTEXT---")?;
+        write_trap_cx(out, tf, get_kernel_trapframe_sp(tf))?;
+    }
+    writeln!(
+        out,
+        "scause {:#x} stval {:#x}",
+        scause::read().bits(),
+        stval::read()
+    )?;
+    backtrace(out)
+}
+
+/// the saved registers, `sp` is passed since kernel traps do not save it
+fn write_trap_cx(out: &mut PanicDump, tf: &TrapContext, sp: usize) -> fmt::Result {
+    for (i, name) in REG_NAMES.iter().enumerate() {
+        let reg = if i == 2 { sp } else { tf.x[i] };
+        write!(out, "{:>4} {:#018x}", name, reg)?;
+        if i % 4 == 3 {
+            writeln!(out)?;
+        } else {
+            write!(out, " ")?;
+        }
+    }
+    write!(out, "sstatus {:#x} sepc {:#x}", tf.sstatus.bits(), tf.sepc)?;
+    write_symbol(out, tf.sepc)?;
+    writeln!(out)
+}
+
+/// whether `fp` may be a frame on the current kernel stack or the boot stack
+fn frame_valid(fp: usize, kstack_top: Option<usize>) -> bool {
+    let on_kstack = kstack_top.map_or(false, |top| fp > top - KERNEL_STACK_SIZE && fp <= top);
+    fp % 8 == 0 && fp >= 16 && (on_kstack || os_kernel_addr_valid(fp - 16, 16))
+}
+
+/// walk the frame pointer chain, ra is at fp - 8 and the caller's fp at fp - 16
+fn backtrace(out: &mut PanicDump) -> fmt::Result {
     let mut fp: usize;
-    let stop = current_kstack_top();
-    asm!("mv {}, s0", out(reg) fp);
-    println!("---START BACKTRACE---");
-    for i in 0..10 {
-        if fp == stop {
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    let kstack_top = try_current_task().map(|task| task.kstack.get_top());
+    writeln!(out, "---START BACKTRACE---")?;
+    for i in 0..MAX_BACKTRACE_DEPTH {
+        if Some(fp) == kstack_top || !frame_valid(fp, kstack_top) {
+            break;
+        }
+        let ra = unsafe { *((fp - 8) as *const usize) };
+        let prev_fp = unsafe { *((fp - 16) as *const usize) };
+        write!(out, "#{}:ra={:#x}", i, ra)?;
+        write_symbol(out, ra)?;
+        writeln!(out)?;
+        // frames only get older up the stack
+        if prev_fp <= fp {
             break;
         }
-        println!("#{}:ra={:#x}", i, *((fp - 8) as *const usize));
-        fp = *((fp - 16) as *const usize);
+        fp = prev_fp;
+    }
+    writeln!(out, "---END   BACKTRACE---")
+}
+
+/// write the dump to the blocks after the end of the fs, read them back with `make panic-dump`
+fn save_dump(dump: &PanicDump) {
+    // interrupts are off, so the disk has to be polled
+    match DEV_NON_BLOCKING_ACCESS.try_exclusive_access() {
+        Some(mut non_blocking) => *non_blocking = false,
+        None => return,
+    }
+    let mut block = [0u8; BLOCK_SZ];
+    BLOCK_DEVICE.read_block(0, &mut block);
+    let magic = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+    let total_blocks = u32::from_le_bytes([block[4], block[5], block[6], block[7]]) as usize;
+    if magic != EFS_MAGIC {
+        return;
+    }
+    for (i, chunk) in dump.buf.chunks(BLOCK_SZ).enumerate() {
+        BLOCK_DEVICE.write_block(total_blocks + i, chunk);
     }
-    println!("---END   BACKTRACE---");
+    println!(
+        "[kernel] panic dump written to blocks {}..{}",
+        total_blocks,
+        total_blocks + PANIC_DUMP_BLOCKS
+    );
 }
@@ -1,18 +1,222 @@
 //! Global logger
+//!
+//! every record that passes the filters is kept in a ring buffer, read (and cleared) by
+//! sys_klog_read, e.g. with `dmesg`. only records at or above the console level are also
+//! printed, so a verbose LOG does not flood the serial port.
+//!
+//! a record passes if its level is at or above the filter of the longest module prefix
+//! of its target, or the max level if no module filter matches. all of them can be
+//! changed at runtime with sys_klog_filter, LOG only sets the initial max level.
 
+use crate::probe::osutils::{current_hart_id, current_time_ns};
+use crate::task::try_current_task;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
 use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+
+/// entries in the ring buffer, the oldest ones are overwritten when full
+pub const LOG_RING_SIZE: usize = 1024;
+/// longer messages are truncated
+pub const LOG_MSG_SIZE: usize = 192;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// one record in the ring buffer, `timestamp` is in ns, `level` is 1 (error) to 5 (trace)
+/// and `pid` is usize::MAX if there was no current process
+pub struct LogEntry {
+    pub timestamp: u64,
+    pub pid: usize,
+    pub hart: u32,
+    pub level: u32,
+    pub len: u32,
+    pub msg: [u8; LOG_MSG_SIZE],
+}
+
+impl Default for LogEntry {
+    fn default() -> Self {
+        Self {
+            timestamp: 0,
+            pid: usize::MAX,
+            hart: 0,
+            level: 0,
+            len: 0,
+            msg: [0; LOG_MSG_SIZE],
+        }
+    }
+}
+
+impl Write for LogEntry {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.len as usize;
+        let n = s.len().min(LOG_MSG_SIZE - len);
+        self.msg[len..len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n as u32;
+        Ok(())
+    }
+}
+
+/// the ring is static, a record may be logged before the heap is set up or while it is
+/// locked. unused slots are never read, zeroed they keep the ring in .bss
+const UNUSED_ENTRY: LogEntry = LogEntry {
+    timestamp: 0,
+    pid: 0,
+    hart: 0,
+    level: 0,
+    len: 0,
+    msg: [0; LOG_MSG_SIZE],
+};
+
+struct LogRing {
+    entries: [LogEntry; LOG_RING_SIZE],
+    head: usize,
+    len: usize,
+    /// entries overwritten before they were read
+    lost: usize,
+}
+
+impl LogRing {
+    const fn new() -> Self {
+        Self {
+            entries: [UNUSED_ENTRY; LOG_RING_SIZE],
+            head: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    fn push(&mut self, entry: LogEntry) {
+        self.entries[self.head] = entry;
+        self.head = (self.head + 1) % LOG_RING_SIZE;
+        if self.len == LOG_RING_SIZE {
+            self.lost += 1;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// move the oldest entries into `buf`
+    fn pop_into(&mut self, buf: &mut [LogEntry]) -> usize {
+        let n = self.len.min(buf.len());
+        let tail = (self.head + LOG_RING_SIZE - self.len) % LOG_RING_SIZE;
+        for (i, slot) in buf.iter_mut().take(n).enumerate() {
+            *slot = self.entries[(tail + i) % LOG_RING_SIZE];
+        }
+        self.len -= n;
+        n
+    }
+}
+
+static RING: Mutex<LogRing> = Mutex::new(LogRing::new());
+/// LevelFilter as usize
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Off as usize);
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Warn as usize);
+
+lazy_static! {
+    /// module path -> filter
+    static ref MODULE_FILTERS: Mutex<Vec<(String, LevelFilter)>> = Mutex::new(Vec::new());
+}
+
+/// 0 (off) to 5 (trace)
+pub fn level_filter(level: usize) -> Option<LevelFilter> {
+    match level {
+        0 => Some(LevelFilter::Off),
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        5 => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+fn load_level(level: &AtomicUsize) -> LevelFilter {
+    level_filter(level.load(Ordering::Relaxed)).unwrap_or(LevelFilter::Off)
+}
+
+/// the filter of `target`, the max level while the module filters are being changed
+fn target_filter(target: &str) -> LevelFilter {
+    let max_level = load_level(&MAX_LEVEL);
+    let filters = match MODULE_FILTERS.try_lock() {
+        Some(filters) => filters,
+        None => return max_level,
+    };
+    filters
+        .iter()
+        .filter(|(module, _)| {
+            target
+                .strip_prefix(module.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(module, _)| module.len())
+        .map_or(max_level, |&(_, filter)| filter)
+}
+
+/// the log crate skips records above its max level, so it is the most verbose filter
+fn update_log_max_level() {
+    let module_max = MODULE_FILTERS.lock().iter().map(|&(_, filter)| filter).max();
+    let max_level = load_level(&MAX_LEVEL);
+    log::set_max_level(module_max.map_or(max_level, |filter| filter.max(max_level)));
+}
+
+pub fn set_max_level(filter: LevelFilter) {
+    MAX_LEVEL.store(filter as usize, Ordering::Relaxed);
+    update_log_max_level();
+}
+
+pub fn set_console_level(filter: LevelFilter) {
+    CONSOLE_LEVEL.store(filter as usize, Ordering::Relaxed);
+}
+
+/// filter the records of `module` and its submodules, None removes the filter
+pub fn set_module_filter(module: &str, filter: Option<LevelFilter>) {
+    {
+        let mut filters = MODULE_FILTERS.lock();
+        filters.retain(|(name, _)| name != module);
+        if let Some(filter) = filter {
+            filters.push((String::from(module), filter));
+        }
+    }
+    update_log_max_level();
+}
+
+/// move the oldest records into `buf`, returns the number moved
+pub fn log_read(buf: &mut [LogEntry]) -> usize {
+    RING.lock().pop_into(buf)
+}
 
 /// a simple logger
 struct SimpleLogger;
 
 impl Log for SimpleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= target_filter(metadata.target())
     }
     fn log(&self, record: &Record) {
         if !self.enabled(record.metadata()) {
             return;
         }
+        let mut entry = LogEntry {
+            timestamp: current_time_ns(),
+            // the logger may run while the processor is borrowed
+            pid: try_current_task()
+                .and_then(|task| task.process.upgrade())
+                .map_or(usize::MAX, |process| process.getpid()),
+            hart: current_hart_id() as u32,
+            level: record.level() as u32,
+            ..LogEntry::default()
+        };
+        let _ = write!(entry, "{}: {}", record.target(), record.args());
+        // a record logged while the ring is read, e.g. by a probe handler, is dropped
+        if let Some(mut ring) = RING.try_lock() {
+            ring.push(entry);
+        }
+        if record.level() > load_level(&CONSOLE_LEVEL) {
+            return;
+        }
         let color = match record.level() {
             Level::Error => 31, // Red
             Level::Warn => 93,  // BrightYellow
@@ -34,7 +238,7 @@ impl Log for SimpleLogger {
 pub fn init() {
     static LOGGER: SimpleLogger = SimpleLogger;
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
+    set_max_level(match option_env!("LOG") {
         Some("ERROR") => LevelFilter::Error,
         Some("WARN") => LevelFilter::Warn,
         Some("INFO") => LevelFilter::Info,
@@ -22,6 +22,8 @@ mod board;
 
 #[macro_use]
 mod console;
+#[macro_use]
+mod probe;
 mod config;
 mod drivers;
 mod fs;
@@ -34,8 +36,9 @@ mod syscall;
 mod task;
 mod timer;
 mod trap;
-mod probe;
 mod ebpf;
+mod gdbstub;
+mod ksyms;
 mod logging;
 
 use crate::drivers::chardev::CharDevice;
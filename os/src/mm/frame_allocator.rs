@@ -51,6 +51,9 @@ impl StackFrameAllocator {
         self.end = r.0;
         // println!("last {} Physical Frames.", self.end - self.current);
     }
+    pub fn free_frames(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
@@ -108,6 +111,16 @@ pub fn init_frame_allocator() {
     );
 }
 
+/// (total, free) frames, the total does not count the frames of the kernel image
+pub fn frame_stats() -> (usize, usize) {
+    extern "C" {
+        fn ekernel();
+    }
+    let start: PhysPageNum = PhysAddr::from(ekernel as usize).ceil();
+    let end: PhysPageNum = PhysAddr::from(MEMORY_END).floor();
+    (end.0 - start.0, FRAME_ALLOCATOR.exclusive_access().free_frames())
+}
+
 pub fn frame_alloc() -> Option<FrameTracker> {
     FRAME_ALLOCATOR
         .exclusive_access()
@@ -19,6 +19,12 @@ pub fn init_heap() {
     }
 }
 
+/// (total, allocated) bytes of the kernel heap
+pub fn heap_stats() -> (usize, usize) {
+    let heap = HEAP_ALLOCATOR.lock();
+    (heap.stats_total_bytes(), heap.stats_alloc_actual())
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
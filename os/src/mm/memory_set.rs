@@ -48,6 +48,9 @@ impl MemorySet {
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
+    pub fn areas(&self) -> &[MapArea] {
+        &self.areas
+    }
     /// Assume that no conflicts.
     pub fn insert_framed_area(
         &mut self,
@@ -292,6 +295,22 @@ impl MapArea {
             map_perm,
         }
     }
+    pub fn start_va(&self) -> VirtAddr {
+        self.vpn_range.get_start().into()
+    }
+    pub fn end_va(&self) -> VirtAddr {
+        self.vpn_range.get_end().into()
+    }
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+    pub fn map_perm(&self) -> MapPermission {
+        self.map_perm
+    }
+    /// pages backed by frames of this area, identical and linear maps have none
+    pub fn frame_count(&self) -> usize {
+        self.data_frames.len()
+    }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
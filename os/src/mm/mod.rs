@@ -6,12 +6,14 @@ mod page_table;
 
 pub use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_alloc_more, frame_dealloc, FrameTracker,raw_frame_alloc,raw_frame_dealloc};
+pub use frame_allocator::{frame_alloc, frame_alloc_more, frame_dealloc, frame_stats, FrameTracker,raw_frame_alloc,raw_frame_dealloc};
+pub use heap_allocator::heap_stats;
 pub use memory_set::remap_test;
 pub use memory_set::{kernel_token, MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE};
 use page_table::PTEFlags;
 pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
+    translated_byte_buffer, translated_ref, try_translated_byte_buffer, try_translated_copy,
+    translated_refmut, translated_str, PageTable,
     PageTableEntry, UserBuffer, UserBufferIterator,
 };
 
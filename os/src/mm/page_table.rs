@@ -164,6 +164,55 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     v
 }
 
+/// like `translated_byte_buffer`, but None if any page of the range is not mapped
+pub fn try_translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Option<Vec<&'static mut [u8]>> {
+    let page_table = PageTable::from_token(token);
+    let mut start = ptr as usize;
+    let end = start.checked_add(len)?;
+    let mut v = Vec::new();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut vpn = start_va.floor();
+        let pte = page_table.translate(vpn).filter(|pte| pte.is_valid())?;
+        vpn.step();
+        let end_va: VirtAddr = VirtAddr::from(vpn).min(VirtAddr::from(end));
+        let bytes = pte.ppn().get_bytes_array();
+        if end_va.page_offset() == 0 {
+            v.push(&mut bytes[start_va.page_offset()..]);
+        } else {
+            v.push(&mut bytes[start_va.page_offset()..end_va.page_offset()]);
+        }
+        start = end_va.into();
+    }
+    Some(v)
+}
+
+/// like `try_translated_byte_buffer`, but copies the range into `dst` without allocating
+pub fn try_translated_copy(token: usize, ptr: *const u8, dst: &mut [u8]) -> Option<()> {
+    let page_table = PageTable::from_token(token);
+    let mut start = ptr as usize;
+    let end = start.checked_add(dst.len())?;
+    let mut copied = 0;
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut vpn = start_va.floor();
+        let pte = page_table.translate(vpn).filter(|pte| pte.is_valid())?;
+        vpn.step();
+        let end_va: VirtAddr = VirtAddr::from(vpn).min(VirtAddr::from(end));
+        let bytes = pte.ppn().get_bytes_array();
+        let len = usize::from(end_va) - start;
+        let offset = start_va.page_offset();
+        dst[copied..copied + len].copy_from_slice(&bytes[offset..offset + len]);
+        copied += len;
+        start = end_va.into();
+    }
+    Some(())
+}
+
 /// Load a string from other address spaces into kernel space without an end `\0`.
 pub fn translated_str(token: usize, ptr: *const u8) -> String {
     let page_table = PageTable::from_token(token);
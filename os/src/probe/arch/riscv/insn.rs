@@ -0,0 +1,212 @@
+//! raw instruction fields that riscv_decode does not expose (or does not decode at all),
+//! used to classify instructions that must not be executed out of line
+
+use super::trapframe::*;
+
+const OPCODE_MISC_MEM: u32 = 0b000_1111;
+const OPCODE_AMO: u32 = 0b010_1111;
+const OPCODE_SYSTEM: u32 = 0b111_0011;
+
+const AMO_LR: u32 = 0b00010;
+const AMO_SC: u32 = 0b00011;
+
+const INSN_WFI: u32 = 0x1050_0073;
+const FUNCT7_SFENCE_VMA: u32 = 0b000_1001;
+
+/// csrs clobbered by the ebreak trap itself, reading them out of line gives the wrong value
+const TRAP_CSRS: [u32; 4] = [
+    0x140, // sscratch
+    0x141, // sepc
+    0x142, // scause
+    0x143, // stval
+];
+
+/// how a 32-bit instruction outside the control flow group should be single-stepped
+pub enum RawInsnClass {
+    /// no special handling, leave it to riscv_decode
+    Normal,
+    /// safe to execute in the instruction buffer
+    Execute,
+    /// emulated as a no-op
+    Nop,
+    /// must not be probed
+    Reject,
+}
+
+/// classify fence, atomics and system instructions
+pub fn classify_insn(i: u32) -> RawInsnClass {
+    let funct3 = (i >> 12) & 0b111;
+    match i & 0x7f {
+        // fence / fence.i have no pc-relative operands
+        OPCODE_MISC_MEM => RawInsnClass::Execute,
+        // the trap between LR and SC always drops the reservation, SC would never succeed
+        OPCODE_AMO => match i >> 27 {
+            AMO_LR | AMO_SC => RawInsnClass::Reject,
+            _ => RawInsnClass::Execute,
+        },
+        OPCODE_SYSTEM if funct3 == 0 => {
+            if i == INSN_WFI {
+                // wfi is allowed to be a no-op
+                RawInsnClass::Nop
+            } else if i >> 25 == FUNCT7_SFENCE_VMA {
+                RawInsnClass::Execute
+            } else {
+                // ecall, ebreak, sret, mret and friends
+                RawInsnClass::Reject
+            }
+        }
+        OPCODE_SYSTEM => {
+            if funct3 == 0b100 || TRAP_CSRS.contains(&(i >> 20)) {
+                RawInsnClass::Reject
+            } else {
+                RawInsnClass::Execute
+            }
+        }
+        _ => RawInsnClass::Normal,
+    }
+}
+
+fn sign_extend(value: u32, bits: u32) -> isize {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as isize
+}
+
+/// compressed instructions emulated from raw bits
+#[derive(Clone, Copy)]
+pub enum RvcInsn {
+    /// c.addi4spn rd', sp, imm
+    Addi4spn { rd: u32, imm: usize },
+    /// c.addi16sp sp, imm
+    Addi16sp { imm: isize },
+    /// c.lwsp / c.ldsp rd, offset(sp)
+    LoadSp { rd: u32, offset: usize, double: bool },
+    /// c.swsp / c.sdsp rs2, offset(sp)
+    StoreSp { rs2: u32, offset: usize, double: bool },
+    /// c.beqz / c.bnez rs1', offset
+    BranchZ { rs1: u32, offset: isize, eq: bool },
+    /// c.ebreak, never probed
+    Ebreak,
+}
+
+impl RvcInsn {
+    /// a kernel trap does not restore sp from the trap frame, so an sp write can not
+    /// be emulated and the instruction is executed out of line instead
+    pub fn writes_sp(&self) -> bool {
+        match *self {
+            RvcInsn::Addi16sp { .. } => true,
+            RvcInsn::LoadSp { rd, .. } => rd == 2,
+            _ => false,
+        }
+    }
+}
+
+/// decode the compressed instructions we emulate ourselves
+pub fn decode_rvc(i: u16) -> Option<RvcInsn> {
+    let i = i as u32;
+    let funct3 = (i >> 13) & 0b111;
+    let rd = (i >> 7) & 0x1f;
+    let rs2 = (i >> 2) & 0x1f;
+    let rd_prime = ((i >> 2) & 0b111) + 8;
+    let rs1_prime = ((i >> 7) & 0b111) + 8;
+    match (i & 0b11, funct3) {
+        (0b00, 0b000) => {
+            let imm = ((i >> 7) & 0x30) | ((i >> 1) & 0x3c0) | ((i >> 4) & 0x4) | ((i >> 2) & 0x8);
+            // zero immediate is reserved, an all-zero halfword is illegal
+            if imm == 0 {
+                return None;
+            }
+            Some(RvcInsn::Addi4spn {
+                rd: rd_prime,
+                imm: imm as usize,
+            })
+        }
+        (0b01, 0b011) if rd == 2 => {
+            let imm = ((i >> 3) & 0x200)
+                | ((i >> 2) & 0x10)
+                | ((i << 1) & 0x40)
+                | ((i << 4) & 0x180)
+                | ((i << 3) & 0x20);
+            if imm == 0 {
+                return None;
+            }
+            Some(RvcInsn::Addi16sp {
+                imm: sign_extend(imm, 10),
+            })
+        }
+        (0b01, 0b110) | (0b01, 0b111) => {
+            let offset = ((i >> 4) & 0x100)
+                | ((i >> 7) & 0x18)
+                | ((i << 1) & 0xc0)
+                | ((i >> 2) & 0x6)
+                | ((i << 3) & 0x20);
+            Some(RvcInsn::BranchZ {
+                rs1: rs1_prime,
+                offset: sign_extend(offset, 9),
+                eq: funct3 == 0b110,
+            })
+        }
+        (0b10, 0b010) if rd != 0 => Some(RvcInsn::LoadSp {
+            rd,
+            offset: (((i >> 7) & 0x20) | ((i >> 2) & 0x1c) | ((i << 4) & 0xc0)) as usize,
+            double: false,
+        }),
+        (0b10, 0b011) if rd != 0 => Some(RvcInsn::LoadSp {
+            rd,
+            offset: (((i >> 7) & 0x20) | ((i >> 2) & 0x18) | ((i << 4) & 0x1c0)) as usize,
+            double: true,
+        }),
+        (0b10, 0b100) if i == 0x9002 => Some(RvcInsn::Ebreak),
+        (0b10, 0b110) => Some(RvcInsn::StoreSp {
+            rs2,
+            offset: (((i >> 7) & 0x3c) | ((i >> 1) & 0xc0)) as usize,
+            double: false,
+        }),
+        (0b10, 0b111) => Some(RvcInsn::StoreSp {
+            rs2,
+            offset: (((i >> 7) & 0x38) | ((i >> 1) & 0x1c0)) as usize,
+            double: true,
+        }),
+        _ => None,
+    }
+}
+
+/// emulate a compressed instruction at `pc` that was hit in the kernel, sets pc to the
+/// next instruction. instructions that write sp are not emulated, see `writes_sp`
+pub fn emulate_rvc(tf: &mut TrapFrame, insn: RvcInsn, pc: usize) {
+    // the saved x2 is garbage
+    let sp = get_kernel_trapframe_sp(tf);
+    let mut next_pc = pc + 2;
+    match insn {
+        RvcInsn::Addi4spn { rd, imm } => set_reg(tf, rd, sp + imm),
+        RvcInsn::Addi16sp { imm } => panic!("c.addi16sp sp, {} is executed out of line", imm),
+        RvcInsn::LoadSp { rd, offset, double } => {
+            let addr = sp + offset;
+            let val = unsafe {
+                if double {
+                    *(addr as *const u64) as usize
+                } else {
+                    *(addr as *const i32) as isize as usize
+                }
+            };
+            set_reg(tf, rd, val);
+        }
+        RvcInsn::StoreSp { rs2, offset, double } => {
+            let addr = sp + offset;
+            let val = if rs2 == 2 { sp } else { get_reg(tf, rs2) };
+            unsafe {
+                if double {
+                    *(addr as *mut u64) = val as u64;
+                } else {
+                    *(addr as *mut u32) = val as u32;
+                }
+            }
+        }
+        RvcInsn::BranchZ { rs1, offset, eq } => {
+            if (get_reg(tf, rs1) == 0) == eq {
+                next_pc = (pc as isize + offset) as usize;
+            }
+        }
+        RvcInsn::Ebreak => panic!("c.ebreak can not be emulated"),
+    }
+    set_trapframe_pc(tf, next_pc);
+}
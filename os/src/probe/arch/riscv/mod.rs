@@ -1,4 +1,4 @@
-use core::arch::{asm};
+use core::arch::{asm, global_asm};
 use riscv_decode::{CompressedInstruction::*, Instruction::*, *};
 pub mod trapframe;
 
@@ -8,6 +8,53 @@ use super::kprobes::SingleStepType::{self, *};
 mod breakpoint;
 pub use breakpoint::*;
 
+mod trigger;
+pub use trigger::*;
+
+mod insn;
+use insn::{classify_insn, decode_rvc, emulate_rvc, RawInsnClass, RvcInsn};
+
+global_asm!(include_str!("ftrace.S"));
+
+extern "C" {
+    fn ftrace_caller();
+}
+
+/// bytes of patchable nops at each function entry, `-Z patchable-function-entry=4` with RVC
+pub const FTRACE_SITE_LEN: usize = 8;
+const C_NOP: u16 = 0x0001;
+const REG_T0: u32 = 5;
+
+/// `auipc t0, hi; jalr t0, lo(t0)` at `site` calling `target`, ra of the traced function is kept
+fn ftrace_call_insns(site: usize, target: usize) -> [u32; 2] {
+    let offset = target.wrapping_sub(site) as isize;
+    let hi = ((offset + 0x800) >> 12) as u32 & 0xfffff;
+    let lo = offset as u32 & 0xfff;
+    [
+        (hi << 12) | (REG_T0 << 7) | 0x17,
+        (lo << 20) | (REG_T0 << 15) | (REG_T0 << 7) | 0x67,
+    ]
+}
+
+/// switch a function entry between the nops and a call to ftrace_caller
+/// returns false if `site` does not hold what we expect to replace
+pub fn patch_ftrace_site(site: usize, enable: bool) -> bool {
+    let nops = [C_NOP; 4];
+    let call = ftrace_call_insns(site, ftrace_caller as usize);
+    let current = unsafe { *(site as *const [u16; 4]) };
+    let (expected, new) = if enable {
+        (nops, unsafe { core::mem::transmute::<[u32; 2], [u16; 4]>(call) })
+    } else {
+        (unsafe { core::mem::transmute::<[u32; 2], [u16; 4]>(call) }, nops)
+    };
+    if current != expected {
+        return false;
+    }
+    byte_copy(site, new.as_ptr() as usize, FTRACE_SITE_LEN);
+    invalidate_icache();
+    true
+}
+
 pub use super::osutils;
 use osutils::*;
 
@@ -61,28 +108,66 @@ pub fn invalidate_icache() {
     }
 }
 
+/// whether the instruction at `addr` is an ebreak or c.ebreak
+pub fn is_ebreak(addr: usize) -> bool {
+    const EBREAK: u32 = 0x0010_0073;
+    let i = unsafe { *(addr as *const u16) };
+    if instruction_length(i) == 2 {
+        i == 0x9002
+    } else {
+        unsafe { *(addr as *const u32) == EBREAK }
+    }
+}
+
 pub fn get_insn_length(addr: usize) -> usize {
     let i = unsafe { *(addr as *const u16) };
     instruction_length(i)
 }
 
+/// decide how the instruction at `addr` is single-stepped
+/// * pc-relative instructions and sp-relative compressed instructions are emulated,
+///   except the ones writing sp
+/// * LR/SC, trap related csrs and privileged instructions are rejected
 pub fn get_insn_type(addr: usize) -> SingleStepType {
     let len = get_insn_length(addr);
     if len != 2 && len != 4 {
         return Unsupported;
     }
 
-    let i = unsafe { *(addr as *const u32) };
+    if len == 2 {
+        let c = unsafe { *(addr as *const u16) };
+        match decode_rvc(c) {
+            Some(RvcInsn::Ebreak) => return Unsupported,
+            Some(insn) if insn.writes_sp() => return Execute,
+            Some(_) => return Emulate,
+            None => {}
+        }
+    } else {
+        let i = unsafe { *(addr as *const u32) };
+        match classify_insn(i) {
+            RawInsnClass::Execute => return Execute,
+            RawInsnClass::Nop => return Emulate,
+            RawInsnClass::Reject => return Unsupported,
+            RawInsnClass::Normal => {}
+        }
+    }
+
+    // only read 2 bytes of a compressed instruction, it may be the last one in the section
+    let i = if len == 2 {
+        unsafe { *(addr as *const u16) as u32 }
+    } else {
+        unsafe { *(addr as *const u32) }
+    };
     match decode(i) {
         Ok(insn) => {
             match insn {
                 Auipc(_) | Jal(_) | Jalr(_) | Beq(_) | Bne(_) | Blt(_) | Bge(_) | Bltu(_)
                 | Bgeu(_) => Emulate,
                 Compressed(c_insn) => match c_insn {
-                    CJ(_) | CJr(_) | CJalr(_) | CBeqz(_) | CBnez(_) => Emulate,
+                    CJ(_) | CJr(_) | CJalr(_) => Emulate,
                     _ => Execute,
                 },
-                _ => Execute, // TODO: handle priviledged instructions
+                _ => Execute,
             }
         }
         Err(_err) => Unsupported,
@@ -94,10 +179,34 @@ pub fn get_insn_type(addr: usize) -> SingleStepType {
 //     i + 8
 // }
 
+/// branch to `pc + offset` if `taken`, otherwise fall through the 4-byte instruction
+fn emulate_branch(tf: &mut TrapFrame, pc: usize, offset: isize, taken: bool) {
+    if taken {
+        set_trapframe_pc(tf, (pc as isize + offset) as usize);
+    } else {
+        set_trapframe_pc(tf, pc + 4);
+    }
+}
+
 pub fn emulate_execution(tf: &mut TrapFrame, insn_addr: usize, pc: usize) {
+    if get_insn_length(insn_addr) == 2 {
+        if let Some(c_insn) = decode_rvc(unsafe { *(insn_addr as *const u16) }) {
+            emulate_rvc(tf, c_insn, pc);
+            return;
+        }
+    }
     let i = unsafe { *(insn_addr as *const u32) };
+    if let RawInsnClass::Nop = classify_insn(i) {
+        set_trapframe_pc(tf, pc + 4);
+        return;
+    }
     let insn = decode(i).unwrap();
     match insn {
+        Auipc(u_type) => {
+            let offset = u_type.imm() as i32 as isize;
+            set_reg(tf, u_type.rd(), (pc as isize + offset) as usize);
+            set_trapframe_pc(tf, pc + 4);
+        }
         Jal(j_type) => {
             let offset = j_type.imm() as isize;
             set_trapframe_pc(tf, pc + offset as usize);
@@ -128,6 +237,26 @@ pub fn emulate_execution(tf: &mut TrapFrame, insn_addr: usize, pc: usize) {
                 set_trapframe_pc(tf, pc + 4);
             }
         }
+        Blt(b_type) => {
+            let rs1 = get_reg(tf, b_type.rs1()) as isize;
+            let rs2 = get_reg(tf, b_type.rs2()) as isize;
+            emulate_branch(tf, pc, b_type.imm() as isize, rs1 < rs2);
+        }
+        Bge(b_type) => {
+            let rs1 = get_reg(tf, b_type.rs1()) as isize;
+            let rs2 = get_reg(tf, b_type.rs2()) as isize;
+            emulate_branch(tf, pc, b_type.imm() as isize, rs1 >= rs2);
+        }
+        Bltu(b_type) => {
+            let rs1 = get_reg(tf, b_type.rs1());
+            let rs2 = get_reg(tf, b_type.rs2());
+            emulate_branch(tf, pc, b_type.imm() as isize, rs1 < rs2);
+        }
+        Bgeu(b_type) => {
+            let rs1 = get_reg(tf, b_type.rs1());
+            let rs2 = get_reg(tf, b_type.rs2());
+            emulate_branch(tf, pc, b_type.imm() as isize, rs1 >= rs2);
+        }
         Compressed(c_insn) => match c_insn {
             CJ(cj_type) => {
                 let offset = cj_type.imm() as isize;
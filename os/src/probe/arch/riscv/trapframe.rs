@@ -1,5 +1,14 @@
 pub use crate::trap::TrapContext as TrapFrame;
 
+/// a kernel trap saves x0-x31, sstatus and sepc just below the sp of the trapped code,
+/// but not that sp
+const KERNEL_TRAPFRAME_SIZE: usize = 34 * 8;
+
+/// sp of the kernel code `tf` was saved from, the saved x2 is garbage
+pub fn get_kernel_trapframe_sp(tf: &TrapFrame) -> usize {
+    tf as *const TrapFrame as usize + KERNEL_TRAPFRAME_SIZE
+}
+
 pub fn get_trapframe_pc(tf: &TrapFrame) -> usize {
     tf.sepc
 }
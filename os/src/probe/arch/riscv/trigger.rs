@@ -0,0 +1,135 @@
+//! data triggers (Sdtrig mcontrol) installed through the SBI debug triggers extension.
+//! tselect/tdata* are M-mode csrs, so S-mode can only reach them via the firmware.
+
+use super::osutils::sbi_call;
+use alloc::vec::Vec;
+
+const EID_DBTR: usize = 0x4442_5452;
+const EID_BASE: usize = 0x10;
+const FID_PROBE_EXTENSION: usize = 3;
+
+const FID_NUM_TRIGGERS: usize = 0;
+const FID_SET_SHMEM: usize = 1;
+const FID_INSTALL_TRIGGERS: usize = 3;
+const FID_UNINSTALL_TRIGGERS: usize = 5;
+const FID_ENABLE_TRIGGERS: usize = 6;
+const FID_DISABLE_TRIGGERS: usize = 7;
+
+// mcontrol (type 2) fields of tdata1
+const MCONTROL_TYPE: usize = 2 << 60;
+const MCONTROL_CHAIN: usize = 1 << 11;
+const MCONTROL_MATCH_SHIFT: usize = 7;
+const MCONTROL_S: usize = 1 << 4;
+pub const MCONTROL_STORE: usize = 1 << 1;
+pub const MCONTROL_LOAD: usize = 1 << 0;
+
+const MATCH_EQUAL: usize = 0;
+const MATCH_NAPOT: usize = 1;
+const MATCH_GE: usize = 2;
+const MATCH_LT: usize = 3;
+
+/// max triggers installed by one call, a range needs at most 2
+const MAX_TRIGGERS: usize = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+/// shared memory entry, `idx` is written back by install
+struct DbtrEntry {
+    idx: usize,
+    tdata1: usize,
+    tdata2: usize,
+    tdata3: usize,
+}
+
+/// shared with the firmware by physical address, the kernel is identity mapped
+static mut SHMEM: [DbtrEntry; MAX_TRIGGERS] = [DbtrEntry {
+    idx: 0,
+    tdata1: 0,
+    tdata2: 0,
+    tdata3: 0,
+}; MAX_TRIGGERS];
+
+fn mcontrol(match_type: usize, access: usize, chain: bool) -> usize {
+    let chain = if chain { MCONTROL_CHAIN } else { 0 };
+    MCONTROL_TYPE | (match_type << MCONTROL_MATCH_SHIFT) | chain | MCONTROL_S | access
+}
+
+/// check the firmware and hand it the shared memory, returns false if triggers are unavailable
+pub fn trigger_init() -> bool {
+    let (error, available) = sbi_call(EID_BASE, FID_PROBE_EXTENSION, [EID_DBTR, 0, 0]);
+    if error != 0 || available == 0 {
+        return false;
+    }
+    let (error, count) = sbi_call(EID_DBTR, FID_NUM_TRIGGERS, [mcontrol(0, 0, false), 0, 0]);
+    if error != 0 || count == 0 {
+        return false;
+    }
+    let shmem = unsafe { SHMEM.as_ptr() as usize };
+    sbi_call(EID_DBTR, FID_SET_SHMEM, [shmem, 0, 0]).0 == 0
+}
+
+/// install triggers on S-mode `access` (MCONTROL_LOAD | MCONTROL_STORE) to [addr, addr + len)
+/// a naturally aligned power-of-two range takes one trigger, others take a chained pair
+/// returns the indexes of the installed triggers
+pub fn install_data_trigger(addr: usize, len: usize, access: usize) -> Option<Vec<usize>> {
+    let entries = unsafe { &mut SHMEM };
+    let count = if len == 1 {
+        entries[0] = DbtrEntry {
+            tdata1: mcontrol(MATCH_EQUAL, access, false),
+            tdata2: addr,
+            ..Default::default()
+        };
+        1
+    } else if len.is_power_of_two() && addr % len == 0 {
+        entries[0] = DbtrEntry {
+            tdata1: mcontrol(MATCH_NAPOT, access, false),
+            tdata2: addr | (len / 2 - 1),
+            ..Default::default()
+        };
+        1
+    } else {
+        entries[0] = DbtrEntry {
+            tdata1: mcontrol(MATCH_GE, access, true),
+            tdata2: addr,
+            ..Default::default()
+        };
+        entries[1] = DbtrEntry {
+            tdata1: mcontrol(MATCH_LT, access, false),
+            tdata2: addr + len,
+            ..Default::default()
+        };
+        2
+    };
+    let (error, _) = sbi_call(EID_DBTR, FID_INSTALL_TRIGGERS, [count, 0, 0]);
+    if error != 0 {
+        return None;
+    }
+    Some(entries[..count].iter().map(|entry| entry.idx).collect())
+}
+
+/// trigger indexes as (base, mask) accepted by the firmware
+fn idx_mask(idx: &[usize]) -> (usize, usize) {
+    let base = *idx.iter().min().unwrap();
+    let mask = idx.iter().fold(0, |mask, i| mask | 1 << (i - base));
+    (base, mask)
+}
+
+pub fn uninstall_data_trigger(idx: &[usize]) {
+    let (base, mask) = idx_mask(idx);
+    sbi_call(EID_DBTR, FID_UNINSTALL_TRIGGERS, [base, mask, 0]);
+}
+
+pub fn enable_data_trigger(idx: &[usize]) {
+    let (base, mask) = idx_mask(idx);
+    sbi_call(EID_DBTR, FID_ENABLE_TRIGGERS, [base, mask, 0]);
+}
+
+pub fn disable_data_trigger(idx: &[usize]) {
+    let (base, mask) = idx_mask(idx);
+    sbi_call(EID_DBTR, FID_DISABLE_TRIGGERS, [base, mask, 0]);
+}
+
+/// address accessed by the load/store that fired a data trigger
+pub fn trigger_hit_addr() -> usize {
+    riscv::register::stval::read()
+}
@@ -0,0 +1,331 @@
+//! function entry tracer
+//!
+//! the kernel is built with `-Z patchable-function-entry=4`, so every function starts with
+//! 4 c.nop and its entry address is collected in `__patchable_function_entries`.
+//! enabling a function replaces the nops with a call to `ftrace_caller` (see arch),
+//! which calls `ftrace_handler` without trapping.
+//!
+//! a traced function can
+//! * record (timestamp, pc, caller, pid) into the ring buffer of the current hart
+//! * run fentry handlers, used by eBPF programs attached with "fentry$<addr>"
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+use super::arch::patch_ftrace_site;
+use super::kprobes::in_noprobe_crate;
+use super::osutils::{current_hart_id, current_time_ns, ftrace_sites, noprobe_ranges};
+use super::tracepoint::{SchedSwitchCtx, TracepointArgs};
+use super::{register_tracepoint, tracepoint_index};
+use crate::config::MAX_HARTS;
+
+/// entries in the ring buffer of each hart, the oldest ones are overwritten when full
+pub const FTRACE_RING_SIZE: usize = 4096;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+/// one record in the ring buffer, `timestamp` is in ns
+pub struct FtraceEntry {
+    pub timestamp: u64,
+    pub pc: usize,
+    pub caller: usize,
+    pub pid: usize,
+}
+
+#[repr(C)]
+/// context passed to fentry handlers, `args` are a0-a7 of the traced function
+pub struct FentryCtx {
+    pub pc: usize,
+    pub caller: usize,
+    pub args: [usize; 8],
+}
+
+/// handler(ctx, user_data)
+pub type FentryHandler = dyn Fn(&FentryCtx, usize) -> isize + Sync + Send;
+
+struct FtraceRing {
+    entries: Vec<FtraceEntry>,
+    /// next slot to write
+    head: usize,
+    len: usize,
+    /// entries overwritten before they were read
+    lost: usize,
+}
+
+impl FtraceRing {
+    const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            head: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    /// allocate the entries outside ftrace_handler, which must not touch the heap
+    fn prepare(&mut self) {
+        if self.entries.is_empty() {
+            self.entries.resize(FTRACE_RING_SIZE, FtraceEntry::default());
+        }
+    }
+
+    fn push(&mut self, entry: FtraceEntry) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.entries[self.head] = entry;
+        self.head = (self.head + 1) % FTRACE_RING_SIZE;
+        if self.len == FTRACE_RING_SIZE {
+            self.lost += 1;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// move the oldest entries into `buf`, returns how many are moved
+    fn drain(&mut self, buf: &mut [FtraceEntry]) -> usize {
+        let n = self.len.min(buf.len());
+        let tail = (self.head + FTRACE_RING_SIZE - self.len) % FTRACE_RING_SIZE;
+        for (i, slot) in buf.iter_mut().take(n).enumerate() {
+            *slot = self.entries[(tail + i) % FTRACE_RING_SIZE];
+        }
+        self.len -= n;
+        n
+    }
+}
+
+/// a patched function entry
+/// recording: entries are written into the ring buffer
+/// handlers: (handle, handler, user_data)
+struct FtraceSite {
+    recording: bool,
+    handlers: Vec<(usize, Arc<FentryHandler>, usize)>,
+}
+
+impl FtraceSite {
+    fn is_idle(&self) -> bool {
+        !self.recording && self.handlers.is_empty()
+    }
+}
+
+lazy_static! {
+    /// entry address -> site, only patched sites are here
+    static ref SITES: Mutex<BTreeMap<usize, FtraceSite>> = Mutex::new(BTreeMap::new());
+}
+
+static RINGS: [Mutex<FtraceRing>; MAX_HARTS] = {
+    const RING: Mutex<FtraceRing> = Mutex::new(FtraceRing::new());
+    [RING; MAX_HARTS]
+};
+
+/// set while ftrace_handler runs on a hart, functions it calls may be traced too
+static IN_HANDLER: [AtomicBool; MAX_HARTS] = {
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_HARTS]
+};
+
+/// pid running on each hart, maintained by a sched_switch handler
+static CURRENT_PID: [AtomicUsize; MAX_HARTS] = {
+    const NONE: AtomicUsize = AtomicUsize::new(usize::MAX);
+    [NONE; MAX_HARTS]
+};
+
+static FTRACE_READY: AtomicBool = AtomicBool::new(false);
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+/// called from ftrace_caller with the entry of the traced function,
+/// its return address and the saved a0-a7
+/// the traced function may hold any lock, including the heap's, so only try_lock here
+/// and never allocate
+#[no_mangle]
+#[link_section = ".text.noprobe"]
+pub extern "C" fn ftrace_handler(pc: usize, caller: usize, args: *const [usize; 8]) {
+    let hart = current_hart_id();
+    if IN_HANDLER[hart].swap(true, Ordering::Acquire) {
+        return;
+    }
+    // fails if the sites are being patched on this hart
+    if let Some(sites) = SITES.try_lock() {
+        if let Some(site) = sites.get(&pc) {
+            if site.recording {
+                if let Some(mut ring) = RINGS[hart].try_lock() {
+                    ring.push(FtraceEntry {
+                        timestamp: current_time_ns(),
+                        pc,
+                        caller,
+                        pid: CURRENT_PID[hart].load(Ordering::Relaxed),
+                    });
+                }
+            }
+            if !site.handlers.is_empty() {
+                let ctx = FentryCtx {
+                    pc,
+                    caller,
+                    args: unsafe { *args },
+                };
+                for (_, handler, user_data) in site.handlers.iter() {
+                    let _ = handler(&ctx, *user_data);
+                }
+            }
+        }
+    }
+    IN_HANDLER[hart].store(false, Ordering::Release);
+}
+
+fn ftrace_sched_switch(ctx: *const u8, _data: usize) -> isize {
+    let ctx = unsafe { &*(ctx as *const SchedSwitchCtx) };
+    CURRENT_PID[current_hart_id()].store(ctx.next_pid, Ordering::Relaxed);
+    0
+}
+
+/// allocate the ring buffers and start tracking pids on the first use of ftrace
+fn ftrace_init_once() {
+    if FTRACE_READY.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    for ring in RINGS.iter() {
+        ring.lock().prepare();
+    }
+    let index = tracepoint_index("sched:sched_switch").unwrap();
+    register_tracepoint(index, TracepointArgs::from(ftrace_sched_switch));
+}
+
+/// whether `addr` is a function entry that may be traced, the crates kprobes must not
+/// probe are patched too but excluded the same way
+fn is_traceable(addr: usize) -> bool {
+    ftrace_sites().contains(&addr)
+        && !noprobe_ranges()
+            .iter()
+            .any(|&(start, end)| addr >= start && addr < end)
+        && !in_noprobe_crate(addr)
+}
+
+/// get the site of `addr`, patching the function entry on first use
+fn get_or_patch<'a>(
+    sites: &'a mut BTreeMap<usize, FtraceSite>,
+    addr: usize,
+) -> Option<&'a mut FtraceSite> {
+    if !sites.contains_key(&addr) {
+        if !is_traceable(addr) || !patch_ftrace_site(addr, true) {
+            return None;
+        }
+        sites.insert(
+            addr,
+            FtraceSite {
+                recording: false,
+                handlers: Vec::new(),
+            },
+        );
+    }
+    sites.get_mut(&addr)
+}
+
+/// restore the nops of a site when nothing uses it any more. a site that can not be
+/// restored, e.g. while a kprobe is armed on its entry, stays patched and is kept, so it
+/// is tried again by the next unpatch of that site or by ftrace_disable_all
+fn unpatch_if_idle(sites: &mut BTreeMap<usize, FtraceSite>, addr: usize) {
+    if sites.get(&addr).map_or(false, |site| site.is_idle()) {
+        if patch_ftrace_site(addr, false) {
+            sites.remove(&addr);
+        } else {
+            warn!("ftrace site {:#x} can not be restored yet", addr);
+        }
+    }
+}
+
+/// record calls of the function at `addr` into the ring buffer
+/// returns false if `addr` is not a traceable function entry
+pub fn ftrace_enable(addr: usize) -> bool {
+    ftrace_init_once();
+    let mut sites = SITES.lock();
+    match get_or_patch(&mut sites, addr) {
+        Some(site) => {
+            site.recording = true;
+            true
+        }
+        None => false,
+    }
+}
+
+/// record calls of every traceable function, returns the number of functions enabled
+pub fn ftrace_enable_all() -> usize {
+    ftrace_sites()
+        .iter()
+        .filter(|&&addr| ftrace_enable(addr))
+        .count()
+}
+
+/// stop recording calls of the function at `addr`
+pub fn ftrace_disable(addr: usize) -> bool {
+    let mut sites = SITES.lock();
+    match sites.get_mut(&addr) {
+        Some(site) if site.recording => {
+            site.recording = false;
+            unpatch_if_idle(&mut sites, addr);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// stop recording calls of every function, fentry handlers are kept
+pub fn ftrace_disable_all() {
+    let addrs: Vec<usize> = SITES.lock().keys().cloned().collect();
+    for addr in addrs {
+        ftrace_disable(addr);
+    }
+    // sites kept by earlier failed restores
+    SITES
+        .lock()
+        .retain(|&addr, site| !site.is_idle() || !patch_ftrace_site(addr, false));
+}
+
+/// move recorded entries of all harts into `buf`, oldest first on each hart
+/// returns the number of entries moved
+pub fn ftrace_read(buf: &mut [FtraceEntry]) -> usize {
+    let mut n = 0;
+    for ring in RINGS.iter() {
+        n += ring.lock().drain(&mut buf[n..]);
+    }
+    n
+}
+
+/// number of entries overwritten before they were read, on all harts
+pub fn ftrace_lost() -> usize {
+    RINGS.iter().map(|ring| ring.lock().lost).sum()
+}
+
+/// run `handler` on every call of the function at `addr`
+/// returns a handle used to unregister it, or None if `addr` is not traceable
+/// handlers run in ftrace_handler, so they must not allocate when the traced function
+/// may be called with the heap locked
+pub fn register_fentry(addr: usize, handler: Arc<FentryHandler>, user_data: usize) -> Option<usize> {
+    let mut sites = SITES.lock();
+    let site = get_or_patch(&mut sites, addr)?;
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    site.handlers.push((handle, handler, user_data));
+    Some(handle)
+}
+
+/// unregister a fentry handler by the handle returned from register_fentry
+pub fn unregister_fentry(handle: usize) -> bool {
+    let mut sites = SITES.lock();
+    let addr = match sites
+        .iter()
+        .find(|(_, site)| site.handlers.iter().any(|(h, _, _)| *h == handle))
+    {
+        Some((&addr, _)) => addr,
+        None => return false,
+    };
+    sites
+        .get_mut(&addr)
+        .unwrap()
+        .handlers
+        .retain(|(h, _, _)| *h != handle);
+    unpatch_if_idle(&mut sites, addr);
+    true
+}
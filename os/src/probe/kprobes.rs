@@ -1,20 +1,31 @@
 use lock::Mutex;
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::ops::Fn;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 
 use super::arch::*;
+use super::osutils::{addr_to_symbol, kernel_text_range, noprobe_crates, noprobe_ranges};
 use super::{KProbeArgs, TrapFrame};
 
 pub type Handler = dyn Fn(&mut TrapFrame, usize) -> isize + Sync + Send;
 pub type HandlerFn = fn(&mut TrapFrame, usize) -> isize;
 
-struct KProbe {
-    addr: usize, // entry address
+/// one registration on a kprobe, identified by the handle returned from register_kprobe
+struct KProbeHandler {
+    handle: usize,
     pre_handler: Arc<Handler>,
     post_handler: Option<Arc<Handler>>,
     user_data: usize,
+}
+
+/// handlers: run in registration order, the breakpoint is removed with the last one
+struct KProbe {
+    addr: usize, // entry address
+    handlers: Vec<KProbeHandler>,
     insn_buf: InstructionBuffer,
     insn_len: usize,
     active_count: usize,
@@ -35,19 +46,24 @@ lazy_static! {
     static ref ADDR_MAP: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
 }
 
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+impl KProbeHandler {
+    pub fn new(args: KProbeArgs) -> Self {
+        Self {
+            handle: NEXT_HANDLE.fetch_add(1, Ordering::Relaxed),
+            pre_handler: args.pre_handler,
+            post_handler: args.post_handler,
+            user_data: args.user_data,
+        }
+    }
+}
+
 impl KProbe {
-    pub fn new(
-        addr: usize,
-        pre_handler: Arc<Handler>,
-        post_handler: Option<Arc<Handler>>,
-        user_data: usize,
-        emulate: bool,
-    ) -> Self {
+    pub fn new(addr: usize, emulate: bool) -> Self {
         Self {
             addr,
-            pre_handler,
-            post_handler,
-            user_data,
+            handlers: Vec::new(),
             insn_buf: InstructionBuffer::new(),
             insn_len: get_insn_length(addr),
             active_count: 0,
@@ -64,6 +80,14 @@ impl KProbe {
         invalidate_icache();
     }
 
+    fn run_post_handlers(&self, tf: &mut TrapFrame) {
+        for h in self.handlers.iter() {
+            if let Some(handler) = &h.post_handler {
+                let _ = handler(tf, h.user_data);
+            }
+        }
+    }
+
     pub fn disarm(&self) {
         // change to original instruction
         self.insn_buf.copy_out(0, self.addr, self.insn_len);
@@ -73,6 +97,7 @@ impl KProbe {
 
 /// entry of ebreak trap, returns whether this event is handled
 /// returning false means the ebreak dosen't belong to kprobes
+#[link_section = ".text.noprobe"]
 pub fn kprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     let pc = get_trapframe_pc(tf);
     let mut map = KPROBES.lock();
@@ -80,13 +105,22 @@ pub fn kprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     if let Some(probe) = map.get_mut(&pc) {
         // breakpoint hit for the first time
         probe.active_count += 1;
-        let _ = (probe.pre_handler)(tf, probe.user_data);
+        // a non-zero return value means the handler has changed pc (e.g. overrode the return value),
+        // so the probed instruction must not be executed, the remaining handlers still run
+        let mut skip = false;
+        for h in probe.handlers.iter() {
+            if (h.pre_handler)(tf, h.user_data) != 0 {
+                skip = true;
+            }
+        }
+        if skip {
+            probe.active_count -= 1;
+            return true;
+        }
         // emulate and return if instruction is emulated
         if probe.emulate {
             emulate_execution(tf, probe.insn_buf.addr(), probe.addr);
-            if let Some(handler) = &probe.post_handler {
-                let _ = handler(tf, probe.user_data);
-            }
+            probe.run_post_handlers(tf);
             probe.active_count -= 1;
             // finished probing, back to kernel handler
             return true;
@@ -101,9 +135,7 @@ pub fn kprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     // post_handler stage
     if let Some(orig_addr) = ADDR_MAP.lock().get(&pc) {
         let probe = map.get_mut(orig_addr).unwrap();
-        if let Some(handler) = &probe.post_handler {
-            let _ = handler(tf, probe.user_data);
-        }
+        probe.run_post_handlers(tf);
         probe.active_count -= 1;
         set_trapframe_pc(tf, *orig_addr + probe.insn_len);
         return true;
@@ -111,66 +143,146 @@ pub fn kprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     false
 }
 
+/// distance between the instruction boundaries kept in INSN_CHECKPOINTS
+const CHECKPOINT_STRIDE: usize = 1024;
+
+lazy_static! {
+    /// instruction boundaries found while decoding kernel text, so that later checks
+    /// decode from the nearest one instead of from the start of text
+    static ref INSN_CHECKPOINTS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+}
+
+/// whether an instruction starts at `addr`, `text_start` must be one
+/// decoding starts from the nearest known boundary below `addr`: a cached checkpoint or
+/// the start of the symbol containing it
+fn is_insn_boundary(text_start: usize, addr: usize) -> bool {
+    let mut checkpoints = INSN_CHECKPOINTS.lock();
+    let mut cur = checkpoints
+        .range(..=addr)
+        .next_back()
+        .copied()
+        .unwrap_or(text_start);
+    if let Some((_, offset)) = addr_to_symbol(addr) {
+        cur = cur.max(addr - offset);
+    }
+    let mut next_checkpoint = cur + CHECKPOINT_STRIDE;
+    while cur < addr {
+        if cur >= next_checkpoint {
+            checkpoints.insert(cur);
+            next_checkpoint = cur + CHECKPOINT_STRIDE;
+        }
+        cur += get_insn_length(cur);
+    }
+    cur == addr
+}
+
+/// whether `addr` is in a function of a crate returned by noprobe_crates
+pub fn in_noprobe_crate(addr: usize) -> bool {
+    addr_to_symbol(addr).map_or(false, |(name, _)| {
+        // trait impls are demangled as `<lock::...>::...`
+        let name = name.trim_start_matches('<');
+        noprobe_crates().iter().any(|krate| name.starts_with(krate))
+    })
+}
+
+/// check if a kprobe may be placed at `addr`:
+/// * it is in kernel .text, outside the blacklist (entry, trampoline and .text.noprobe)
+/// * it is not in the code of a blacklisted crate (e.g. the lock crate), found by symbol
+/// * it is on an instruction boundary
+///
+/// put `#[link_section = ".text.noprobe"]` on a function to blacklist it.
+/// crates are blacklisted by the kernel symbol table, a kernel built without it only
+/// has the section based blacklist
+pub fn check_kprobe_addr(addr: usize) -> bool {
+    let (start, end) = kernel_text_range();
+    if addr < start || addr >= end || addr % 2 != 0 {
+        return false;
+    }
+    if noprobe_ranges()
+        .iter()
+        .any(|&(bl_start, bl_end)| addr >= bl_start && addr < bl_end)
+    {
+        return false;
+    }
+    if in_noprobe_crate(addr) {
+        return false;
+    }
+    is_insn_boundary(start, addr)
+}
+
 /// register kprobe with args at given address
-/// multiple kprobes at the same address is not supported for now
-/// possible errors: kprobe already exist at given addr, target instruction is not supported
-pub fn register_kprobe(addr: usize, args: KProbeArgs) -> bool {
+/// several kprobes at the same address share one breakpoint, the instruction is armed on the first one
+/// returns a handle used to unregister this kprobe
+/// possible errors: target instruction is not supported (e.g. LR/SC, sret, trap csrs),
+/// addr rejected by check_kprobe_addr
+pub fn register_kprobe(addr: usize, args: KProbeArgs) -> Option<usize> {
+    if !check_kprobe_addr(addr) {
+        return None;
+    }
     let mut map = KPROBES.lock();
-    if map.contains_key(&addr) {
-        return false;
+    if let Some(probe) = map.get_mut(&addr) {
+        let handler = KProbeHandler::new(args);
+        let handle = handler.handle;
+        probe.handlers.push(handler);
+        return Some(handle);
     }
 
     let insn_type = get_insn_type(addr);
     if insn_type == SingleStepType::Unsupported {
-        return false;
+        return None;
     }
 
     let emulate = insn_type == SingleStepType::Emulate;
-    let probe = KProbe::new(
-        addr,
-        args.pre_handler,
-        args.post_handler,
-        args.user_data,
-        emulate,
-    );
+    let mut probe = KProbe::new(addr, emulate);
+    let handler = KProbeHandler::new(args);
+    let handle = handler.handle;
+    probe.handlers.push(handler);
     // bp in inst buffer, will be executed if inst not emulated
     let next_bp_addr = probe.insn_buf.addr() + probe.insn_len;
     probe.arm();
     ADDR_MAP.lock().insert(next_bp_addr, addr);
     map.insert(addr, probe);
-    true
+    Some(handle)
 }
 
-/// unregister kprobe at given address
-/// possible errors: kprobe not exist at given addr, kprobe is still active(post handler not executed)
-pub fn unregister_kprobe(addr: usize) -> bool {
+/// unregister the kprobe with the handle returned from register_kprobe
+/// the breakpoint is removed together with the last kprobe at that address
+/// possible errors: handle not found, the last kprobe is still active(post handler not executed)
+pub fn unregister_kprobe(handle: usize) -> bool {
     let mut map = KPROBES.lock();
-    if let Some(probe) = map.get(&addr) {
+    let addr = match map
+        .values()
+        .find(|probe| probe.handlers.iter().any(|h| h.handle == handle))
+    {
+        Some(probe) => probe.addr,
+        None => return false,
+    };
+    let probe = map.get_mut(&addr).unwrap();
+    if probe.handlers.len() == 1 {
         if probe.active_count > 0 {
-            false
-        } else {
-            probe.disarm();
-            map.remove(&addr).unwrap();
-            true
+            return false;
         }
+        probe.disarm();
+        ADDR_MAP
+            .lock()
+            .remove(&(probe.insn_buf.addr() + probe.insn_len));
+        map.remove(&addr).unwrap();
     } else {
-        false
+        probe.handlers.retain(|h| h.handle != handle);
     }
+    true
 }
 
-use super::osutils::symbol_to_addr;
-pub fn register_kprobe_with_symbol(symbol: &str, args: KProbeArgs) -> bool {
-    if let Some(addr) = symbol_to_addr(symbol) {
-        register_kprobe(addr, args)
-    } else {
-        false
-    }
+/// (address, number of handlers) of every kprobe
+pub fn kprobe_list() -> Vec<(usize, usize)> {
+    KPROBES
+        .lock()
+        .values()
+        .map(|probe| (probe.addr, probe.handlers.len()))
+        .collect()
 }
 
-pub fn unregister_kprobe_with_symbol(symbol: &str) -> bool {
-    if let Some(addr) = symbol_to_addr(symbol) {
-        unregister_kprobe(addr)
-    } else {
-        false
-    }
+use super::osutils::symbol_to_addr;
+pub fn register_kprobe_with_symbol(symbol: &str, args: KProbeArgs) -> Option<usize> {
+    symbol_to_addr(symbol).and_then(|addr| register_kprobe(addr, args))
 }
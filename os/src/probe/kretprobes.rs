@@ -1,19 +1,23 @@
 use lock::Mutex;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 
 use super::arch::{
-    alloc_breakpoint, free_breakpoint, get_trapframe_pc, get_trapframe_ra, set_trapframe_pc,
-    set_trapframe_ra,
+    alloc_breakpoint, free_breakpoint, get_kernel_trapframe_sp, get_trapframe_pc, get_trapframe_ra,
+    set_trapframe_pc, set_trapframe_ra,
 };
 use super::kprobes::{register_kprobe, unregister_kprobe, Handler};
+use super::osutils::current_task_key;
 use super::{KProbeArgs, KRetProbeArgs, TrapFrame};
 
 /// instances: the function is entered but has not returned, leaving the probe hanging
 /// instance_limit: the maximum number of instances allowed, limits probing of recursive functions
 /// misses: the number of times the instance limit was reached and retprobe was not executed
 struct KRetProbe {
+    /// None while register_kretprobe is registering the kprobe
+    kprobe_handle: Option<usize>,
     entry_handler: Option<Arc<Handler>>,
     exit_handler: Arc<Handler>,
     instance_limit: usize,
@@ -23,22 +27,27 @@ struct KRetProbe {
 }
 
 struct KRetProbeInstance {
-    pub entry_addr: usize, // used to obtain associated KRetProbe
+    pub entry_addr: Option<usize>, // used to obtain associated KRetProbe, None once unregistered
     pub ret_addr: usize,
+    pub bp_addr: usize, // the trampoline breakpoint that replaced ret_addr
+    pub sp: usize,      // sp at entry, which is also the sp after the return
 }
 
 lazy_static! {
     /// address -> KRetProbe instance
     static ref KRETPROBES: Mutex<BTreeMap<usize, KRetProbe>> = Mutex::new(BTreeMap::new());
-    /// breakpoint addr -> (pc, ra) in original trapframe
-    /// the breakpoint is in a trampoline area, where every ebreak is associated with a kretprobe instance
-    /// pc is used to obtain associated KRetProbe from the above map
-    /// ra is used to restore the original trapframe since it is modified to execute post_handler
-    static ref INSTANCES: Mutex<BTreeMap<usize, KRetProbeInstance>> = Mutex::new(BTreeMap::new());
+    /// task key -> outstanding instances of that task, innermost call last
+    /// every instance owns a breakpoint in the trampoline area that replaced its ra
+    /// entry_addr is used to obtain associated KRetProbe from the above map
+    /// ret_addr is used to restore the original trapframe since it is modified to execute exit_handler
+    /// keeping instances per task lets us drop the instances of a task that exits before
+    /// the probed call returns
+    static ref INSTANCES: Mutex<BTreeMap<usize, Vec<KRetProbeInstance>>> = Mutex::new(BTreeMap::new());
 }
 
 impl KRetProbe {
     pub fn new(
+        kprobe_handle: Option<usize>,
         exit_handler: Arc<Handler>,
         entry_handler: Option<Arc<Handler>>,
         limit: Option<usize>,
@@ -46,6 +55,7 @@ impl KRetProbe {
     ) -> Self {
         let instance_limit = limit.unwrap_or(usize::max_value());
         Self {
+            kprobe_handle,
             entry_handler,
             exit_handler,
             instance_limit,
@@ -57,21 +67,27 @@ impl KRetProbe {
 }
 
 impl KRetProbeInstance {
-    pub fn new(entry_addr: usize, ret_addr: usize) -> Self {
+    pub fn new(entry_addr: Option<usize>, ret_addr: usize, bp_addr: usize, sp: usize) -> Self {
         Self {
             entry_addr,
             ret_addr,
+            bp_addr,
+            sp,
         }
     }
 }
 
 /// a kretprobe is registered by registering a kprobe with this as the handler
 /// executes pre_handler like kprobe, then changes ra to a breakpoint trampoline to execute exit_handler in kretprobe
-/// meanwhile saves pc and ra in INSTANCES to restore trapframe later
+/// meanwhile saves pc and ra in INSTANCES of the current task to restore trapframe later
+#[link_section = ".text.noprobe"]
 fn kretprobe_kprobe_pre_handler(tf: &mut TrapFrame, _data: usize) -> isize {
     let pc = get_trapframe_pc(tf);
     let mut kretprobes = KRETPROBES.lock();
-    let probe = kretprobes.get_mut(&pc).unwrap();
+    let probe = match kretprobes.get_mut(&pc) {
+        Some(probe) => probe,
+        None => return 0,
+    };
     if probe.nr_instances >= probe.instance_limit {
         probe.nr_misses += 1;
         return 0;
@@ -83,67 +99,158 @@ fn kretprobe_kprobe_pre_handler(tf: &mut TrapFrame, _data: usize) -> isize {
     }
 
     let ra = get_trapframe_ra(tf);
-    let instance = KRetProbeInstance::new(pc, ra);
     let bp_addr = alloc_breakpoint();
+    let instance = KRetProbeInstance::new(Some(pc), ra, bp_addr, get_kernel_trapframe_sp(tf));
     // save pc and ra to restore trapframe later
-    INSTANCES.lock().insert(bp_addr, instance);
+    INSTANCES
+        .lock()
+        .entry(current_task_key())
+        .or_insert_with(Vec::new)
+        .push(instance);
     set_trapframe_ra(tf, bp_addr);
     0
 }
 
+/// drop an instance whose probed call will never return through its breakpoint
+fn drop_instance(kretprobes: &mut BTreeMap<usize, KRetProbe>, instance: KRetProbeInstance) {
+    if let Some(probe) = instance.entry_addr.and_then(|addr| kretprobes.get_mut(&addr)) {
+        probe.nr_instances -= 1;
+    }
+    free_breakpoint(instance.bp_addr);
+}
+
 /// this will be called when the breakpoint in the trampoline area is hit
 /// restores trapframe and executes exit_handler
+/// returns false if the breakpoint does not belong to any instance
+#[link_section = ".text.noprobe"]
 pub fn kretprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     // lock KRETPROBES first to avoid dead lock
     let mut kretprobes = KRETPROBES.lock();
 
     let pc = get_trapframe_pc(tf);
+    let sp = get_kernel_trapframe_sp(tf);
     let mut instance_map = INSTANCES.lock();
-    let instance = instance_map.get(&pc).unwrap();
+    // every instance has its own breakpoint, so it is found even if the task key
+    // differs from the one at entry, e.g. when the processor was borrowed then
+    let key = current_task_key();
+    let found = match instance_map.get(&key) {
+        Some(instances) if instances.iter().any(|inst| inst.bp_addr == pc) => Some(key),
+        _ => instance_map
+            .iter()
+            .find(|(_, instances)| instances.iter().any(|inst| inst.bp_addr == pc))
+            .map(|(&key, _)| key),
+    };
+    let key = match found {
+        Some(key) => key,
+        None => return false,
+    };
+    let instances = instance_map.get_mut(&key).unwrap();
+    // normally the innermost one, search from the end
+    let pos = instances
+        .iter()
+        .rposition(|inst| inst.bp_addr == pc)
+        .unwrap();
+    let instance = instances.remove(pos);
+    // the trampoline is stack safe: inner calls entered below this sp are gone once the
+    // outer call returns, e.g. after a non-local exit, so their instances are dropped
+    let mut orphans = Vec::new();
+    let mut i = pos;
+    while i < instances.len() {
+        if instances[i].sp < sp {
+            orphans.push(instances.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    if instances.is_empty() {
+        instance_map.remove(&key);
+    }
+    drop(instance_map);
+    for orphan in orphans {
+        drop_instance(&mut kretprobes, orphan);
+    }
 
-    let probe = kretprobes.get_mut(&instance.entry_addr).unwrap();
-    let _ = (probe.exit_handler)(tf, probe.user_data);
-    probe.nr_instances -= 1;
+    if let Some(probe) = instance.entry_addr.and_then(|addr| kretprobes.get_mut(&addr)) {
+        let _ = (probe.exit_handler)(tf, probe.user_data);
+        probe.nr_instances -= 1;
+    }
 
     let ra = instance.ret_addr;
     set_trapframe_pc(tf, ra);
     set_trapframe_ra(tf, ra);
     free_breakpoint(pc);
-    instance_map.remove(&pc).unwrap();
     true
 }
 
+/// drop all outstanding instances of a task, called when the task exits
+/// the probed calls will never return, so their exit_handler is not executed
+pub fn kretprobe_task_exit(key: usize) {
+    let mut kretprobes = KRETPROBES.lock();
+    let instances = match INSTANCES.lock().remove(&key) {
+        Some(instances) => instances,
+        None => return,
+    };
+    for instance in instances {
+        drop_instance(&mut kretprobes, instance);
+    }
+}
+
 /// register a kretprobe by registering a kprobe with kretprobe_kprobe_pre_handler as the handler
 pub fn register_kretprobe(entry_addr: usize, args: KRetProbeArgs) -> bool {
-    if !register_kprobe(entry_addr, KProbeArgs::from(kretprobe_kprobe_pre_handler)) {
-        return false;
+    // only one kretprobe at each address, the address is claimed before the kprobe is
+    // registered since KRETPROBES is not held across register_kprobe: the trap path
+    // locks KPROBES first
+    {
+        let mut kretprobes = KRETPROBES.lock();
+        if kretprobes.contains_key(&entry_addr) {
+            return false;
+        }
+        let probe = KRetProbe::new(
+            None,
+            args.exit_handler,
+            args.entry_handler,
+            args.limit,
+            args.user_data,
+        );
+        kretprobes.insert(entry_addr, probe);
+    }
+    let handle = register_kprobe(entry_addr, KProbeArgs::from(kretprobe_kprobe_pre_handler));
+    let mut kretprobes = KRETPROBES.lock();
+    match handle {
+        Some(handle) => {
+            kretprobes.get_mut(&entry_addr).unwrap().kprobe_handle = Some(handle);
+            true
+        }
+        None => {
+            kretprobes.remove(&entry_addr);
+            false
+        }
     }
-
-    let probe = KRetProbe::new(
-        args.exit_handler,
-        args.entry_handler,
-        args.limit,
-        args.user_data,
-    );
-    KRETPROBES.lock().insert(entry_addr, probe);
-    true
 }
 
+/// calls still outstanding are detached from the kretprobe, they return through their
+/// trampoline breakpoint as usual but without running exit_handler, so the address can be
+/// probed again at once. false if there is no kretprobe or it is still being registered
 pub fn unregister_kretprobe(entry_addr: usize) -> bool {
-    let mut kretprobes = KRETPROBES.lock();
-    if let Some(probe) = kretprobes.get(&entry_addr) {
-        if probe.nr_instances > 0 {
-            false
-        } else {
-            let ok = unregister_kprobe(entry_addr);
-            if ok {
-                kretprobes.remove(&entry_addr).unwrap();
+    let handle = {
+        let mut kretprobes = KRETPROBES.lock();
+        let handle = match kretprobes.get(&entry_addr).and_then(|probe| probe.kprobe_handle) {
+            Some(handle) => handle,
+            None => return false,
+        };
+        kretprobes.remove(&entry_addr);
+        for instances in INSTANCES.lock().values_mut() {
+            for instance in instances.iter_mut() {
+                if instance.entry_addr == Some(entry_addr) {
+                    instance.entry_addr = None;
+                }
             }
-            ok
         }
-    } else {
-        false
-    }
+        handle
+    };
+    // not under KRETPROBES, the trap path locks KPROBES first. a hit in between finds no
+    // kretprobe and does nothing
+    unregister_kprobe(handle)
 }
 
 use super::osutils::symbol_to_addr;
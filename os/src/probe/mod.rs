@@ -1,6 +1,10 @@
+pub mod ftrace;
 pub mod kprobes;
 pub mod kretprobes;
 pub mod osutils;
+#[macro_use]
+pub mod tracepoint;
+pub mod watchpoints;
 pub use osutils::init_osutils;
 
 use kprobes::{Handler, HandlerFn};
@@ -49,15 +53,13 @@ impl KRetProbeArgs {
     }
 }
 
-pub fn register_kprobe(addr: usize, args: KProbeArgs) -> Option<()> {
-    match kprobes::register_kprobe(addr, args) {
-        true => Some(()),
-        false => None,
-    }
+/// returns a handle used to unregister the kprobe, kprobes at the same address do not conflict
+pub fn register_kprobe(addr: usize, args: KProbeArgs) -> Option<usize> {
+    kprobes::register_kprobe(addr, args)
 }
 
-pub fn unregister_kprobe(addr: usize) -> Option<()> {
-    match kprobes::unregister_kprobe(addr) {
+pub fn unregister_kprobe(handle: usize) -> Option<()> {
+    match kprobes::unregister_kprobe(handle) {
         true => Some(()),
         false => None,
     }
@@ -77,17 +79,29 @@ pub fn unregister_kretprobe(addr: usize) -> Option<()> {
     }
 }
 
+pub use tracepoint::{register_tracepoint, tracepoint_index, TracepointArgs};
+pub use watchpoints::{register_watchpoint, unregister_watchpoint, WatchpointArgs};
+
+pub fn unregister_tracepoint(handle: usize) -> Option<()> {
+    match tracepoint::unregister_tracepoint(handle) {
+        true => Some(()),
+        false => None,
+    }
+}
+
 /// This function should be called from the trap handler when a breakpoint is hit.
+/// Returns false if the breakpoint belongs to none of kprobes, kretprobes and watchpoints.
 #[no_mangle]
-pub fn kprobes_breakpoint_handler(tf: &mut TrapFrame) {
-    let handled = kprobes::kprobe_trap_handler(tf);
-    if !handled {
-        kretprobes::kretprobe_trap_handler(tf);
-    }
+#[link_section = ".text.noprobe"]
+pub fn kprobes_breakpoint_handler(tf: &mut TrapFrame) -> bool {
+    kprobes::kprobe_trap_handler(tf)
+        || kretprobes::kretprobe_trap_handler(tf)
+        || watchpoints::watchpoint_trap_handler(tf)
 }
 
 mod tests;
 pub fn run_tests() {
     tests::kprobes_test::run_kprobes_tests();
     tests::kretprobes_test::run_kretprobes_test();
+    tests::watchpoints_test::run_watchpoints_test();
 }
\ No newline at end of file
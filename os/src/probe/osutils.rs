@@ -1,7 +1,18 @@
 use crate::mm::{raw_frame_alloc, raw_frame_dealloc};
+pub use crate::sbi::sbi_call;
+use crate::task::try_current_task;
+use alloc::sync::Arc;
 
 pub const PAGE_SIZE: usize = crate::config::PAGE_SIZE;
 
+extern "C" {
+    fn stext();
+    fn etext();
+    fn enoprobe();
+    fn sftrace_sites();
+    fn eftrace_sites();
+}
+
 /// optional function to initialize anything needed
 pub fn init_osutils() {
 }
@@ -31,5 +42,57 @@ pub fn byte_copy(dst_addr: usize, src_addr: usize, len: usize) {
 
 /// Convert symbol to address for kprobe registering, not required
 pub fn symbol_to_addr(symbol: &str) -> Option<usize> {
-    None
-}
\ No newline at end of file
+    crate::ksyms::symbol_to_addr(symbol)
+}
+
+/// Symbol containing `addr` and the offset into it, None if the symbol table is empty
+pub fn addr_to_symbol(addr: usize) -> Option<(&'static str, usize)> {
+    crate::ksyms::addr_to_symbol(addr)
+}
+
+/// Range of kernel code that kprobes may be placed in
+pub fn kernel_text_range() -> (usize, usize) {
+    (stext as usize, etext as usize)
+}
+
+/// Ranges of kernel code that must never be probed:
+/// .text.entry, the trap trampoline and functions placed in .text.noprobe
+pub fn noprobe_ranges() -> [(usize, usize); 1] {
+    [(stext as usize, enoprobe as usize)]
+}
+
+/// Crates whose code must never be probed, matched on the demangled symbol name,
+/// as their generic code is compiled into the crates using it and can not be placed
+/// in .text.noprobe. the lock crate runs on the probe path, the spin crate guards
+/// the tracing state and the allocator runs with the heap locked
+pub fn noprobe_crates() -> &'static [&'static str] {
+    &["lock::", "spin::", "buddy_system_allocator::"]
+}
+
+/// Entries of all functions built with patchable nops, see the linker script
+pub fn ftrace_sites() -> &'static [usize] {
+    let start = sftrace_sites as usize;
+    let len = (eftrace_sites as usize - start) / core::mem::size_of::<usize>();
+    unsafe { core::slice::from_raw_parts(start as *const usize, len) }
+}
+
+/// Id of the current hart, used to index per-hart states
+#[inline(always)]
+#[link_section = ".text.noprobe"]
+pub fn current_hart_id() -> usize {
+    0 // the kernel runs on a single hart
+}
+
+/// Current time in ns, used to timestamp ftrace entries
+#[inline(always)]
+#[link_section = ".text.noprobe"]
+pub fn current_time_ns() -> u64 {
+    (crate::timer::get_time() as u128 * 1_000_000_000 / crate::config::CLOCK_FREQ as u128) as u64
+}
+
+/// Identify the current task, used to track kretprobe instances per task
+/// 0 is returned when no task is running, e.g. during boot, or when the processor is
+/// borrowed, e.g. in run_tasks
+pub fn current_task_key() -> usize {
+    try_current_task().map_or(0, |task| Arc::as_ptr(&task) as usize)
+}
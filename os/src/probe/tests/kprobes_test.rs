@@ -1,5 +1,5 @@
 // WARNING: riscv only!
-use super::kprobes::{register_kprobe};
+use super::kprobes::{register_kprobe, unregister_kprobe};
 use core::slice::from_raw_parts;
 use core::arch::global_asm;
 use alloc::sync::Arc;
@@ -23,6 +23,11 @@ fn test_pre_handler(tf: &mut TrapFrame, _data: usize) -> isize {
     0
 }
 
+fn test_second_pre_handler(tf: &mut TrapFrame, _data: usize) -> isize {
+    println!("[KPROBE_PRE_HANDLER] second handler, pc = {:#x}", get_trapframe_pc(tf));
+    0
+}
+
 fn test_post_handler(_tf: &mut TrapFrame, _data: usize) -> isize {
     println!("[KPROBE_POST_HANDLER] post handler invoked");
     0
@@ -43,6 +48,12 @@ pub fn run_kprobes_tests() {
             });
             f(0);
         }
+
+        // a second kprobe at the same address, both pre handlers should run
+        let handle = register_kprobe(probes[0], KProbeArgs::from(test_second_pre_handler)).unwrap();
+        test_fns[0](0);
+        assert!(unregister_kprobe(handle));
+        test_fns[0](0);
     }
     println!("kprobes tests finished");
 }
@@ -1,5 +1,6 @@
-use super::kretprobes::{register_kretprobe};
+use super::kretprobes::{register_kretprobe, unregister_kretprobe};
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use super::{KRetProbeArgs, TrapFrame};
 use super::trapframe::*;
 
@@ -23,6 +24,29 @@ fn test_exit_handler(tf: &mut TrapFrame, _data: usize) -> isize {
     0
 }
 
+static EXITS: AtomicUsize = AtomicUsize::new(0);
+
+/// unregisters its own kretprobe while the probed call is outstanding
+#[inline(never)]
+fn unregistering_fn() -> isize {
+    assert!(unregister_kretprobe(unregistering_fn as usize));
+    42
+}
+
+fn counting_exit_handler(_tf: &mut TrapFrame, _data: usize) -> isize {
+    EXITS.fetch_add(1, Ordering::Relaxed);
+    0
+}
+
+fn counting_args() -> KRetProbeArgs {
+    KRetProbeArgs {
+        exit_handler: Arc::new(counting_exit_handler),
+        entry_handler: None,
+        limit: None,
+        user_data: 0,
+    }
+}
+
 pub fn run_kretprobes_test() {
     let args = KRetProbeArgs {
         exit_handler: Arc::new(test_exit_handler),
@@ -32,4 +56,13 @@ pub fn run_kretprobes_test() {
     };
     register_kretprobe(recursive_fn as usize, args);
     recursive_fn(1);
+
+    assert!(register_kretprobe(unregistering_fn as usize, counting_args()));
+    assert_eq!(unregistering_fn(), 42);
+    assert_eq!(EXITS.load(Ordering::Relaxed), 0);
+    // the outstanding call did not keep the address claimed
+    assert!(register_kretprobe(unregistering_fn as usize, counting_args()));
+    unregistering_fn();
+    assert_eq!(EXITS.load(Ordering::Relaxed), 0);
+    println!("kretprobe unregistered during its call");
 }
@@ -1,5 +1,6 @@
 pub mod kprobes_test;
 pub mod kretprobes_test;
-pub use super::{kprobes, kretprobes, KProbeArgs, KRetProbeArgs};
+pub mod watchpoints_test;
+pub use super::{kprobes, kretprobes, watchpoints, KProbeArgs, KRetProbeArgs, WatchpointArgs};
 pub use super::TrapFrame;
 pub use super::arch::trapframe;
\ No newline at end of file
@@ -0,0 +1,37 @@
+use super::watchpoints::{register_watchpoint, unregister_watchpoint, WATCH_STORE};
+use super::{TrapFrame, WatchpointArgs};
+use super::trapframe::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static mut WATCHED: usize = 0;
+static HITS: AtomicUsize = AtomicUsize::new(0);
+
+fn test_watch_handler(tf: &mut TrapFrame, addr: usize, _data: usize) -> isize {
+    println!("[WATCHPOINT] store to {:#x} at pc = {:#x}", addr, get_trapframe_pc(tf));
+    // the triggers are off while the handler runs, reading the watched range is fine
+    let old = unsafe { core::ptr::read_volatile(&WATCHED) };
+    assert_eq!(old, 0);
+    HITS.fetch_add(1, Ordering::Relaxed);
+    0
+}
+
+pub fn run_watchpoints_test() {
+    let addr = unsafe { &WATCHED as *const usize as usize };
+    let handle = match register_watchpoint(addr, 8, WatchpointArgs::from(test_watch_handler, WATCH_STORE)) {
+        Some(handle) => handle,
+        None => {
+            println!("watchpoints test skipped, no debug triggers in firmware");
+            return;
+        }
+    };
+    unsafe {
+        core::ptr::write_volatile(&mut WATCHED, 42);
+        assert_eq!(core::ptr::read_volatile(&WATCHED), 42);
+    }
+    assert_eq!(HITS.load(Ordering::Relaxed), 1);
+    unregister_watchpoint(handle);
+    // not watched any more
+    unsafe { core::ptr::write_volatile(&mut WATCHED, 0) };
+    assert_eq!(HITS.load(Ordering::Relaxed), 1);
+    println!("watchpoints test finished");
+}
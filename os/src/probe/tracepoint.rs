@@ -0,0 +1,273 @@
+//! static kernel tracepoints
+//!
+//! unlike kprobes, a static tracepoint is compiled into the kernel at a fixed place,
+//! so it has a stable name that survives rebuilds.
+//! when nothing is attached, `trace_point!` costs one relaxed atomic load.
+//!
+//! every tracepoint passes a typed `#[repr(C)]` context to its handlers,
+//! see `user/ebpf/kern/tracepoint.h` for the C side of these layouts.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// handler(ctx, user_data), ctx points to the typed context of the tracepoint
+pub type TracepointHandler = dyn Fn(*const u8, usize) -> isize + Sync + Send;
+pub type TracepointHandlerFn = fn(*const u8, usize) -> isize;
+
+pub struct TracepointArgs {
+    pub handler: Arc<TracepointHandler>,
+    // Extra user-defined data. Tracepoints will not touch it and pass it to handler as-is.
+    pub user_data: usize,
+}
+
+impl TracepointArgs {
+    pub fn from(handler: TracepointHandlerFn) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            user_data: 0,
+        }
+    }
+}
+
+pub struct StaticTracepoint {
+    pub category: &'static str,
+    pub name: &'static str,
+    enabled: AtomicBool,
+    /// (registration handle, args)
+    handlers: Mutex<Vec<(usize, TracepointArgs)>>,
+}
+
+impl StaticTracepoint {
+    pub const fn new(category: &'static str, name: &'static str) -> Self {
+        Self {
+            category,
+            name,
+            enabled: AtomicBool::new(false),
+            handlers: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// accepts both `name` and `category:name`
+    fn matches(&self, name: &str) -> bool {
+        match name.split_once(':') {
+            Some((category, name)) => category == self.category && name == self.name,
+            None => name == self.name,
+        }
+    }
+
+    /// run all handlers with `ctx`
+    /// handlers are cloned out first so that they may register or fire other tracepoints
+    pub fn fire<T>(&self, ctx: &T) {
+        let handlers: Vec<(Arc<TracepointHandler>, usize)> = self
+            .handlers
+            .lock()
+            .iter()
+            .map(|(_, args)| (args.handler.clone(), args.user_data))
+            .collect();
+        for (handler, user_data) in handlers {
+            let _ = handler(ctx as *const T as *const u8, user_data);
+        }
+    }
+}
+
+/// fire a static tracepoint, the context expression is only evaluated when enabled
+/// # example
+/// `trace_point!(SYS_ENTER, SysEnterCtx::new(syscall_id, args));`
+#[macro_export]
+macro_rules! trace_point {
+    ($tp: ident, $ctx: expr) => {
+        if $crate::probe::tracepoint::$tp.is_enabled() {
+            let ctx = $ctx;
+            $crate::probe::tracepoint::$tp.fire(&ctx);
+        }
+    };
+}
+
+pub static SYS_ENTER: StaticTracepoint = StaticTracepoint::new("syscalls", "sys_enter");
+pub static SYS_EXIT: StaticTracepoint = StaticTracepoint::new("syscalls", "sys_exit");
+pub static SCHED_SWITCH: StaticTracepoint = StaticTracepoint::new("sched", "sched_switch");
+pub static PROCESS_FORK: StaticTracepoint = StaticTracepoint::new("sched", "process_fork");
+pub static PROCESS_EXEC: StaticTracepoint = StaticTracepoint::new("sched", "process_exec");
+pub static PROCESS_EXIT: StaticTracepoint = StaticTracepoint::new("sched", "process_exit");
+pub static PAGE_FAULT: StaticTracepoint = StaticTracepoint::new("exceptions", "page_fault");
+
+/// all static tracepoints, the index in this table is used as the tracepoint id
+pub static TRACEPOINTS: [&StaticTracepoint; 7] = [
+    &SYS_ENTER,
+    &SYS_EXIT,
+    &SCHED_SWITCH,
+    &PROCESS_FORK,
+    &PROCESS_EXEC,
+    &PROCESS_EXIT,
+    &PAGE_FAULT,
+];
+
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+/// get the id of a tracepoint by `name` or `category:name`
+pub fn tracepoint_index(name: &str) -> Option<usize> {
+    TRACEPOINTS.iter().position(|tp| tp.matches(name))
+}
+
+/// register a handler on the tracepoint with id `index`
+/// returns a handle used to unregister it, or None if the id is invalid
+pub fn register_tracepoint(index: usize, args: TracepointArgs) -> Option<usize> {
+    let tp = TRACEPOINTS.get(index)?;
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    let mut handlers = tp.handlers.lock();
+    handlers.push((handle, args));
+    tp.enabled.store(true, Ordering::Relaxed);
+    Some(handle)
+}
+
+/// unregister a handler by the handle returned from `register_tracepoint`
+/// the tracepoint is disabled again when its last handler is removed
+pub fn unregister_tracepoint(handle: usize) -> bool {
+    for tp in TRACEPOINTS.iter() {
+        let mut handlers = tp.handlers.lock();
+        if let Some(pos) = handlers.iter().position(|(h, _)| *h == handle) {
+            handlers.remove(pos);
+            if handlers.is_empty() {
+                tp.enabled.store(false, Ordering::Relaxed);
+            }
+            return true;
+        }
+    }
+    false
+}
+
+/// length of the name buffers in contexts, including the trailing 0
+pub const TP_COMM_LEN: usize = 32;
+
+/// copy `s` into a fixed size, null terminated buffer
+fn comm_from_str(s: &str) -> [u8; TP_COMM_LEN] {
+    let mut comm = [0u8; TP_COMM_LEN];
+    let len = s.len().min(TP_COMM_LEN - 1);
+    comm[..len].copy_from_slice(&s.as_bytes()[..len]);
+    comm
+}
+
+#[repr(C)]
+/// context of `syscalls:sys_enter`
+pub struct SysEnterCtx {
+    pub id: usize,
+    pub args: [usize; 3],
+}
+
+impl SysEnterCtx {
+    pub fn new(id: usize, args: [usize; 3]) -> Self {
+        Self { id, args }
+    }
+}
+
+#[repr(C)]
+/// context of `syscalls:sys_exit`
+pub struct SysExitCtx {
+    pub id: usize,
+    pub args: [usize; 3],
+    pub ret: isize,
+}
+
+impl SysExitCtx {
+    pub fn new(id: usize, args: [usize; 3], ret: isize) -> Self {
+        Self { id, args, ret }
+    }
+}
+
+#[repr(C)]
+/// context of `sched:sched_switch`, prev_pid is usize::MAX on the first switch
+pub struct SchedSwitchCtx {
+    pub prev_pid: usize,
+    pub prev_tid: usize,
+    pub next_pid: usize,
+    pub next_tid: usize,
+}
+
+impl SchedSwitchCtx {
+    pub fn new(prev: (usize, usize), next: (usize, usize)) -> Self {
+        Self {
+            prev_pid: prev.0,
+            prev_tid: prev.1,
+            next_pid: next.0,
+            next_tid: next.1,
+        }
+    }
+}
+
+#[repr(C)]
+/// context of `sched:process_fork`
+pub struct ProcessForkCtx {
+    pub parent_pid: usize,
+    pub child_pid: usize,
+}
+
+impl ProcessForkCtx {
+    pub fn new(parent_pid: usize, child_pid: usize) -> Self {
+        Self {
+            parent_pid,
+            child_pid,
+        }
+    }
+}
+
+#[repr(C)]
+/// context of `sched:process_exec`, `path` is truncated to TP_COMM_LEN - 1 bytes
+pub struct ProcessExecCtx {
+    pub pid: usize,
+    pub path: [u8; TP_COMM_LEN],
+}
+
+impl ProcessExecCtx {
+    pub fn new(pid: usize, path: &str) -> Self {
+        Self {
+            pid,
+            path: comm_from_str(path),
+        }
+    }
+}
+
+#[repr(C)]
+/// context of `sched:process_exit`, fired when the main thread exits, so tid is 0
+pub struct ProcessExitCtx {
+    pub pid: usize,
+    pub tid: usize,
+    pub exit_code: isize,
+}
+
+impl ProcessExitCtx {
+    pub fn new(pid: usize, tid: usize, exit_code: i32) -> Self {
+        Self {
+            pid,
+            tid,
+            exit_code: exit_code as isize,
+        }
+    }
+}
+
+#[repr(C)]
+/// context of `exceptions:page_fault`
+/// `cause` is the raw scause, `addr` is stval and `pc` is the faulting sepc
+pub struct PageFaultCtx {
+    pub pid: usize,
+    pub cause: usize,
+    pub addr: usize,
+    pub pc: usize,
+}
+
+impl PageFaultCtx {
+    pub fn new(pid: usize, cause: usize, addr: usize, pc: usize) -> Self {
+        Self {
+            pid,
+            cause,
+            addr,
+            pc,
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! watchpoints: probes fired by loads/stores to an address range, using hardware data triggers
+//!
+//! a trigger fires before the access, so the instruction is stepped over out of line
+//! (like a kprobe) with the triggers of that watchpoint disabled, then they are enabled again.
+//! the handler is called before the access, a store has not changed memory yet.
+//! it runs with the triggers of its watchpoint disabled and no lock held, so it may access
+//! the watched range and register or unregister watchpoints.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use lock::Mutex;
+
+use super::arch::*;
+use super::TrapFrame;
+
+/// handler(tf, accessed address, user_data)
+pub type WatchpointHandler = dyn Fn(&mut TrapFrame, usize, usize) -> isize + Sync + Send;
+pub type WatchpointHandlerFn = fn(&mut TrapFrame, usize, usize) -> isize;
+
+pub const WATCH_LOAD: usize = MCONTROL_LOAD;
+pub const WATCH_STORE: usize = MCONTROL_STORE;
+
+pub struct WatchpointArgs {
+    pub handler: Arc<WatchpointHandler>,
+    /// WATCH_LOAD, WATCH_STORE or both
+    pub access: usize,
+    // Extra user-defined data. Watchpoints will not touch it and pass it to handler as-is.
+    pub user_data: usize,
+}
+
+impl WatchpointArgs {
+    pub fn from(handler: WatchpointHandlerFn, access: usize) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            access,
+            user_data: 0,
+        }
+    }
+}
+
+struct Watchpoint {
+    addr: usize,
+    len: usize,
+    handler: Arc<WatchpointHandler>,
+    user_data: usize,
+    /// indexes of the hardware triggers
+    triggers: Vec<usize>,
+}
+
+impl Watchpoint {
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.addr && addr < self.addr + self.len
+    }
+}
+
+/// a watched instruction being stepped over
+struct StepOver {
+    handle: usize,
+    /// where to continue after the instruction buffer
+    next_pc: usize,
+}
+
+lazy_static! {
+    /// handle -> watchpoint
+    static ref WATCHPOINTS: Mutex<BTreeMap<usize, Watchpoint>> = Mutex::new(BTreeMap::new());
+    /// the watched instruction is copied here, followed by an ebreak
+    static ref STEP_BUFFER: InstructionBuffer = InstructionBuffer::new();
+    static ref STEPPING: Mutex<Option<StepOver>> = Mutex::new(None);
+    static ref TRIGGERS_READY: bool = trigger_init();
+}
+
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+/// watch [addr, addr + len) for `args.access`
+/// returns a handle used to unregister it
+/// possible errors: no trigger support in the firmware, out of triggers
+pub fn register_watchpoint(addr: usize, len: usize, args: WatchpointArgs) -> Option<usize> {
+    if !*TRIGGERS_READY || len == 0 || args.access & (WATCH_LOAD | WATCH_STORE) == 0 {
+        return None;
+    }
+    let triggers = install_data_trigger(addr, len, args.access)?;
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    WATCHPOINTS.lock().insert(
+        handle,
+        Watchpoint {
+            addr,
+            len,
+            handler: args.handler,
+            user_data: args.user_data,
+            triggers,
+        },
+    );
+    Some(handle)
+}
+
+/// possible errors: handle not found
+pub fn unregister_watchpoint(handle: usize) -> bool {
+    match WATCHPOINTS.lock().remove(&handle) {
+        Some(wp) => {
+            uninstall_data_trigger(&wp.triggers);
+            true
+        }
+        None => false,
+    }
+}
+
+/// entry of breakpoint trap, returns whether this event is handled
+/// returning false means the trap was not raised by a watchpoint
+#[link_section = ".text.noprobe"]
+pub fn watchpoint_trap_handler(tf: &mut TrapFrame) -> bool {
+    let pc = get_trapframe_pc(tf);
+    let watchpoints = WATCHPOINTS.lock();
+
+    // the step over is finished, enable the triggers again
+    let mut stepping = STEPPING.lock();
+    if let Some(step) = stepping.as_ref() {
+        if pc == STEP_BUFFER.addr() + get_insn_length(STEP_BUFFER.addr()) {
+            if let Some(wp) = watchpoints.get(&step.handle) {
+                enable_data_trigger(&wp.triggers);
+            }
+            set_trapframe_pc(tf, step.next_pc);
+            *stepping = None;
+            return true;
+        }
+    }
+
+    // an ebreak is a kprobe that is gone or a bug, not a data trigger
+    if is_ebreak(pc) {
+        return false;
+    }
+    let hit = trigger_hit_addr();
+    let (handle, handler, user_data) = match watchpoints.iter().find(|(_, wp)| wp.contains(hit)) {
+        Some((&handle, wp)) => {
+            // step over the access with the triggers off, or it fires again
+            disable_data_trigger(&wp.triggers);
+            (handle, wp.handler.clone(), wp.user_data)
+        }
+        None => return false,
+    };
+    drop(stepping);
+    drop(watchpoints);
+    let _ = handler(tf, hit, user_data);
+
+    let len = get_insn_length(pc);
+    STEP_BUFFER.copy_in(0, pc, len);
+    STEP_BUFFER.add_breakpoint(len);
+    invalidate_icache();
+    *STEPPING.lock() = Some(StepOver {
+        handle,
+        next_pc: pc + len,
+    });
+    set_trapframe_pc(tf, STEP_BUFFER.addr());
+    true
+}
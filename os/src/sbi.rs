@@ -13,3 +13,20 @@ pub fn shutdown(failure: bool) -> ! {
     }
     unreachable!()
 }
+
+/// raw sbi call for extensions sbi_rt does not cover, returns (error, value)
+pub fn sbi_call(eid: usize, fid: usize, args: [usize; 3]) -> (isize, usize) {
+    let error: usize;
+    let value: usize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a6") fid,
+            in("a7") eid,
+        );
+    }
+    (error as isize, value)
+}
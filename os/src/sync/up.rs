@@ -105,12 +105,32 @@ impl<T> UPIntrFreeCell<T> {
         }
     }
 
+    /// offset of the value inside the cell, used by the eBPF type info.
+    /// the value of a RefCell may be unsized, so it is laid out last, after the isize
+    /// borrow flag, and the cell is just the RefCell
+    pub const fn value_offset() -> usize {
+        let align = core::mem::align_of::<T>();
+        (core::mem::size_of::<isize>() + align - 1) / align * align
+    }
+
     /// Panic if the data has been borrowed.
     pub fn exclusive_access(&self) -> UPIntrRefMut<'_, T> {
         INTR_MASKING_INFO.get_mut().enter();
         UPIntrRefMut(Some(self.inner.borrow_mut()))
     }
 
+    /// None if the data has been borrowed, for paths that must not panic
+    pub fn try_exclusive_access(&self) -> Option<UPIntrRefMut<'_, T>> {
+        INTR_MASKING_INFO.get_mut().enter();
+        match self.inner.try_borrow_mut() {
+            Ok(inner) => Some(UPIntrRefMut(Some(inner))),
+            Err(_) => {
+                INTR_MASKING_INFO.get_mut().exit();
+                None
+            }
+        }
+    }
+
     pub fn exclusive_session<F, V>(&self, f: F) -> V
     where
         F: FnOnce(&mut T) -> V,
@@ -30,6 +30,10 @@ pub fn sys_bpf(cmd: isize, bpf_attr: usize , size: usize) -> isize {
             BPF_PROG_LOAD => todo!(),
             BPF_PROG_ATTACH => sys_bpf_program_attach(ptr, size),
             BPF_PROG_DETACH => sys_bpf_program_detach(ptr, size),
+            BPF_PROG_GET_NEXT_ID => sys_bpf_obj_get_next_id(ptr, size, false),
+            BPF_MAP_GET_NEXT_ID => sys_bpf_obj_get_next_id(ptr, size, true),
+            BPF_OBJ_GET_INFO_BY_FD => sys_bpf_obj_get_info_by_fd(ptr, size),
+            BPF_ENABLE_STATS => sys_bpf_enable_stats(ptr, size),
             BPF_PROG_LOAD_EX => sys_preprocess_bpf_program_load_ex(ptr, size),
         };
         if ret < 0 {
@@ -1,4 +1,4 @@
-use crate::fs::{make_pipe, open_file, OpenFlags};
+use crate::fs::{make_pipe, open, OpenFlags};
 use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
 use crate::task::{current_process, current_user_token};
 use alloc::sync::Arc;
@@ -49,11 +49,11 @@ pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    if let Some(file) = open(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         // println!("Getting PCB in src/syscall/fs.rs sys_open()");
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
+        inner.fd_table[fd] = Some(file);
         fd as isize
     } else {
         -1
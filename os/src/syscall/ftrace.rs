@@ -0,0 +1,71 @@
+use crate::config::MAX_HARTS;
+use crate::mm::try_translated_byte_buffer;
+use crate::probe::ftrace::{
+    ftrace_disable, ftrace_disable_all, ftrace_enable, ftrace_enable_all, ftrace_read, FtraceEntry,
+    FTRACE_RING_SIZE,
+};
+use crate::task::current_user_token;
+use alloc::vec;
+use core::mem::size_of;
+
+const FTRACE_ENABLE: usize = 0;
+const FTRACE_DISABLE: usize = 1;
+const FTRACE_READ: usize = 2;
+
+/// * FTRACE_ENABLE, addr: record calls of the function at addr, or of every function if addr is 0
+///   returns the number of functions enabled
+/// * FTRACE_DISABLE, addr: stop recording the function at addr, or every function if addr is 0
+/// * FTRACE_READ, buf, count: move at most count entries into buf, returns the number moved
+///   or -1 if buf is not mapped
+pub fn sys_ftrace(cmd: usize, arg0: usize, arg1: usize) -> isize {
+    match cmd {
+        FTRACE_ENABLE if arg0 == 0 => ftrace_enable_all() as isize,
+        FTRACE_ENABLE => {
+            if ftrace_enable(arg0) {
+                1
+            } else {
+                -1
+            }
+        }
+        FTRACE_DISABLE if arg0 == 0 => {
+            ftrace_disable_all();
+            0
+        }
+        FTRACE_DISABLE => {
+            if ftrace_disable(arg0) {
+                0
+            } else {
+                -1
+            }
+        }
+        FTRACE_READ => {
+            // no more than the rings of all harts hold
+            let count = arg1.min(FTRACE_RING_SIZE * MAX_HARTS);
+            // translate before draining, so that a bad buffer loses no entries
+            let buffers = match try_translated_byte_buffer(
+                current_user_token(),
+                arg0 as *const u8,
+                count * size_of::<FtraceEntry>(),
+            ) {
+                Some(buffers) => buffers,
+                None => return -1,
+            };
+            let mut entries = vec![FtraceEntry::default(); count];
+            let n = ftrace_read(&mut entries);
+            let src = unsafe {
+                core::slice::from_raw_parts(
+                    entries.as_ptr() as *const u8,
+                    n * size_of::<FtraceEntry>(),
+                )
+            };
+            let mut copied = 0;
+            for buffer in buffers {
+                let len = buffer.len().min(src.len() - copied);
+                buffer[..len].copy_from_slice(&src[copied..copied + len]);
+                copied += len;
+            }
+            n as isize
+        }
+        _ => -1,
+    }
+}
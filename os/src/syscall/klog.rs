@@ -0,0 +1,61 @@
+use crate::logging::{
+    level_filter, log_read, set_console_level, set_max_level, set_module_filter, LogEntry,
+    LOG_RING_SIZE,
+};
+use crate::mm::{translated_str, try_translated_byte_buffer};
+use crate::task::current_user_token;
+use alloc::vec;
+use core::mem::size_of;
+
+const KLOG_MAX_LEVEL: usize = 0;
+const KLOG_MODULE_LEVEL: usize = 1;
+const KLOG_CONSOLE_LEVEL: usize = 2;
+
+/// move at most count records of the kernel log into buf, returns the number moved
+/// or -1 if buf is not mapped
+pub fn sys_klog_read(buf: usize, count: usize) -> isize {
+    // no more than the ring holds
+    let count = count.min(LOG_RING_SIZE);
+    // translate before popping, so that a bad buffer loses no records
+    let buffers = match try_translated_byte_buffer(
+        current_user_token(),
+        buf as *const u8,
+        count * size_of::<LogEntry>(),
+    ) {
+        Some(buffers) => buffers,
+        None => return -1,
+    };
+    let mut entries = vec![LogEntry::default(); count];
+    let n = log_read(&mut entries);
+    let src = unsafe {
+        core::slice::from_raw_parts(entries.as_ptr() as *const u8, n * size_of::<LogEntry>())
+    };
+    let mut copied = 0;
+    for buffer in buffers {
+        let len = buffer.len().min(src.len() - copied);
+        buffer[..len].copy_from_slice(&src[copied..copied + len]);
+        copied += len;
+    }
+    n as isize
+}
+
+/// level is 0 (off) to 5 (trace)
+/// * KLOG_MAX_LEVEL, level: records kept for modules without a filter
+/// * KLOG_MODULE_LEVEL, level, module: records kept for a module path and its submodules,
+///   a level above 5 removes the filter
+/// * KLOG_CONSOLE_LEVEL, level: records also printed to the console
+pub fn sys_klog_filter(cmd: usize, level: usize, module: *const u8) -> isize {
+    match (cmd, level_filter(level)) {
+        (KLOG_MAX_LEVEL, Some(filter)) => set_max_level(filter),
+        (KLOG_MODULE_LEVEL, filter) => {
+            let module = translated_str(current_user_token(), module);
+            if module.is_empty() {
+                return -1;
+            }
+            set_module_filter(&module, filter);
+        }
+        (KLOG_CONSOLE_LEVEL, Some(filter)) => set_console_level(filter),
+        _ => return -1,
+    }
+    0
+}
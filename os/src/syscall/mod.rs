@@ -36,6 +36,10 @@ const SYSCALL_KEY_PRESSED: usize = 3001;
 const SYSCALL_UART1_READ: usize = 4000;
 const SYSCALL_UART1_WRITE: usize = 4001;
 const SYSCALL_UART1_FLUSH: usize = 4002;
+const SYSCALL_FTRACE: usize = 5000;
+const SYSCALL_KLOG_READ: usize = 5001;
+const SYSCALL_KLOG_FILTER: usize = 5002;
+const SYSCALL_GETPROCS: usize = 5003;
 
 
 pub(crate) mod fs;
@@ -47,6 +51,8 @@ mod sync;
 mod thread;
 mod ebpf;
 mod uart1;
+mod ftrace;
+mod klog;
 
 use fs::*;
 use gui::*;
@@ -57,9 +63,14 @@ use sync::*;
 use thread::*;
 use ebpf::*;
 use uart1::*;
+use ftrace::*;
+use klog::*;
+
+use crate::probe::tracepoint::{SysEnterCtx, SysExitCtx};
 
 pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
-    match syscall_id {
+    trace_point!(SYS_ENTER, SysEnterCtx::new(syscall_id, args));
+    let ret = match syscall_id {
         SYSCALL_DUP => sys_dup(args[0]),
         SYSCALL_CONNECT => sys_connect(args[0] as _, args[1] as _, args[2] as _),
         SYSCALL_LISTEN => sys_listen(args[0] as _),
@@ -98,6 +109,12 @@ pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
         SYSCALL_UART1_READ => sys_uart1_read(),
         SYSCALL_UART1_WRITE => sys_uart1_write(args[0]),
         SYSCALL_UART1_FLUSH => sys_uart1_flush(),
+        SYSCALL_FTRACE => sys_ftrace(args[0], args[1], args[2]),
+        SYSCALL_KLOG_READ => sys_klog_read(args[0], args[1]),
+        SYSCALL_KLOG_FILTER => sys_klog_filter(args[0], args[1], args[2] as *const u8),
+        SYSCALL_GETPROCS => sys_getprocs(args[0], args[1]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
-    }
+    };
+    trace_point!(SYS_EXIT, SysExitCtx::new(syscall_id, args, ret));
+    ret
 }
@@ -1,13 +1,14 @@
 use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::mm::{translated_ref, translated_refmut, translated_str, try_translated_byte_buffer};
 use crate::task::{
     current_process, current_task, current_user_token, exit_current_and_run_next, pid2process,
-    suspend_current_and_run_next, SignalFlags,
+    suspend_current_and_run_next, ProcessState, SignalFlags, PID2PCB,
 };
-use crate::timer::get_time_ms;
+use crate::timer::{get_time_ms, ticks_to_ms};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::mem::size_of;
 use ruprobes::uprobes_init;
 
 pub fn sys_exit(exit_code: i32) -> ! {
@@ -122,3 +123,75 @@ pub fn sys_kill(pid: usize, signal: u32) -> isize {
         -1
     }
 }
+
+/// longer exec paths are truncated
+const PROC_PATH_SIZE: usize = 32;
+
+const PROC_RUNNING: usize = 0;
+const PROC_SLEEPING: usize = 1;
+const PROC_ZOMBIE: usize = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// one process as seen by sys_getprocs, `mem_pages` counts the frames mapped for it
+pub struct ProcInfo {
+    pub pid: usize,
+    pub ppid: usize,
+    pub state: usize,
+    pub threads: usize,
+    pub mem_pages: usize,
+    pub cpu_time_ms: usize,
+    pub path: [u8; PROC_PATH_SIZE],
+}
+
+/// fill buf with at most count live processes in pid order, returns the number filled
+/// or -1 if buf is not mapped
+pub fn sys_getprocs(buf: usize, count: usize) -> isize {
+    let processes: Vec<_> = PID2PCB
+        .exclusive_access()
+        .values()
+        .take(count)
+        .cloned()
+        .collect();
+    let infos: Vec<ProcInfo> = processes
+        .iter()
+        .map(|process| {
+            let state = match process.state() {
+                ProcessState::Running => PROC_RUNNING,
+                ProcessState::Sleeping => PROC_SLEEPING,
+                ProcessState::Zombie => PROC_ZOMBIE,
+            };
+            let ppid = process.getppid();
+            let inner = process.inner_exclusive_access();
+            let mut path = [0u8; PROC_PATH_SIZE];
+            let len = inner.path.len().min(PROC_PATH_SIZE);
+            path[..len].copy_from_slice(&inner.path.as_bytes()[..len]);
+            ProcInfo {
+                pid: process.getpid(),
+                ppid,
+                state,
+                threads: inner.tasks.iter().flatten().count(),
+                mem_pages: inner.resident_pages(),
+                cpu_time_ms: ticks_to_ms(inner.cpu_time),
+                path,
+            }
+        })
+        .collect();
+    let src = unsafe {
+        core::slice::from_raw_parts(
+            infos.as_ptr() as *const u8,
+            infos.len() * size_of::<ProcInfo>(),
+        )
+    };
+    let token = current_user_token();
+    let buffers = match try_translated_byte_buffer(token, buf as *const u8, src.len()) {
+        Some(buffers) => buffers,
+        None => return -1,
+    };
+    let mut copied = 0;
+    for buffer in buffers {
+        buffer.copy_from_slice(&src[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+    infos.len() as isize
+}
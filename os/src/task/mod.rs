@@ -10,23 +10,27 @@ mod task;
 
 use self::id::TaskUserRes;
 use crate::fs::{open_file, OpenFlags};
+use crate::probe::kretprobes::kretprobe_task_exit;
+use crate::probe::tracepoint::{ProcessExitCtx, PROCESS_EXIT};
 use crate::sbi::shutdown;
 use alloc::string::ToString;
 use alloc::{sync::Arc, vec::Vec};
 use lazy_static::*;
 use manager::fetch_task;
-use process::ProcessControlBlock;
 use switch::__switch;
 
 pub use context::TaskContext;
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle, IDLE_PID};
-pub use manager::{add_task, pid2process, remove_from_pid2process, wakeup_task};
+pub use manager::{
+    add_task, pid2process, remove_from_pid2process, try_pid2process, wakeup_task, PID2PCB,
+};
 pub use processor::{
     current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, run_tasks, schedule, take_current_task,
+    current_user_token, run_tasks, schedule, take_current_task, try_current_task,
 };
+pub use process::{ProcessControlBlock, ProcessControlBlockInner, ProcessState};
 pub use signal::SignalFlags;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskControlBlockInner, TaskStatus};
 
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
@@ -63,7 +67,20 @@ pub fn block_current_and_run_next() {
 
 /// Exit the current 'Running' task and run the next task in task list.
 pub fn exit_current_and_run_next(exit_code: i32) {
+    // fired before the task is taken, so that handlers can still see it as current.
+    // only the exit of the main thread ends the process
+    if PROCESS_EXIT.is_enabled() {
+        let task = current_task().unwrap();
+        let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+        if tid == 0 {
+            let pid = task.process.upgrade().unwrap().getpid();
+            drop(task);
+            trace_point!(PROCESS_EXIT, ProcessExitCtx::new(pid, tid, exit_code));
+        }
+    }
     let task = take_current_task().unwrap();
+    // probed calls still outstanding in this task will never return
+    kretprobe_task_exit(Arc::as_ptr(&task) as usize);
     let mut task_inner = task.inner_exclusive_access();
     let process = task.process.upgrade().unwrap();
     let tid = task_inner.res.as_ref().unwrap().tid;
@@ -116,6 +133,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         let mut recycle_res = Vec::<TaskUserRes>::new();
         for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
             let task = task.as_ref().unwrap();
+            kretprobe_task_exit(Arc::as_ptr(task) as usize);
             let mut task_inner = task.inner_exclusive_access();
             if let Some(res) = task_inner.res.take() {
                 recycle_res.push(res);
@@ -1,10 +1,11 @@
 use super::id::RecycleAllocator;
 use super::manager::insert_into_pid2process;
-use super::TaskControlBlock;
+use super::{TaskControlBlock, TaskStatus};
 use super::{add_task, SignalFlags};
 use super::{pid_alloc, PidHandle};
 use crate::fs::{File, Stdin, Stdout};
 use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE, PageTable};
+use crate::probe::tracepoint::{ProcessExecCtx, ProcessForkCtx};
 use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
 use crate::trap::{trap_handler, TrapContext};
 use alloc::string::String;
@@ -16,7 +17,7 @@ pub struct ProcessControlBlock {
     // immutable
     pub pid: PidHandle,
     // mutable
-    inner: UPIntrFreeCell<ProcessControlBlockInner>,
+    pub(crate) inner: UPIntrFreeCell<ProcessControlBlockInner>,
 }
 
 pub struct ProcessControlBlockInner {
@@ -33,6 +34,8 @@ pub struct ProcessControlBlockInner {
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
     pub path: String,
+    /// clock ticks its threads have run
+    pub cpu_time: usize,
 }
 
 impl ProcessControlBlockInner {
@@ -65,6 +68,23 @@ impl ProcessControlBlockInner {
     pub fn get_task(&self, tid: usize) -> Arc<TaskControlBlock> {
         self.tasks[tid].as_ref().unwrap().clone()
     }
+
+    /// frames mapped in its address space
+    pub fn resident_pages(&self) -> usize {
+        self.memory_set
+            .areas()
+            .iter()
+            .map(|area| area.frame_count())
+            .sum()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ProcessState {
+    Running,
+    /// all of its threads are blocked
+    Sleeping,
+    Zombie,
 }
 
 impl ProcessControlBlock {
@@ -72,6 +92,13 @@ impl ProcessControlBlock {
         self.inner.exclusive_access()
     }
 
+    /// None if the inner is borrowed, for probe handlers
+    pub fn try_inner_exclusive_access(
+        &self,
+    ) -> Option<UPIntrRefMut<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn new(elf_data: &[u8],path:String) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
@@ -101,6 +128,7 @@ impl ProcessControlBlock {
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
                     path:path,
+                    cpu_time: 0,
                 })
             },
         });
@@ -139,6 +167,7 @@ impl ProcessControlBlock {
         // println!("Getting PCB in src/task/process.rs PCB::exec()");
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // println!("Getting PCB AGAIN in src/task/process.rs PCB::exec()");
+        trace_point!(PROCESS_EXEC, ProcessExecCtx::new(self.getpid(), &path));
         self.inner_exclusive_access().path=path;
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
@@ -228,6 +257,7 @@ impl ProcessControlBlock {
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
                     path: parent_path,
+                    cpu_time: 0,
                 })
             },
         });
@@ -259,6 +289,7 @@ impl ProcessControlBlock {
         trap_cx.kernel_sp = task.kstack.get_top();
         drop(task_inner);
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
+        trace_point!(PROCESS_FORK, ProcessForkCtx::new(self.getpid(), child.getpid()));
         // add this thread to scheduler
         add_task(task);
         child
@@ -267,4 +298,30 @@ impl ProcessControlBlock {
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
+
+    /// 0 if it has no parent
+    pub fn getppid(&self) -> usize {
+        self.inner_exclusive_access()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .map_or(0, |parent| parent.getpid())
+    }
+
+    pub fn state(&self) -> ProcessState {
+        let inner = self.inner_exclusive_access();
+        if inner.is_zombie {
+            return ProcessState::Zombie;
+        }
+        let tasks: Vec<_> = inner.tasks.iter().flatten().cloned().collect();
+        drop(inner);
+        if tasks
+            .iter()
+            .all(|task| task.inner_exclusive_access().task_status == TaskStatus::Blocked)
+        {
+            ProcessState::Sleeping
+        } else {
+            ProcessState::Running
+        }
+    }
 }
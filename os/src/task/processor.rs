@@ -1,14 +1,20 @@
 use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
+use crate::probe::tracepoint::SchedSwitchCtx;
 use crate::sync::UPIntrFreeCell;
+use crate::timer::get_time;
 use crate::trap::TrapContext;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use lazy_static::*;
 
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
     idle_task_cx: TaskContext,
+    /// the task that ran last, reported as prev by the sched_switch tracepoint
+    prev: Weak<TaskControlBlock>,
+    /// when prev was switched to, in clock ticks
+    switched_at: usize,
 }
 
 impl Processor {
@@ -16,6 +22,8 @@ impl Processor {
         Self {
             current: None,
             idle_task_cx: TaskContext::zero_init(),
+            prev: Weak::new(),
+            switched_at: 0,
         }
     }
     fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
@@ -44,18 +52,51 @@ pub fn run_tasks() {
                 task_inner.task_status = TaskStatus::Running;
                 &task_inner.task_cx as *const TaskContext
             });
+            let prev = core::mem::replace(&mut processor.prev, Arc::downgrade(&task));
             processor.current = Some(task);
+            processor.switched_at = get_time();
             // release processor manually
             drop(processor);
+            // fired after current is set, so that handlers see the next task as current
+            trace_point!(
+                SCHED_SWITCH,
+                SchedSwitchCtx::new(
+                    prev.upgrade().map_or(NO_TASK, |prev| task_ids(&prev)),
+                    current_task().map_or(NO_TASK, |next| task_ids(&next)),
+                )
+            );
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            account_cpu_time();
         } else {
             println!("no tasks available in run_tasks");
         }
     }
 }
 
+/// charge the time since the last switch to the process of the task that ran
+fn account_cpu_time() {
+    let (prev, switched_at) =
+        PROCESSOR.exclusive_session(|processor| (processor.prev.upgrade(), processor.switched_at));
+    if let Some(process) = prev.and_then(|task| task.process.upgrade()) {
+        process.inner_exclusive_access().cpu_time += get_time() - switched_at;
+    }
+}
+
+const NO_TASK: (usize, usize) = (usize::MAX, usize::MAX);
+
+/// (pid, tid) of a task, usize::MAX if the task has already exited
+fn task_ids(task: &TaskControlBlock) -> (usize, usize) {
+    let pid = task.process.upgrade().map_or(usize::MAX, |p| p.getpid());
+    let tid = task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .map_or(usize::MAX, |res| res.tid);
+    (pid, tid)
+}
+
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
     PROCESSOR.exclusive_access().take_current()
 }
@@ -64,6 +105,11 @@ pub fn current_task() -> Option<Arc<TaskControlBlock>> {
     PROCESSOR.exclusive_access().current()
 }
 
+/// current_task that does not panic if the processor is borrowed, for the panic handler
+pub fn try_current_task() -> Option<Arc<TaskControlBlock>> {
+    PROCESSOR.try_exclusive_access()?.current()
+}
+
 pub fn current_process() -> Arc<ProcessControlBlock> {
     current_task().unwrap().process.upgrade().unwrap()
 }
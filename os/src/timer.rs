@@ -20,6 +20,10 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+pub fn ticks_to_ms(ticks: usize) -> usize {
+    ticks / (CLOCK_FREQ / MSEC_PER_SEC)
+}
+
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
@@ -3,16 +3,19 @@ mod context;
 extern crate ruprobes;
 
 use crate::config::TRAMPOLINE;
+use crate::probe::tracepoint::PageFaultCtx;
 //use crate::probe::kprobes_breakpoint_handler;
 use ruprobes::uprobes_trap_handler;
 use trapframe::{UserContext, GeneralRegs};
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next, suspend_current_and_run_next, SignalFlags,
+    check_signals_of_current, current_add_signal, current_process, current_trap_cx,
+    current_trap_cx_user_va, current_user_token, exit_current_and_run_next,
+    suspend_current_and_run_next, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
@@ -64,6 +67,7 @@ fn disable_supervisor_interrupt() {
 
 #[no_mangle]
 #[inline(never)]
+#[link_section = ".text.noprobe"]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
     let scause = scause::read();
@@ -78,6 +82,12 @@ pub fn trap_handler() -> ! {
                 // TrapContext(from rCore-Tutorial) => UserContext (from rCore-Plus, supported by ruprobes)
                 uprobes_trap_handler(cx);
             }
+            // a uprobe program called bpf_override_return: skip the user function
+            if let Some(rc) = crate::ebpf::tracepoints::take_uprobe_override() {
+                let cx = current_trap_cx();
+                cx.x[10] = rc as usize;
+                cx.sepc = cx.x[1];
+            }
         }
         Trap::Exception(Exception::UserEnvCall) => {
             // jump to next instruction anyway
@@ -98,7 +108,15 @@ pub fn trap_handler() -> ! {
         | Trap::Exception(Exception::InstructionPageFault)
         | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            
+            trace_point!(
+                PAGE_FAULT,
+                PageFaultCtx::new(
+                    current_process().getpid(),
+                    scause.bits(),
+                    stval,
+                    current_trap_cx().sepc,
+                )
+            );
             println!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
                 scause.cause(),
@@ -114,10 +132,12 @@ pub fn trap_handler() -> ! {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
+            crate::gdbstub::tick();
             suspend_current_and_run_next();
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
+            crate::gdbstub::check_halt(current_trap_cx(), true);
         }
         _ => {
             panic!(
@@ -160,25 +180,49 @@ pub fn trap_return() -> ! {
     }
 }
 extern "C" {
-    fn kprobes_breakpoint_handler(_trap_cx: &TrapContext);
+    fn kprobes_breakpoint_handler(_trap_cx: &mut TrapContext) -> bool;
+}
+
+/// frame of the kernel trap being handled, 0 outside of trap_from_kernel
+static KERNEL_TRAP_CX: AtomicUsize = AtomicUsize::new(0);
+
+/// trap frame of the kernel trap being handled, for the panic handler
+pub fn current_kernel_trap_cx() -> Option<&'static TrapContext> {
+    let tf = KERNEL_TRAP_CX.load(Ordering::Relaxed);
+    if tf == 0 {
+        None
+    } else {
+        Some(unsafe { &*(tf as *const TrapContext) })
+    }
 }
 
 #[no_mangle]
-pub fn trap_from_kernel(mut _trap_cx: &TrapContext) {
+#[link_section = ".text.noprobe"]
+pub fn trap_from_kernel(_trap_cx: &mut TrapContext) {
+    // traps nest when a handler hits a probe
+    let prev_trap_cx = KERNEL_TRAP_CX.swap(_trap_cx as *mut TrapContext as usize, Ordering::Relaxed);
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
+            crate::gdbstub::check_halt(_trap_cx, false);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
+            crate::gdbstub::tick();
             // do not schedule now
         }
         Trap::Exception(Exception::Breakpoint) => {
             println!("[kernel] breakpoint at {:#x}", _trap_cx.sepc);
-            unsafe {kprobes_breakpoint_handler(_trap_cx);}
+            if crate::gdbstub::step_trap_handler(_trap_cx) {
+                // a single step requested by gdb
+            } else if !unsafe { kprobes_breakpoint_handler(_trap_cx) } {
+                // not ours, e.g. an ebreak compiled into the kernel, step over it
+                warn!("[kernel] unexpected breakpoint at {:#x}, skipped", _trap_cx.sepc);
+                _trap_cx.sepc += crate::probe::arch::get_insn_length(_trap_cx.sepc);
+            }
         }
         _ => {
             panic!(
@@ -188,6 +232,7 @@ pub fn trap_from_kernel(mut _trap_cx: &TrapContext) {
             );
         }
     }
+    KERNEL_TRAP_CX.store(prev_trap_cx, Ordering::Relaxed);
 }
 
 pub use context::TrapContext;
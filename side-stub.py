@@ -9,8 +9,35 @@
 import gdb
 import threading
 import serial
+import struct
 import time
 
+# binary trace records sent by bpf_trace_output, see os/src/ebpf/trace_channel.rs
+TRACE_SYNC = b'\xeb\x9f'
+TRACE_HEADER = '<BBHHIQ' # type, cpu, len, seq, pid, time_ns
+TRACE_HEADER_SIZE = struct.calcsize(TRACE_HEADER)
+TRACE_MAX_PAYLOAD = 512
+TRACE_REGS = 1
+TRACE_MEMORY = 2
+TRACE_MESSAGE = 3
+TRACE_DROPPED = 4
+TRACE_ARGS = 5
+REG_NAMES = ['zero', 'ra', 'sp', 'gp', 'tp', 't0', 't1', 't2', 's0', 's1',
+             'a0', 'a1', 'a2', 'a3', 'a4', 'a5', 'a6', 'a7',
+             's2', 's3', 's4', 's5', 's6', 's7', 's8', 's9', 's10', 's11',
+             't3', 't4', 't5', 't6', 'sstatus', 'sepc']
+
+def fletcher16(data):
+    a = b = 0
+    for byte in data:
+        a = (a + byte) % 255
+        b = (b + a) % 255
+    return b << 8 | a
+
+def trace_print(text):
+    # gdb is not thread safe, print from its own thread
+    gdb.post_event(lambda: gdb.write(text))
+
 
 # 用户自定义命令需要继承自gdb.Command类
 class SideStub(gdb.MICommand):
@@ -19,8 +46,8 @@ class SideStub(gdb.MICommand):
     """side-stub
     Usage: -side-stub target remote /dev/tty1
            -side-stub tracepoint-then-get-registers <addr such as 0x80201234>
-           -side-stub tracepoint-then-get-memory <addr> <length>
-           -side-stub tracepoint-then-get-arguments <function name>
+           -side-stub tracepoint-then-get-memory <addr> <memory addr, or register[+-offset]> <length>
+           -side-stub tracepoint-then-get-arguments <function name or addr> [argument count]
            -side-stub tracepoint_user_program_then_get_registers <program_name> <addr such as 0x80201234>
     """
     def __init__(self):
@@ -36,6 +63,7 @@ class SideStub(gdb.MICommand):
 
         self.packets_queue_lock = threading.Lock()
         self.packets_queue = []
+        self.last_seq = None
 
         self.msg_reader_thread = threading.Thread(target=self.msg_reader, name='msg_reader')
         self.msg_reader_thread.start()
@@ -58,8 +86,11 @@ class SideStub(gdb.MICommand):
         elif (argv[0]=='tracepoint-then-get-registers'):
             self.tracepoint_then_get_registers(argv[1])
 
-        elif (argv[0]=='tracepoint_then_get_arguments'):
-            self.tracepoint_then_get_arguments(argv[1])
+        elif (argv[0]=='tracepoint-then-get-memory') and len(argv)==4:
+            self.tracepoint_then_get_memory(argv[1],argv[2],argv[3])
+
+        elif (argv[0] in ('tracepoint-then-get-arguments', 'tracepoint_then_get_arguments')):
+            self.tracepoint_then_get_arguments(*argv[1:3])
         elif (argv[0]=='tracepoint_user_program_then_get_registers'):
             self.tracepoint_user_program_then_get_registers(argv[1],argv[2])
         else:
@@ -69,32 +100,91 @@ class SideStub(gdb.MICommand):
         # gdb.execute('delete ' + argv[0])
         # gdb.execute('break ' + argv[1])
 
+    def send_packet(self, payload):
+        # the checksum covers the whole payload, the kernel stub (os/src/gdbstub) naks anything else
+        data = payload.encode('ascii')
+        self.ser.write(b'$' + data + b'#' + ('%02x' % (sum(data) % 256)).encode('ascii'))
+
     def tracepoint_then_get_registers(self,symbol):
-        # self.write_queue_lock.acquire()
-        command = 'vTR'
-        # self.write_queue.append('#'+command+symbol+'#'+hex(sum(command.encode('ascii')) % 256)[2:])
-        self.ser.write(('$'+command+symbol+'#'+hex(sum(command.encode('ascii')) % 256)[2:]).encode('ascii'))
+        self.send_packet('vTR' + symbol)
 
 
     def tracepoint_user_program_then_get_registers(self,program_name,addr):
-        command = 'vTU' # it's obvious that there should NOT be : in program_name,
-        self.ser.write(('$'+command+program_name+':'+addr+'#'+hex(sum(command.encode('ascii')) % 256)[2:]).encode('ascii'))
+        # it's obvious that there should NOT be : in program_name,
+        self.send_packet('vTU' + program_name + ':' + addr)
 
-    def tracepoint_then_get_arguments(self,fn_name):
-        pass
+    def tracepoint_then_get_memory(self,addr,base,length):
+        # the kernel wants hex, like gdb does
+        self.send_packet('vTM' + addr + ':' + base + ',' + '%x' % int(length, 0))
+
+    def tracepoint_then_get_arguments(self,fn_name,count=None):
+        if count is None:
+            self.send_packet('vTA' + fn_name)
+        else:
+            self.send_packet('vTA' + fn_name + ':' + '%x' % int(count, 0))
     def disconnect(self):
         # should we close another thread here?
         self.ser.close()
         return
+    def read_char(self):
+        # trace records may come between any two characters of the text protocol
+        while True:
+            c = self.ser.read(1)
+            if c == TRACE_SYNC[0:1]:
+                c = self.ser.read(1)
+                if c == TRACE_SYNC[1:2]:
+                    self.read_trace_record()
+                    continue
+            return str(c, 'ascii', errors='replace')
+
+    def read_trace_record(self):
+        header = self.ser.read(TRACE_HEADER_SIZE)
+        rtype, cpu, length, seq, pid, time_ns = struct.unpack(TRACE_HEADER, header)
+        if length > TRACE_MAX_PAYLOAD:
+            trace_print('eBPF trace: corrupted record header\n')
+            return
+        payload = self.ser.read(length)
+        (checksum,) = struct.unpack('<H', self.ser.read(2))
+        if fletcher16(header + payload) != checksum:
+            trace_print('eBPF trace: record %d has a bad checksum\n' % seq)
+            return
+        if self.last_seq is not None and seq != (self.last_seq + 1) & 0xffff:
+            trace_print('eBPF trace: records %d to %d lost on the line\n' % (self.last_seq + 1, seq - 1))
+        self.last_seq = seq
+        prefix = 'eBPF [time %d ns, hart %d, pid %d] ' % (time_ns, cpu, pid)
+        if rtype == TRACE_REGS:
+            values = struct.unpack('<%dQ' % (length // 8), payload)
+            text = prefix + 'registers at %#x:\n' % values[0]
+            for i, value in enumerate(values[1:]):
+                text += '  %-7s %#018x%s' % (REG_NAMES[i], value, '\n' if i % 4 == 3 else '')
+            trace_print(text + '\n')
+        elif rtype == TRACE_MEMORY:
+            (addr,) = struct.unpack('<Q', payload[:8])
+            data = payload[8:]
+            text = prefix + 'memory at %#x:\n' % addr
+            for off in range(0, len(data), 16):
+                text += '  %#x: %s\n' % (addr + off, data[off:off + 16].hex(' '))
+            trace_print(text)
+        elif rtype == TRACE_ARGS:
+            values = struct.unpack('<%dQ' % (length // 8), payload)
+            args = ', '.join('a%d=%#x' % (i, value) for i, value in enumerate(values[1:]))
+            trace_print(prefix + 'arguments at %#x: %s\n' % (values[0], args))
+        elif rtype == TRACE_MESSAGE:
+            trace_print(prefix + payload.decode('utf-8', errors='replace') + '\n')
+        elif rtype == TRACE_DROPPED:
+            (count,) = struct.unpack('<I', payload)
+            trace_print('eBPF trace: %d records dropped, the kernel queue was full\n' % count)
+        else:
+            trace_print(prefix + 'unknown record type %d\n' % rtype)
+
     def read_async_msg(self,starts_with):
         msg=''
         end_count = 10000000 # todo: set this as msg_max_len
         # print("gonna loop")
         while end_count > 0:
-            c = self.ser.read(1)
-            if c == b'\x00':
+            c = self.read_char()
+            if c == '\x00':
                 continue
-            c = str(c,'ascii')
             # gdb.execute("echo "+c)
             msg+=c
             if c == '#':
@@ -109,7 +199,7 @@ class SideStub(gdb.MICommand):
             input_stream = "" # **A** packet
             end_count = 10000000 # todo: set this as msg_max_len
             while (end_count > 0):
-                c = str(self.ser.read(1),encoding='ascii')
+                c = self.read_char()
                 if c == '+':
                     pass
                 elif c == '%':
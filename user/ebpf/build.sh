@@ -27,7 +27,7 @@ make
 popd
 
 userprogs=("naivetest" "maptest" "kernmaptest" "loadprogextest" "gdbserver")
-kernprogs=("map" "time1" "context" "get_regs")
+kernprogs=("map" "time1" "context" "get_regs" "syscall_count" "fail_open" "current_task" "globals" "multi")
 objcopy="riscv64-unknown-elf-objcopy"
 for i in ${userprogs[@]};
 do
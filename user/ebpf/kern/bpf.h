@@ -7,10 +7,32 @@ static void (*bpf_print_str)(const char *s, int len) = (void*) 9;
 static int (*__bpf_trace_printk)(const char *fmt, int fmt_size, long p1, long p2, long p3) = (void*) 6;
 static void* (*bpf_map_lookup_elem)(int map_fd, const void *key, void *value) = (void*) 1;
 static int (*bpf_map_update_elem)(int map_fd, const void *key, const void *value, u64 flags) = (void*) 2;
+static int (*bpf_probe_read)(void *dst, int size, const void *src) = (void*) 4;
 static u64 (*bpf_ktime_get_ns)() = (void*) 5;
 static int (*bpf_get_smp_processor_id)() = (void*) 8;
 static i64 (*bpf_get_current_pid_tgid)() = (void*) 14;
 static int (*bpf_get_current_comm)(char *buf, int max_size) = (void*) 16;
+// address of the current TaskControlBlock, see bpf_core.h to read its fields
+static u64 (*bpf_get_current_task)() = (void*) 35;
+// only effective on kprobes of allow-listed functions (sys_open, sys_read, ...) and uprobe_syncfunc
+static int (*bpf_override_return)(void *ctx, u64 rc) = (void*) 58;
+// send a typed binary record to the debugger over the uart1 trace channel, decoded by side-stub.py
+// returns -7 (E2BIG) above BPF_TRACE_MAX_PAYLOAD bytes, -16 (EBUSY) if the channel queue is full
+// not a linux helper, its id is past the ones linux uses
+static int (*bpf_trace_output)(u64 type, const void *data, u64 size) = (void*) 256;
+
+#define BPF_TRACE_MAX_PAYLOAD 512
+// probed address, then struct pt_regs: send &ctx->paddr with sizeof(ctx->paddr) + sizeof(ctx->regs)
+#define BPF_TRACE_REGS 1
+// address, then the bytes read there
+#define BPF_TRACE_MEMORY 2
+// text
+#define BPF_TRACE_MESSAGE 3
+// probed address, then argument registers a0, a1, ...
+#define BPF_TRACE_ARGS 5
+
+// put a program in its own section, each section of an ELF is loaded as a separate program
+#define SEC(name) __attribute__((section(name), used))
 
 #define bpf_trace_printk(fmt, p1, p2, p3) do { \
     const char _fmt[] = fmt; \
@@ -0,0 +1,24 @@
+#pragma once
+
+// field offsets of kernel structs, resolved when the program is loaded
+// the names are the rust struct and field names, see os_btf_structs in os/src/ebpf/osutil.rs
+// loading fails if a struct or field does not exist in the running kernel
+
+#include "bpf.h"
+
+#define __BPF_CORE_SYM(name) ({ extern char name[]; (u64)name; })
+
+#define BPF_CORE_OFFSET(s, f) __BPF_CORE_SYM(__btf_off__##s##__##f)
+#define BPF_CORE_FIELD_SIZE(s, f) __BPF_CORE_SYM(__btf_size__##s##__##f)
+#define BPF_CORE_SIZE(s) __BPF_CORE_SYM(__btf_size__##s)
+
+// read field f of struct s at kernel address ptr into *dst
+#define BPF_CORE_READ_INTO(dst, ptr, s, f) \
+    bpf_probe_read(dst, sizeof(*(dst)), (const void *)((u64)(ptr) + BPF_CORE_OFFSET(s, f)))
+
+// read a u64 field of struct s at kernel address ptr
+#define BPF_CORE_READ(ptr, s, f) ({ \
+    u64 __v = 0; \
+    BPF_CORE_READ_INTO(&__v, ptr, s, f); \
+    __v; \
+})
@@ -0,0 +1,116 @@
+// generated by os/build.rs from os/src/ebpf/ctx_abi.rs, copied here by `make ctx-header`, do not edit
+#ifndef __LIBS_BPF_CTX_H__
+#define __LIBS_BPF_CTX_H__
+
+typedef unsigned long long size_t;
+typedef long long ssize_t;
+
+// x0-x31, then sstatus and sepc
+struct pt_regs {
+  size_t regs[32];
+  size_t sstatus;
+  size_t sepc;
+};
+
+// x is a struct pt_regs *, e.g. &ctx->regs of a kprobe_ctx
+#define __PT_REG(x, i) (((size_t *)(x))[i])
+#define PT_REGS_PARM1(x) __PT_REG(x, 10)
+#define PT_REGS_PARM2(x) __PT_REG(x, 11)
+#define PT_REGS_PARM3(x) __PT_REG(x, 12)
+#define PT_REGS_PARM4(x) __PT_REG(x, 13)
+#define PT_REGS_PARM5(x) __PT_REG(x, 14)
+#define PT_REGS_PARM6(x) __PT_REG(x, 15)
+#define PT_REGS_RC(x) __PT_REG(x, 10)
+#define PT_REGS_RET(x) __PT_REG(x, 1)
+#define PT_REGS_SP(x) __PT_REG(x, 2)
+#define PT_REGS_FP(x) __PT_REG(x, 8)
+#define PT_REGS_IP(x) __PT_REG(x, 33)
+
+// ptype of kprobe_ctx, PT_REGS_RC is only valid on KRETPROBE_EXIT
+#define BPF_PTYPE_KPROBE 0
+#define BPF_PTYPE_KRETPROBE_ENTRY 1
+#define BPF_PTYPE_KRETPROBE_EXIT 2
+// paddr is the accessed address
+#define BPF_PTYPE_WATCHPOINT 3
+// ptype of uprobe_ctx
+#define BPF_PTYPE_UPROBE_SYNCFUNC 0
+
+// kprobe$, kretprobe@entry$, kretprobe@exit$, watchpoint$, watchpoint_rw$, 288 bytes
+struct kprobe_ctx {
+  size_t ptype;
+  size_t paddr;
+  struct pt_regs regs;
+};
+
+// uprobe_syncfunc$, 288 bytes
+struct uprobe_ctx {
+  size_t ptype;
+  size_t paddr;
+  struct pt_regs regs;
+};
+
+// raw_syscalls$sys_enter, raw_syscalls$sys_exit, 56 bytes
+struct raw_syscall_ctx {
+  size_t id;
+  size_t args[3];
+  ssize_t ret;
+  size_t pid;
+  size_t tid;
+};
+
+// fentry$, 80 bytes
+struct fentry_ctx {
+  size_t pc;
+  size_t caller;
+  size_t args[8];
+};
+
+// tracepoint$syscalls:sys_enter, 32 bytes
+struct sys_enter_ctx {
+  size_t id;
+  size_t args[3];
+};
+
+// tracepoint$syscalls:sys_exit, 40 bytes
+struct sys_exit_ctx {
+  size_t id;
+  size_t args[3];
+  ssize_t ret;
+};
+
+// tracepoint$sched:sched_switch, 32 bytes
+struct sched_switch_ctx {
+  size_t prev_pid;
+  size_t prev_tid;
+  size_t next_pid;
+  size_t next_tid;
+};
+
+// tracepoint$sched:process_fork, 16 bytes
+struct process_fork_ctx {
+  size_t parent_pid;
+  size_t child_pid;
+};
+
+// tracepoint$sched:process_exec, 40 bytes
+struct process_exec_ctx {
+  size_t pid;
+  char path[32];
+};
+
+// tracepoint$sched:process_exit, 24 bytes
+struct process_exit_ctx {
+  size_t pid;
+  size_t tid;
+  ssize_t exit_code;
+};
+
+// tracepoint$exceptions:page_fault, 32 bytes
+struct page_fault_ctx {
+  size_t pid;
+  size_t cause;
+  size_t addr;
+  size_t pc;
+};
+
+#endif
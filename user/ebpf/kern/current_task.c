@@ -0,0 +1,17 @@
+#include "bpf.h"
+#include "bpf_core.h"
+
+// print the pid and tid of the current task without hard-coded offsets
+int bpf_prog(void *ctx) {
+  u64 task = bpf_get_current_task();
+  if (task == 0)
+    return 0;
+  // TaskControlBlock.process is a Weak pointing at the ArcInner of the process
+  u64 process = BPF_CORE_READ(task, TaskControlBlock, process) + BPF_CORE_OFFSET(ArcInner, data);
+  u64 pid = BPF_CORE_READ(process, ProcessControlBlock, pid);
+  u64 inner = task + BPF_CORE_OFFSET(TaskControlBlock, inner);
+  u64 kstack = BPF_CORE_READ(task, TaskControlBlock, kstack);
+  bpf_trace_printk("pid = {}, kstack id = {}\n", pid, kstack, 0);
+  bpf_trace_printk("task inner at {}\n", inner, 0, 0);
+  return 0;
+}
@@ -0,0 +1,9 @@
+#include "bpf.h"
+#include "kprobe.h"
+
+// attach to "kprobe$<address of sys_open>", makes every open fail with -1
+int bpf_prog(struct kprobe_bpf_ctx *ctx) {
+  bpf_trace_printk("fail sys_open at {}\n", ctx->paddr, 0, 0);
+  bpf_override_return(ctx, -1);
+  return 0;
+}
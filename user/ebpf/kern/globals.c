@@ -0,0 +1,13 @@
+#include "bpf.h"
+
+// globals live in internal array maps created by the loader
+u64 hits;                        // .bss
+u64 every = 10;                  // .data
+const char banner[] = "hit {} times\n"; // .rodata
+
+int bpf_prog(void *ctx) {
+  hits++;
+  if (hits % every == 0)
+    __bpf_trace_printk(banner, sizeof(banner), hits, 0, 0);
+  return 0;
+}
@@ -3,6 +3,8 @@
 
 typedef unsigned long long size_t;
 
+// same layout as struct kprobe_ctx in bpf_ctx.h, which also has the PT_REGS_* accessors
+
 #define KPROBE_TYPE_KPROBE 0
 #define KRPOBE_TYPE_KRETPROBE_ENTRY 1
 #define KPROBE_TYPE_KRETPROBE_EXIT 2
@@ -0,0 +1,28 @@
+#include "bpf.h"
+#include "bpf_ctx.h"
+
+// every SEC is a program of its own, the loader returns one fd per section.
+// count() is a bpf-to-bpf call, the kernel inlines it into both programs.
+
+u64 enters;
+u64 exits;
+
+static __attribute__((noinline)) u64 count(u64 *counter) {
+  *counter += 1;
+  return *counter;
+}
+
+SEC("kprobe")
+int on_enter(struct kprobe_ctx *ctx) {
+  count(&enters);
+  return 0;
+}
+
+SEC("kretprobe")
+int on_exit(struct kprobe_ctx *ctx) {
+  if (count(&exits) % 16 == 0) {
+    char fmt[] = "{} enters, {} exits\n";
+    __bpf_trace_printk(fmt, sizeof(fmt), enters, exits, 0);
+  }
+  return 0;
+}
@@ -0,0 +1,14 @@
+#include "bpf.h"
+#include "tracepoint.h"
+
+// attach to "raw_syscalls$sys_enter", counts syscalls by id
+extern int syscall_counts;
+
+int bpf_prog(struct raw_syscall_ctx *ctx) {
+  u64 id = ctx->id;
+  u64 count = 0;
+  bpf_map_lookup_elem(syscall_counts, &id, &count);
+  count = count + 1;
+  bpf_map_update_elem(syscall_counts, &id, &count, 0);
+  return 0;
+}
@@ -0,0 +1,26 @@
+#ifndef __LIBS_TRACEPOINT_H__
+#define __LIBS_TRACEPOINT_H__
+
+// contexts of static kernel tracepoints, see os/src/probe/tracepoint.rs
+// attach with "tracepoint$<name>" or "tracepoint$<category>:<name>"
+
+#include "bpf_ctx.h"
+
+#define TP_COMM_LEN 32
+
+// the context structs are generated into bpf_ctx.h:
+// syscalls:sys_enter       struct sys_enter_ctx
+// syscalls:sys_exit        struct sys_exit_ctx
+// sched:sched_switch       struct sched_switch_ctx, prev_pid is -1 on the first switch
+// sched:process_fork       struct process_fork_ctx
+// sched:process_exec       struct process_exec_ctx, path is truncated to TP_COMM_LEN - 1
+// sched:process_exit       struct process_exit_ctx, once per process when its main thread exits
+// exceptions:page_fault    struct page_fault_ctx
+
+// raw_syscalls$sys_enter and raw_syscalls$sys_exit use struct raw_syscall_ctx, ret is 0 on enter
+// append "@<id>" to the target to only run on one syscall, e.g. "raw_syscalls$sys_enter@64"
+
+// fentry$<addr> uses struct fentry_ctx, calls of a function patched by ftrace, no trap is taken
+// args are a0-a7 at function entry
+
+#endif
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::bpf::{bpf_enable_stats, BpfProgram};
+use user_lib::{close, open, read, sleep, OpenFlags};
+
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let size = read(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..size as usize]);
+    }
+    close(fd);
+    Some(data)
+}
+
+/// usage: bpf_load <eBPF ELF> <target> [ms]
+/// loads the programs of the ELF, attaches them to the target for a while, e.g.
+/// `bpf_load ebpf_kern_syscall_count tracepoint$syscalls:sys_enter 1000`
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 3 {
+        println!("usage: bpf_load <eBPF ELF> <target> [ms]");
+        return -1;
+    }
+    let duration = if argc > 3 { argv[3].parse().unwrap_or(1000) } else { 1000 };
+    let elf = match read_file(argv[1]) {
+        Some(elf) => elf,
+        None => {
+            println!("bpf_load: can not open {}", argv[1]);
+            return -1;
+        }
+    };
+    let progs = match BpfProgram::load_elf(&elf, &[]) {
+        Some(progs) => progs,
+        None => {
+            println!("bpf_load: failed to load {}", argv[1]);
+            return -1;
+        }
+    };
+
+    bpf_enable_stats(true);
+    for prog in progs.iter() {
+        if prog.attach(argv[2]) < 0 {
+            println!("bpf_load: failed to attach {} to {}", prog.name(), argv[2]);
+        }
+    }
+    sleep(duration);
+    for prog in progs.iter() {
+        // detach unloads the program, read its stats first
+        if let Some(info) = prog.info() {
+            println!(
+                "{:>4} {:<16} run {} times in {} ns",
+                prog.fd(),
+                prog.name(),
+                info.run_cnt,
+                info.run_time_ns
+            );
+        }
+        prog.detach();
+    }
+    bpf_enable_stats(false);
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::bpf::{BpfMap, BPF_ANY, BPF_EXIST, BPF_MAP_TYPE_ARRAY, BPF_MAP_TYPE_HASH, BPF_NOEXIST};
+
+/// exercises hash and array maps through user_lib::bpf
+#[no_mangle]
+pub fn main() -> i32 {
+    let hash = BpfMap::<u64, u64>::create(BPF_MAP_TYPE_HASH, 16).expect("create hash map");
+    for key in 0..8u64 {
+        assert_eq!(hash.update(&key, &(key * key), BPF_ANY), 0);
+    }
+    assert_eq!(hash.lookup(&3), Some(9));
+    assert!(hash.lookup(&100).is_none());
+    assert!(hash.update(&3, &0, BPF_NOEXIST) < 0);
+    assert!(hash.update(&100, &0, BPF_EXIST) < 0);
+    assert_eq!(hash.delete(&3), 0);
+    assert!(hash.lookup(&3).is_none());
+
+    let mut elems: Vec<(u64, u64)> = hash.iter().collect();
+    elems.sort();
+    assert_eq!(elems.len(), 7);
+    for (key, value) in elems.iter() {
+        assert_ne!(*key, 3);
+        assert_eq!(*value, key * key);
+    }
+
+    let array = BpfMap::<u32, u64>::create(BPF_MAP_TYPE_ARRAY, 4).expect("create array map");
+    assert_eq!(array.update(&2, &42, BPF_ANY), 0);
+    assert!(array.update(&4, &0, BPF_ANY) < 0);
+    let elems: Vec<(u32, u64)> = array.iter().collect();
+    assert_eq!(elems, [(0, 0), (1, 0), (2, 42), (3, 0)]);
+
+    println!("bpf_maptest passed!");
+    0
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::bpf::{bpf_enable_stats, BpfProgram};
+use user_lib::{close, getpid, open, read, OpenFlags};
+
+const ELF: &str = "ebpf_kern_multi\0";
+const TARGET: &str = "os::syscall::process::sys_getpid";
+const CALLS: u64 = 32;
+
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let size = read(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..size as usize]);
+    }
+    close(fd);
+    Some(data)
+}
+
+/// loads user/ebpf/kern/multi.c, whose programs make a bpf-to-bpf call and pass a stack
+/// buffer to a helper, and runs them on every getpid
+#[no_mangle]
+pub fn main() -> i32 {
+    let elf = read_file(ELF).expect("read ebpf_kern_multi");
+    let progs = BpfProgram::load_elf(&elf, &[]).expect("load ebpf_kern_multi");
+    assert_eq!(progs.len(), 2);
+
+    bpf_enable_stats(true);
+    for prog in progs.iter() {
+        let target = match prog.name() {
+            "kprobe" => alloc::format!("kprobe${}", TARGET),
+            "kretprobe" => alloc::format!("kretprobe@exit${}", TARGET),
+            name => panic!("unexpected program {}", name),
+        };
+        assert_eq!(prog.attach(&target), 0);
+    }
+    for _ in 0..CALLS {
+        getpid();
+    }
+    for prog in progs.iter() {
+        let info = prog.info().expect("program info");
+        assert!(info.run_cnt >= CALLS);
+        println!("{:<16} run {} times", prog.name(), info.run_cnt);
+        assert_eq!(prog.detach(), 0);
+    }
+    bpf_enable_stats(false);
+    println!("bpf_multitest passed!");
+    0
+}
@@ -0,0 +1,235 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::bpf::*;
+use user_lib::{close, open, read, OpenFlags};
+
+const USAGE: &str = "usage:
+  bpftool prog [list]
+  bpftool prog show <fd>
+  bpftool prog load <eBPF ELF> <target> [map <name>=<fd>]...
+                                          target e.g. kprobe$<sym or 0x addr>,
+                                          uprobe_syncfunc$<path>$<0x addr>
+  bpftool prog detach <fd>                detach and unload
+  bpftool map [list]
+  bpftool map dump <fd> [hex|int]
+  bpftool stats on|off";
+
+fn parse_fd(arg: &str) -> Option<u32> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let size = read(fd, &mut buf);
+        if size <= 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..size as usize]);
+    }
+    close(fd);
+    Some(data)
+}
+
+fn map_type_name(map_type: u32) -> &'static str {
+    match map_type {
+        BPF_MAP_TYPE_HASH => "hash",
+        BPF_MAP_TYPE_ARRAY => "array",
+        _ => "unknown",
+    }
+}
+
+fn print_prog(fd: u32, info: &BpfProgInfo) {
+    println!(
+        "{:#x}: {:<20} jited {}B  maps {}  run_cnt {}  run_time_ns {}  misses {}",
+        fd,
+        info.name(),
+        info.jited_prog_len,
+        info.nr_map_fds,
+        info.run_cnt,
+        info.run_time_ns,
+        info.recursion_misses
+    );
+}
+
+fn prog_list() -> i32 {
+    for fd in bpf_prog_fds() {
+        if let Some(info) = BpfProgram::from_fd(fd).info() {
+            print_prog(fd, &info);
+        }
+    }
+    0
+}
+
+fn prog_show(fd: u32) -> i32 {
+    match BpfProgram::from_fd(fd).info() {
+        Some(info) => {
+            print_prog(fd, &info);
+            if info.run_cnt > 0 {
+                println!("  avg {} ns per run", info.run_time_ns / info.run_cnt);
+            }
+            0
+        }
+        None => {
+            println!("bpftool: no program {:#x}", fd);
+            -1
+        }
+    }
+}
+
+/// `map <name>=<fd>` pairs, the fds of the maps an ELF refers to
+fn parse_maps<'a>(args: &[&'a str]) -> Option<Vec<(&'a str, u32)>> {
+    if args.len() % 2 != 0 {
+        return None;
+    }
+    args.chunks(2)
+        .map(|pair| match (pair[0], pair[1].split_once('=')) {
+            ("map", Some((name, fd))) if !name.is_empty() => Some((name, parse_fd(fd)?)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn prog_load(path: &str, target: &str, maps: &[(&str, u32)]) -> i32 {
+    let elf = match read_file(path) {
+        Some(elf) => elf,
+        None => {
+            println!("bpftool: can not open {}", path);
+            return -1;
+        }
+    };
+    let progs = match BpfProgram::load_elf(&elf, maps) {
+        Some(progs) => progs,
+        None => {
+            println!(
+                "bpftool: failed to load {}, are its maps given with map <name>=<fd>?",
+                path
+            );
+            return -1;
+        }
+    };
+    let mut ret = 0;
+    for prog in progs.iter() {
+        if prog.attach(target) < 0 {
+            // detach also unloads a program that is not attached, so it is not left behind
+            if prog.detach() < 0 {
+                println!("bpftool: failed to attach {} to {}", prog.name(), target);
+            } else {
+                println!("bpftool: failed to attach {} to {}, unloaded", prog.name(), target);
+            }
+            ret = -1;
+        } else {
+            println!("{:#x}: {} attached to {}", prog.fd(), prog.name(), target);
+        }
+    }
+    ret
+}
+
+fn prog_detach(fd: u32) -> i32 {
+    if !bpf_prog_fds().contains(&fd) {
+        println!("bpftool: {:#x} is not a program", fd);
+        return -1;
+    }
+    if BpfProgram::from_fd(fd).detach() < 0 {
+        println!("bpftool: failed to detach {:#x}", fd);
+        return -1;
+    }
+    0
+}
+
+fn map_list() -> i32 {
+    for fd in bpf_map_fds() {
+        if let Some(info) = bpf_map_info(fd) {
+            println!(
+                "{:#x}: {:<6} key {}B  value {}B  max_entries {}",
+                fd,
+                map_type_name(info.map_type),
+                info.key_size,
+                info.value_size,
+                info.max_entries
+            );
+        }
+    }
+    0
+}
+
+/// little endian words of 8 bytes, or 4 if the size is not a multiple of 8
+fn print_ints(bytes: &[u8]) {
+    let word = if bytes.len() % 8 == 0 { 8 } else { 4 };
+    if bytes.len() % word != 0 {
+        return print_hex(bytes);
+    }
+    for chunk in bytes.chunks(word) {
+        let mut value = 0u64;
+        for (i, b) in chunk.iter().enumerate() {
+            value |= (*b as u64) << (i * 8);
+        }
+        print!("{} ", value);
+    }
+}
+
+fn print_hex(bytes: &[u8]) {
+    for b in bytes {
+        print!("{:02x} ", b);
+    }
+}
+
+fn map_dump(fd: u32, as_int: bool) -> i32 {
+    let elems = match bpf_map_dump_raw(fd) {
+        Some(elems) => elems,
+        None => {
+            println!("bpftool: no map {:#x}", fd);
+            return -1;
+        }
+    };
+    for (key, value) in elems.iter() {
+        print!("key: ");
+        if as_int { print_ints(key) } else { print_hex(key) }
+        print!(" value: ");
+        if as_int { print_ints(value) } else { print_hex(value) }
+        println!("");
+    }
+    println!("{} elements", elems.len());
+    0
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let arg = |i: usize| if i < argc { argv[i] } else { "" };
+    let fd = parse_fd(arg(3));
+    match (arg(1), arg(2)) {
+        ("prog", "") | ("prog", "list") => prog_list(),
+        ("prog", "show") if fd.is_some() => prog_show(fd.unwrap()),
+        ("prog", "load") if argc > 4 => match parse_maps(&argv[5..argc]) {
+            Some(maps) => prog_load(arg(3), arg(4), &maps),
+            None => {
+                println!("{}", USAGE);
+                -1
+            }
+        },
+        ("prog", "detach") if fd.is_some() => prog_detach(fd.unwrap()),
+        ("map", "") | ("map", "list") => map_list(),
+        ("map", "dump") if fd.is_some() => map_dump(fd.unwrap(), arg(4) == "int"),
+        ("stats", "on") => bpf_enable_stats(true) as i32,
+        ("stats", "off") => bpf_enable_stats(false) as i32,
+        _ => {
+            println!("{}", USAGE);
+            -1
+        }
+    }
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    klog_read, klog_set_console_level, klog_set_level, klog_set_module_level, KlogEntry,
+};
+
+const BATCH: usize = 16;
+const LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+const USAGE: &str = "usage: dmesg [-l <level> | -n <level> | -m <module> <level|->]";
+
+fn parse_level(name: &str) -> Option<usize> {
+    LEVELS
+        .iter()
+        .position(|&level| level == name)
+        .or_else(|| name.parse().ok().filter(|&level| level < LEVELS.len()))
+}
+
+/// usage: dmesg [-l <level> | -n <level> | -m <module> <level|->]
+/// prints and clears the kernel log, or changes what it keeps:
+/// * -l: the max level
+/// * -n: the level printed to the console too
+/// * -m: the level of a module, e.g. `os::ebpf`, `-` removes it
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let ret = match (argc, argv.get(1).copied()) {
+        (1, _) => return print_log(),
+        (3, Some("-l")) => parse_level(argv[2]).map(klog_set_level),
+        (3, Some("-n")) => parse_level(argv[2]).map(klog_set_console_level),
+        (4, Some("-m")) if argv[3] == "-" => Some(klog_set_module_level(argv[2], None)),
+        (4, Some("-m")) => {
+            parse_level(argv[3]).map(|level| klog_set_module_level(argv[2], Some(level)))
+        }
+        _ => None,
+    };
+    match ret {
+        Some(0) => 0,
+        Some(_) => {
+            println!("dmesg: the kernel refused the filter");
+            -1
+        }
+        None => {
+            println!("{}", USAGE);
+            -1
+        }
+    }
+}
+
+fn print_log() -> i32 {
+    let mut buf = [KlogEntry::default(); BATCH];
+    loop {
+        let n = klog_read(&mut buf);
+        if n <= 0 {
+            break;
+        }
+        for entry in buf[..n as usize].iter() {
+            let level = LEVELS.get(entry.level as usize).copied().unwrap_or("?");
+            println!(
+                "[{:>5}.{:06}] {:>5} hart {} pid {:>4} {}",
+                entry.timestamp / 1_000_000_000,
+                entry.timestamp / 1_000 % 1_000_000,
+                level,
+                entry.hart,
+                entry.pid as isize,
+                entry.message()
+            );
+        }
+    }
+    0
+}
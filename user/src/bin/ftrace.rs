@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{ftrace_disable, ftrace_enable, ftrace_read, sleep, FtraceEntry};
+
+const BATCH: usize = 64;
+
+/// usage: ftrace [0x<function addr>] [ms]
+/// traces one kernel function, or all of them, for a while and prints the calls
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let addr = if argc > 1 {
+        match usize::from_str_radix(argv[1].trim_start_matches("0x"), 16) {
+            Ok(addr) => addr,
+            Err(_) => {
+                println!("usage: ftrace [0x<function addr>] [ms]");
+                return -1;
+            }
+        }
+    } else {
+        0
+    };
+    let duration = if argc > 2 { argv[2].parse().unwrap_or(100) } else { 100 };
+
+    let enabled = ftrace_enable(addr);
+    if enabled < 0 {
+        println!("ftrace: {:#x} can not be traced", addr);
+        return -1;
+    }
+    println!("ftrace: {} functions enabled", enabled);
+    sleep(duration);
+    ftrace_disable(addr);
+
+    let mut buf = [FtraceEntry::default(); BATCH];
+    let mut total = 0;
+    loop {
+        let n = ftrace_read(&mut buf);
+        if n <= 0 {
+            break;
+        }
+        for entry in buf[..n as usize].iter() {
+            println!(
+                "{:>12} pid {:>4} {:#x} <- {:#x}",
+                entry.timestamp, entry.pid as isize, entry.pc, entry.caller
+            );
+        }
+        total += n;
+    }
+    println!("ftrace: {} calls", total);
+    0
+}
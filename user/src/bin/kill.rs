@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{kill, SignalFlags};
+
+const USAGE: &str = "usage: kill <pid> [signal]";
+const SIGNALS: [(&str, SignalFlags); 5] = [
+    ("INT", SignalFlags::SIGINT),
+    ("ILL", SignalFlags::SIGILL),
+    ("ABRT", SignalFlags::SIGABRT),
+    ("FPE", SignalFlags::SIGFPE),
+    ("SEGV", SignalFlags::SIGSEGV),
+];
+
+/// a signal number, e.g. 2, or name, e.g. INT or SIGINT
+fn parse_signal(arg: &str) -> Option<SignalFlags> {
+    if let Ok(signum) = arg.parse::<u32>() {
+        return SignalFlags::from_bits(1i32.checked_shl(signum)?).filter(|flag| !flag.is_empty());
+    }
+    let name = arg.strip_prefix("SIG").unwrap_or(arg);
+    SIGNALS
+        .iter()
+        .find(|&&(signal, _)| signal == name)
+        .map(|&(_, flag)| flag)
+}
+
+/// usage: kill <pid> [signal]
+/// sends a signal to a process, SIGINT by default
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let pid = match argv.get(1).and_then(|pid| pid.parse::<usize>().ok()) {
+        Some(pid) if argc <= 3 => pid,
+        _ => {
+            println!("{}", USAGE);
+            return -1;
+        }
+    };
+    let signal = match argv.get(2) {
+        Some(arg) => match parse_signal(arg) {
+            Some(signal) => signal,
+            None => {
+                println!("kill: unknown signal {}", arg);
+                return -1;
+            }
+        },
+        None => SignalFlags::SIGINT,
+    };
+    if kill(pid, signal.bits()) != 0 {
+        println!("kill: no process {}", pid);
+        return -1;
+    }
+    0
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getprocs, ProcInfo};
+
+const MAX_PROCS: usize = 64;
+
+/// usage: ps
+/// lists the live processes, TIME is the cpu time their threads have run
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [ProcInfo::default(); MAX_PROCS];
+    let n = getprocs(&mut buf);
+    if n < 0 {
+        println!("ps: cannot read the process list");
+        return -1;
+    }
+    println!(
+        "{:>5} {:>5} S {:>3} {:>8} {:>8} CMD",
+        "PID", "PPID", "THR", "MEM(KB)", "TIME(ms)"
+    );
+    for info in buf[..n as usize].iter() {
+        println!(
+            "{:>5} {:>5} {} {:>3} {:>8} {:>8} {}",
+            info.pid,
+            info.ppid,
+            info.state_char(),
+            info.threads,
+            info.mem_kb(),
+            info.cpu_time_ms,
+            info.path()
+        );
+    }
+    0
+}
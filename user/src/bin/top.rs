@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{get_time, getprocs, sleep, ProcInfo, PROC_RUNNING, PROC_SLEEPING};
+
+const MAX_PROCS: usize = 64;
+const USAGE: &str = "usage: top [-d <delay ms>] [-n <iterations>]";
+
+/// usage: top [-d <delay ms>] [-n <iterations>]
+/// lists the live processes every delay (1000 ms by default), the busiest first,
+/// and stops after the given number of refreshes (10 by default)
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut delay_ms = 1000;
+    let mut iterations = 10;
+    let mut i = 1;
+    while i < argc {
+        let value = argv
+            .get(i + 1)
+            .and_then(|value| value.parse::<usize>().ok());
+        match (argv[i], value) {
+            ("-d", Some(value)) if value > 0 => delay_ms = value,
+            ("-n", Some(value)) => iterations = value,
+            _ => {
+                println!("{}", USAGE);
+                return -1;
+            }
+        }
+        i += 2;
+    }
+    let mut last = match snapshot() {
+        Some(procs) => procs,
+        None => {
+            println!("top: cannot read the process list");
+            return -1;
+        }
+    };
+    let mut last_time = get_time() as usize;
+    for _ in 0..iterations {
+        sleep(delay_ms);
+        let procs = match snapshot() {
+            Some(procs) => procs,
+            None => return -1,
+        };
+        let now = get_time() as usize;
+        print_procs(&procs, &last, now - last_time);
+        last = procs;
+        last_time = now;
+    }
+    0
+}
+
+fn snapshot() -> Option<Vec<ProcInfo>> {
+    let mut procs = alloc::vec![ProcInfo::default(); MAX_PROCS];
+    let n = getprocs(&mut procs);
+    if n < 0 {
+        return None;
+    }
+    procs.truncate(n as usize);
+    Some(procs)
+}
+
+/// cpu usage since the last refresh, in tenths of a percent
+fn usage(info: &ProcInfo, last: &[ProcInfo], elapsed_ms: usize) -> usize {
+    let before = last
+        .iter()
+        .find(|prev| prev.pid == info.pid)
+        .map_or(0, |prev| prev.cpu_time_ms);
+    info.cpu_time_ms.saturating_sub(before) * 1000 / elapsed_ms.max(1)
+}
+
+fn print_procs(procs: &[ProcInfo], last: &[ProcInfo], elapsed_ms: usize) {
+    let mut rows: Vec<(usize, &ProcInfo)> = procs
+        .iter()
+        .map(|info| (usage(info, last, elapsed_ms), info))
+        .collect();
+    rows.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.pid.cmp(&b.1.pid)));
+    let count = |state| procs.iter().filter(|info| info.state == state).count();
+    // clear the screen and move the cursor home
+    print!("\x1b[2J\x1b[H");
+    println!(
+        "top - {} ms, {} processes: {} running, {} sleeping",
+        get_time(),
+        procs.len(),
+        count(PROC_RUNNING),
+        count(PROC_SLEEPING)
+    );
+    println!(
+        "{:>5} {:>5} S {:>3} {:>8} {:>6} {:>8} CMD",
+        "PID", "PPID", "THR", "MEM(KB)", "%CPU", "TIME(ms)"
+    );
+    for (usage, info) in rows {
+        println!(
+            "{:>5} {:>5} {} {:>3} {:>8} {:>4}.{} {:>8} {}",
+            info.pid,
+            info.ppid,
+            info.state_char(),
+            info.threads,
+            info.mem_kb(),
+            usage / 10,
+            usage % 10,
+            info.cpu_time_ms,
+            info.path()
+        );
+    }
+}
@@ -16,7 +16,7 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{close, dup, exec, fork, open, pipe, wait_nb, waitpid, OpenFlags};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -74,6 +74,18 @@ impl ProcessArguments {
     }
 }
 
+/// reap the background jobs that have exited
+fn reap_jobs() {
+    let mut exit_code: i32 = 0;
+    loop {
+        let pid = wait_nb(&mut exit_code);
+        if pid < 0 {
+            break;
+        }
+        println!("[{}] done, exit code {}", pid, exit_code);
+    }
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
@@ -84,8 +96,12 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 println!("");
-                if !line.is_empty() {
-                    let splited: Vec<_> = line.as_str().split('|').collect();
+                // a trailing & runs the command in the background
+                let command = line.trim_end();
+                let background = command.ends_with('&');
+                let command = command.trim_end_matches('&');
+                if !command.trim().is_empty() {
+                    let splited: Vec<_> = command.split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
                         .map(|&cmd| ProcessArguments::new(cmd))
@@ -186,6 +202,10 @@ pub fn main() -> i32 {
                             close(pipe_fd[0]);
                             close(pipe_fd[1]);
                         }
+                        if background {
+                            println!("[{}]", children.last().unwrap());
+                            children.clear();
+                        }
                         let mut exit_code: i32 = 0;
                         for pid in children.into_iter() {
                             let exit_pid = waitpid(pid as usize, &mut exit_code);
@@ -193,8 +213,9 @@ pub fn main() -> i32 {
                             //println!("Shell: Process {} exited with code {}", pid, exit_code);
                         }
                     }
-                    line.clear();
                 }
+                line.clear();
+                reap_jobs();
                 print!("{}", LINE_START);
             }
             BS | DL => {
@@ -0,0 +1,443 @@
+//! eBPF maps and programs on top of the bpf(2) system call
+//!
+//! the attrs mirror the kernel ones in `os/src/ebpf`, every call returns -1 on error.
+
+use super::*;
+use alloc::format;
+use alloc::vec;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+const BPF_MAP_CREATE: usize = 0;
+const BPF_MAP_LOOKUP_ELEM: usize = 1;
+const BPF_MAP_UPDATE_ELEM: usize = 2;
+const BPF_MAP_DELETE_ELEM: usize = 3;
+const BPF_MAP_GET_NEXT_KEY: usize = 4;
+const BPF_PROG_ATTACH: usize = 8;
+const BPF_PROG_DETACH: usize = 9;
+const BPF_PROG_GET_NEXT_ID: usize = 11;
+const BPF_MAP_GET_NEXT_ID: usize = 12;
+const BPF_OBJ_GET_INFO_BY_FD: usize = 15;
+const BPF_ENABLE_STATS: usize = 32;
+const BPF_PROG_LOAD_EX: usize = 1000;
+
+pub const BPF_MAP_TYPE_HASH: u32 = 1;
+pub const BPF_MAP_TYPE_ARRAY: u32 = 2;
+
+/// update flags
+pub const BPF_ANY: u64 = 0;
+pub const BPF_NOEXIST: u64 = 1;
+pub const BPF_EXIST: u64 = 2;
+
+/// longest program name returned by `BpfProgram::load_elf`, nul included
+pub const BPF_PROG_NAME_LEN: usize = 32;
+/// programs of one ELF `load_elf` can return
+const MAX_PROGS_PER_ELF: usize = 16;
+
+#[repr(C)]
+struct MapAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+}
+
+#[repr(C)]
+struct MapOpAttr {
+    map_fd: u32,
+    key: u64,
+    value_or_nextkey: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct MapFdEntry {
+    name: *const u8,
+    fd: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProgFdEntry {
+    name: [u8; BPF_PROG_NAME_LEN],
+    fd: u32,
+}
+
+#[repr(C)]
+struct ProgramLoadExAttr {
+    elf_prog: u64,
+    elf_size: u32,
+    map_array_len: u32,
+    map_array: *const MapFdEntry,
+    prog_array_len: u32,
+    prog_array: *mut ProgFdEntry,
+}
+
+#[repr(C)]
+struct KprobeAttachAttr {
+    target: *const u8,
+    str_len: u32,
+    prog_fd: u32,
+}
+
+#[repr(C)]
+struct ObjInfoAttr {
+    bpf_fd: u32,
+    info_len: u32,
+    info: u64,
+}
+
+#[repr(C)]
+struct GetNextIdAttr {
+    start_id: u32,
+    next_id: u32,
+    open_flags: u32,
+}
+
+#[repr(C)]
+struct EnableStatsAttr {
+    enable: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+/// what the kernel reports about a loaded program, the time and count need `bpf_enable_stats`
+pub struct BpfProgInfo {
+    pub jited_prog_len: u32,
+    pub nr_map_fds: u32,
+    pub run_time_ns: u64,
+    pub run_cnt: u64,
+    pub recursion_misses: u64,
+    /// ELF section the program was loaded from, nul-terminated
+    pub name: [u8; BPF_PROG_NAME_LEN],
+}
+
+impl BpfProgInfo {
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(BPF_PROG_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+/// what the kernel reports about a map
+pub struct BpfMapInfo {
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+}
+
+fn bpf<T>(cmd: usize, attr: &T) -> isize {
+    sys_bpf(cmd, attr as *const T as usize, size_of::<T>())
+}
+
+fn obj_get_info<T>(fd: u32, info: &mut T) -> isize {
+    let attr = ObjInfoAttr {
+        bpf_fd: fd,
+        info_len: size_of::<T>() as u32,
+        info: info as *mut T as u64,
+    };
+    bpf(BPF_OBJ_GET_INFO_BY_FD, &attr)
+}
+
+fn get_next_id(cmd: usize, start: u32) -> Option<u32> {
+    let mut attr = GetNextIdAttr {
+        start_id: start,
+        next_id: 0,
+        open_flags: 0,
+    };
+    match sys_bpf(cmd, &mut attr as *mut GetNextIdAttr as usize, size_of::<GetNextIdAttr>()) {
+        0 => Some(attr.next_id),
+        _ => None,
+    }
+}
+
+/// fds of the loaded programs, in increasing order
+pub fn bpf_prog_fds() -> Vec<u32> {
+    let mut fds = Vec::new();
+    while let Some(fd) = get_next_id(BPF_PROG_GET_NEXT_ID, fds.last().copied().unwrap_or(0)) {
+        fds.push(fd);
+    }
+    fds
+}
+
+/// fds of the maps, in increasing order
+pub fn bpf_map_fds() -> Vec<u32> {
+    let mut fds = Vec::new();
+    while let Some(fd) = get_next_id(BPF_MAP_GET_NEXT_ID, fds.last().copied().unwrap_or(0)) {
+        fds.push(fd);
+    }
+    fds
+}
+
+/// info of the map `fd`, whatever its key and value types
+pub fn bpf_map_info(fd: u32) -> Option<BpfMapInfo> {
+    let mut info = BpfMapInfo::default();
+    match obj_get_info(fd, &mut info) {
+        0 => Some(info),
+        _ => None,
+    }
+}
+
+/// the elements of the map `fd` as raw bytes, for tools that do not know its types
+pub fn bpf_map_dump_raw(fd: u32) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let info = bpf_map_info(fd)?;
+    let mut elems = Vec::new();
+    let mut key: Option<Vec<u8>> = None;
+    loop {
+        let mut next = vec![0u8; info.key_size as usize];
+        let attr = MapOpAttr {
+            map_fd: fd,
+            key: key.as_ref().map_or(0, |key| key.as_ptr() as u64),
+            value_or_nextkey: next.as_mut_ptr() as u64,
+            flags: 0,
+        };
+        if bpf(BPF_MAP_GET_NEXT_KEY, &attr) != 0 {
+            break;
+        }
+        let mut value = vec![0u8; info.value_size as usize];
+        let attr = MapOpAttr {
+            map_fd: fd,
+            key: next.as_ptr() as u64,
+            value_or_nextkey: value.as_mut_ptr() as u64,
+            flags: 0,
+        };
+        if bpf(BPF_MAP_LOOKUP_ELEM, &attr) == 0 {
+            elems.push((next.clone(), value));
+        }
+        key = Some(next);
+    }
+    Some(elems)
+}
+
+/// a map with keys of type `K` and values of type `V`
+pub struct BpfMap<K, V> {
+    fd: u32,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: Copy + Default, V: Copy + Default> BpfMap<K, V> {
+    /// create a map of `map_type` holding up to `max_entries` elements
+    /// array maps need `K` to be `u32`
+    pub fn create(map_type: u32, max_entries: u32) -> Option<Self> {
+        let attr = MapAttr {
+            map_type,
+            key_size: size_of::<K>() as u32,
+            value_size: size_of::<V>() as u32,
+            max_entries,
+        };
+        let fd = bpf(BPF_MAP_CREATE, &attr);
+        if fd < 0 {
+            return None;
+        }
+        Some(Self::from_fd(fd as u32))
+    }
+
+    /// a map created elsewhere, e.g. by another process
+    pub fn from_fd(fd: u32) -> Self {
+        Self {
+            fd,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn fd(&self) -> u32 {
+        self.fd
+    }
+
+    pub fn info(&self) -> Option<BpfMapInfo> {
+        bpf_map_info(self.fd)
+    }
+
+    fn op(&self, cmd: usize, key: *const K, value: *const V, flags: u64) -> isize {
+        let attr = MapOpAttr {
+            map_fd: self.fd,
+            key: key as u64,
+            value_or_nextkey: value as u64,
+            flags,
+        };
+        bpf(cmd, &attr)
+    }
+
+    pub fn lookup(&self, key: &K) -> Option<V> {
+        let mut value = V::default();
+        match self.op(BPF_MAP_LOOKUP_ELEM, key, &mut value, 0) {
+            0 => Some(value),
+            _ => None,
+        }
+    }
+
+    /// `flags` is one of BPF_ANY, BPF_NOEXIST and BPF_EXIST
+    pub fn update(&self, key: &K, value: &V, flags: u64) -> isize {
+        self.op(BPF_MAP_UPDATE_ELEM, key, value, flags)
+    }
+
+    pub fn delete(&self, key: &K) -> isize {
+        self.op(BPF_MAP_DELETE_ELEM, key, core::ptr::null(), 0)
+    }
+
+    /// the key after `key`, the first key if `key` is None
+    pub fn next_key(&self, key: Option<&K>) -> Option<K> {
+        let mut next = K::default();
+        let key = key.map_or(core::ptr::null(), |key| key as *const K);
+        let attr = MapOpAttr {
+            map_fd: self.fd,
+            key: key as u64,
+            value_or_nextkey: &mut next as *mut K as u64,
+            flags: 0,
+        };
+        match bpf(BPF_MAP_GET_NEXT_KEY, &attr) {
+            0 => Some(next),
+            _ => None,
+        }
+    }
+
+    /// the elements of the map, entries changed while iterating may be missed
+    pub fn iter(&self) -> BpfMapIter<'_, K, V> {
+        BpfMapIter {
+            map: self,
+            key: None,
+        }
+    }
+}
+
+pub struct BpfMapIter<'a, K, V> {
+    map: &'a BpfMap<K, V>,
+    key: Option<K>,
+}
+
+impl<K: Copy + Default, V: Copy + Default> Iterator for BpfMapIter<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let key = self.map.next_key(self.key.as_ref())?;
+            self.key = Some(key);
+            // skip keys deleted since next_key
+            if let Some(value) = self.map.lookup(&key) {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+/// a program loaded into the kernel
+pub struct BpfProgram {
+    fd: u32,
+    /// ELF section the program was loaded from
+    name: String,
+}
+
+impl BpfProgram {
+    /// load every program section of the eBPF ELF `elf`, None if loading fails or the ELF
+    /// has more than MAX_PROGS_PER_ELF programs, none of them is loaded then
+    /// `maps` gives the fds of the maps the programs refer to by symbol name
+    pub fn load_elf(elf: &[u8], maps: &[(&str, u32)]) -> Option<Vec<BpfProgram>> {
+        // the kernel reads the names as C strings
+        let names: Vec<String> = maps.iter().map(|(name, _)| format!("{}\0", name)).collect();
+        let map_array: Vec<MapFdEntry> = names
+            .iter()
+            .zip(maps)
+            .map(|(name, (_, fd))| MapFdEntry {
+                name: name.as_ptr(),
+                fd: *fd,
+            })
+            .collect();
+        let mut prog_array = [ProgFdEntry {
+            name: [0; BPF_PROG_NAME_LEN],
+            fd: 0,
+        }; MAX_PROGS_PER_ELF];
+        let attr = ProgramLoadExAttr {
+            elf_prog: elf.as_ptr() as u64,
+            elf_size: elf.len() as u32,
+            map_array_len: map_array.len() as u32,
+            map_array: map_array.as_ptr(),
+            prog_array_len: MAX_PROGS_PER_ELF as u32,
+            prog_array: prog_array.as_mut_ptr(),
+        };
+        if bpf(BPF_PROG_LOAD_EX, &attr) < 0 {
+            return None;
+        }
+        let progs = prog_array
+            .iter()
+            .take_while(|entry| entry.name[0] != 0)
+            .map(|entry| {
+                let len = entry.name.iter().position(|&c| c == 0).unwrap_or(BPF_PROG_NAME_LEN);
+                BpfProgram {
+                    fd: entry.fd,
+                    name: String::from(core::str::from_utf8(&entry.name[..len]).unwrap_or("")),
+                }
+            })
+            .collect();
+        Some(progs)
+    }
+
+    /// a program loaded elsewhere
+    pub fn from_fd(fd: u32) -> Self {
+        BpfProgram {
+            fd,
+            name: String::new(),
+        }
+    }
+
+    pub fn fd(&self) -> u32 {
+        self.fd
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// attach to `target` in the kernel's syntax, e.g. "kprobe$0x80201234"
+    /// or "tracepoint$sched:sched_switch"
+    pub fn attach(&self, target: &str) -> isize {
+        let attr = KprobeAttachAttr {
+            target: target.as_ptr(),
+            str_len: target.len() as u32,
+            prog_fd: self.fd,
+        };
+        bpf(BPF_PROG_ATTACH, &attr)
+    }
+
+    /// run on entry of the kernel function at `addr`
+    pub fn attach_kprobe(&self, addr: usize) -> isize {
+        self.attach(&format!("kprobe${:#x}", addr))
+    }
+
+    /// run on entry of the function at `addr` of the user program at `path`
+    pub fn attach_uprobe(&self, path: &str, addr: usize) -> isize {
+        self.attach(&format!("uprobe_syncfunc${}${:#x}", path, addr))
+    }
+
+    /// detach from every hookpoint and unload the program, even if it was never attached.
+    /// the fd is invalid afterwards, read the stats before
+    /// returns < 0 if the fd is not a loaded program
+    pub fn detach(&self) -> isize {
+        let attr = KprobeAttachAttr {
+            target: core::ptr::null(),
+            str_len: 0,
+            prog_fd: self.fd,
+        };
+        bpf(BPF_PROG_DETACH, &attr)
+    }
+
+    pub fn info(&self) -> Option<BpfProgInfo> {
+        let mut info = BpfProgInfo::default();
+        match obj_get_info(self.fd, &mut info) {
+            0 => Some(info),
+            _ => None,
+        }
+    }
+}
+
+/// turn collection of the run time and count of every program on or off
+pub fn bpf_enable_stats(enable: bool) -> isize {
+    let attr = EnableStatsAttr {
+        enable: enable as u32,
+    };
+    bpf(BPF_ENABLE_STATS, &attr)
+}
@@ -0,0 +1,31 @@
+use super::*;
+
+const FTRACE_ENABLE: usize = 0;
+const FTRACE_DISABLE: usize = 1;
+const FTRACE_READ: usize = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+/// one call recorded by the kernel function tracer, `timestamp` is in ns
+pub struct FtraceEntry {
+    pub timestamp: u64,
+    pub pc: usize,
+    pub caller: usize,
+    pub pid: usize,
+}
+
+/// trace the kernel function at `addr`, or every function if `addr` is 0
+/// returns the number of functions enabled, -1 if `addr` can not be traced
+pub fn ftrace_enable(addr: usize) -> isize {
+    sys_ftrace(FTRACE_ENABLE, addr, 0)
+}
+
+/// stop tracing the kernel function at `addr`, or every function if `addr` is 0
+pub fn ftrace_disable(addr: usize) -> isize {
+    sys_ftrace(FTRACE_DISABLE, addr, 0)
+}
+
+/// move recorded calls into `buf`, returns the number of entries filled
+pub fn ftrace_read(buf: &mut [FtraceEntry]) -> isize {
+    sys_ftrace(FTRACE_READ, buf.as_mut_ptr() as usize, buf.len())
+}
@@ -0,0 +1,72 @@
+use super::*;
+use alloc::format;
+
+const KLOG_MAX_LEVEL: usize = 0;
+const KLOG_MODULE_LEVEL: usize = 1;
+const KLOG_CONSOLE_LEVEL: usize = 2;
+
+/// longer kernel log messages are truncated
+pub const KLOG_MSG_SIZE: usize = 192;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// one record of the kernel log, `timestamp` is in ns, `level` is 1 (error) to 5 (trace)
+/// and `pid` is usize::MAX if there was no current process
+pub struct KlogEntry {
+    pub timestamp: u64,
+    pub pid: usize,
+    pub hart: u32,
+    pub level: u32,
+    pub len: u32,
+    pub msg: [u8; KLOG_MSG_SIZE],
+}
+
+impl Default for KlogEntry {
+    fn default() -> Self {
+        Self {
+            timestamp: 0,
+            pid: usize::MAX,
+            hart: 0,
+            level: 0,
+            len: 0,
+            msg: [0; KLOG_MSG_SIZE],
+        }
+    }
+}
+
+impl KlogEntry {
+    pub fn message(&self) -> &str {
+        let len = (self.len as usize).min(KLOG_MSG_SIZE);
+        // a message cut in the middle of a character keeps its valid part
+        match core::str::from_utf8(&self.msg[..len]) {
+            Ok(msg) => msg,
+            Err(e) => core::str::from_utf8(&self.msg[..e.valid_up_to()]).unwrap(),
+        }
+    }
+}
+
+/// move the oldest kernel log records into `buf`, returns the number of entries filled
+/// or -1 if buf is not mapped
+pub fn klog_read(buf: &mut [KlogEntry]) -> isize {
+    sys_klog_read(buf.as_mut_ptr() as usize, buf.len())
+}
+
+/// keep kernel log records up to `level`, 0 (off) to 5 (trace)
+pub fn klog_set_level(level: usize) -> isize {
+    sys_klog_filter(KLOG_MAX_LEVEL, level, core::ptr::null())
+}
+
+/// keep records of `module` and its submodules up to `level`, None removes the filter
+pub fn klog_set_module_level(module: &str, level: Option<usize>) -> isize {
+    let module = format!("{}\0", module);
+    sys_klog_filter(
+        KLOG_MODULE_LEVEL,
+        level.unwrap_or(usize::MAX),
+        module.as_ptr(),
+    )
+}
+
+/// also print kernel log records up to `level` to the console
+pub fn klog_set_console_level(level: usize) -> isize {
+    sys_klog_filter(KLOG_CONSOLE_LEVEL, level, core::ptr::null())
+}
@@ -6,10 +6,14 @@
 
 #[macro_use]
 pub mod console;
+pub mod bpf;
 mod file;
+mod ftrace;
 mod io;
+mod klog;
 mod lang_items;
 mod net;
+mod procs;
 mod sync;
 mod syscall;
 mod task;
@@ -21,8 +25,11 @@ extern crate bitflags;
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
 pub use file::*;
+pub use ftrace::*;
 pub use io::*;
+pub use klog::*;
 pub use net::*;
+pub use procs::*;
 pub use sync::*;
 use syscall::*;
 pub use task::*;
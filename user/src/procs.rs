@@ -0,0 +1,58 @@
+use super::*;
+
+const PAGE_SIZE: usize = 4096;
+
+/// longer exec paths are truncated
+pub const PROC_PATH_SIZE: usize = 32;
+
+pub const PROC_RUNNING: usize = 0;
+/// all of its threads are blocked
+pub const PROC_SLEEPING: usize = 1;
+pub const PROC_ZOMBIE: usize = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+/// one process as seen by getprocs, `mem_pages` counts the frames mapped for it
+pub struct ProcInfo {
+    pub pid: usize,
+    pub ppid: usize,
+    pub state: usize,
+    pub threads: usize,
+    pub mem_pages: usize,
+    pub cpu_time_ms: usize,
+    pub path: [u8; PROC_PATH_SIZE],
+}
+
+impl ProcInfo {
+    pub fn path(&self) -> &str {
+        let len = self
+            .path
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(PROC_PATH_SIZE);
+        // a path cut in the middle of a character keeps its valid part
+        match core::str::from_utf8(&self.path[..len]) {
+            Ok(path) => path,
+            Err(e) => core::str::from_utf8(&self.path[..e.valid_up_to()]).unwrap(),
+        }
+    }
+
+    pub fn mem_kb(&self) -> usize {
+        self.mem_pages * PAGE_SIZE / 1024
+    }
+
+    /// `R`, `S` or `Z`, as in ps
+    pub fn state_char(&self) -> char {
+        match self.state {
+            PROC_RUNNING => 'R',
+            PROC_SLEEPING => 'S',
+            PROC_ZOMBIE => 'Z',
+            _ => '?',
+        }
+    }
+}
+
+/// fill `buf` with live processes in pid order, returns the number of entries filled
+pub fn getprocs(buf: &mut [ProcInfo]) -> isize {
+    sys_getprocs(buf.as_mut_ptr() as usize, buf.len())
+}
@@ -16,6 +16,7 @@ const SYSCALL_GETPID: usize = 172;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
 const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_BPF: usize = 280;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
 const SYSCALL_WAITTID: usize = 1002;
@@ -32,6 +33,10 @@ const SYSCALL_FRAMEBUFFER: usize = 2000;
 const SYSCALL_FRAMEBUFFER_FLUSH: usize = 2001;
 const SYSCALL_EVENT_GET: usize = 3000;
 const SYSCALL_KEY_PRESSED: usize = 3001;
+const SYSCALL_FTRACE: usize = 5000;
+const SYSCALL_KLOG_READ: usize = 5001;
+const SYSCALL_KLOG_FILTER: usize = 5002;
+const SYSCALL_GETPROCS: usize = 5003;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -192,4 +197,24 @@ pub fn sys_event_get() -> isize {
 
 pub fn sys_key_pressed() -> isize {
     syscall(SYSCALL_KEY_PRESSED, [0, 0, 0])
-}
\ No newline at end of file
+}
+
+pub fn sys_ftrace(cmd: usize, arg0: usize, arg1: usize) -> isize {
+    syscall(SYSCALL_FTRACE, [cmd, arg0, arg1])
+}
+
+pub fn sys_klog_read(buf: usize, count: usize) -> isize {
+    syscall(SYSCALL_KLOG_READ, [buf, count, 0])
+}
+
+pub fn sys_klog_filter(cmd: usize, level: usize, module: *const u8) -> isize {
+    syscall(SYSCALL_KLOG_FILTER, [cmd, level, module as usize])
+}
+
+pub fn sys_getprocs(buf: usize, count: usize) -> isize {
+    syscall(SYSCALL_GETPROCS, [buf, count, 0])
+}
+
+pub fn sys_bpf(cmd: usize, attr: usize, size: usize) -> isize {
+    syscall(SYSCALL_BPF, [cmd, attr, size])
+}
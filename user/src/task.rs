@@ -47,6 +47,10 @@ pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
     sys_waitpid(pid as isize, exit_code as *mut _)
 }
 
+pub fn wait_nb(exit_code: &mut i32) -> isize {
+    sys_waitpid(-1, exit_code as *mut _)
+}
+
 bitflags! {
     pub struct SignalFlags: i32 {
         const SIGINT    = 1 << 2;